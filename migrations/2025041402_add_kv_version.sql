@@ -0,0 +1,3 @@
+-- 行版本号，每次更新加 1，用于 ETag 和条件更新
+ALTER TABLE kv_store
+    ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,8 @@
+-- key 的过期时间，为空表示永不过期
+ALTER TABLE kv_store
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP WITH TIME ZONE;
+
+-- 过期清理任务按过期时间扫描
+CREATE INDEX IF NOT EXISTS kv_store_expires_at_idx
+    ON kv_store (expires_at)
+    WHERE expires_at IS NOT NULL;
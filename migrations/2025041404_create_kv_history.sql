@@ -0,0 +1,15 @@
+-- 每次写操作追加一条记录，用于查看旧值、按版本或时间点读取和回滚
+CREATE TABLE IF NOT EXISTS kv_history
+(
+    id         BIGSERIAL PRIMARY KEY,
+    key        VARCHAR(50)   NOT NULL,
+    value      VARCHAR(1000) NOT NULL,
+    version    BIGINT        NOT NULL,
+    operation  VARCHAR(10)   NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- 按 key 倒序查看历史
+CREATE INDEX IF NOT EXISTS kv_history_key_idx
+    ON kv_history (key, id);
@@ -0,0 +1,18 @@
+-- 多租户 namespace，key 只在 namespace 内唯一，已有的 key 归入 default
+ALTER TABLE kv_store
+    ADD COLUMN IF NOT EXISTS namespace VARCHAR(50) NOT NULL DEFAULT 'default';
+
+ALTER TABLE kv_store
+    DROP CONSTRAINT IF EXISTS kv_store_pkey;
+
+ALTER TABLE kv_store
+    ADD PRIMARY KEY (namespace, key);
+
+ALTER TABLE kv_history
+    ADD COLUMN IF NOT EXISTS namespace VARCHAR(50) NOT NULL DEFAULT 'default';
+
+DROP INDEX IF EXISTS kv_history_key_idx;
+
+-- 按 namespace 和 key 倒序查看历史
+CREATE INDEX IF NOT EXISTS kv_history_namespace_key_idx
+    ON kv_history (namespace, key, id);
@@ -0,0 +1,14 @@
+-- value 改为 JSONB，原来的字符串值转为 JSON 字符串
+ALTER TABLE kv_store
+    ALTER COLUMN value TYPE JSONB USING to_jsonb(value);
+
+ALTER TABLE kv_history
+    ALTER COLUMN value TYPE JSONB USING to_jsonb(value);
+
+-- 每个 namespace 可以注册一个 JSON Schema，写入的 value 需要通过校验
+CREATE TABLE IF NOT EXISTS kv_schema
+(
+    namespace  VARCHAR(50) PRIMARY KEY,
+    schema     JSONB       NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,11 @@
+-- 二进制值原样存在 data 里，value 为空；content_type 是上传时的 Content-Type，JSON 值为空
+ALTER TABLE kv_store
+    ALTER COLUMN value DROP NOT NULL,
+    ADD COLUMN data BYTEA,
+    ADD COLUMN content_type VARCHAR(255),
+    ADD CONSTRAINT kv_store_value_or_data CHECK ((value IS NULL) <> (data IS NULL));
+
+ALTER TABLE kv_history
+    ALTER COLUMN value DROP NOT NULL,
+    ADD COLUMN data BYTEA,
+    ADD COLUMN content_type VARCHAR(255);
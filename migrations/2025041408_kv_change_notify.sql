@@ -0,0 +1,26 @@
+-- kv_store 的每次写入都通过 kv_changes 通道通知所有实例，用于 watch
+-- 通知只作为唤醒信号，事件内容和 resume token（id）来自 kv_history
+CREATE OR REPLACE FUNCTION kv_notify_change() RETURNS trigger AS
+$$
+DECLARE
+    changed RECORD;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+    PERFORM pg_notify('kv_changes', json_build_object(
+        'namespace', changed.namespace,
+        'key', changed.key,
+        'operation', lower(TG_OP)
+    )::TEXT);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER kv_store_notify_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON kv_store
+    FOR EACH ROW
+EXECUTE FUNCTION kv_notify_change();
@@ -0,0 +1,48 @@
+-- SQLite 版本的表结构，和 Postgres 迁移完成后的结构一致
+-- 时间按 RFC 3339（UTC，+00:00）文本保存，同一格式的文本按字典序比较即按时间比较
+-- value 是 JSON 文本，二进制值原样存在 data 里
+CREATE TABLE IF NOT EXISTS kv_store
+(
+    namespace    TEXT    NOT NULL DEFAULT 'default',
+    key          TEXT    NOT NULL,
+    value        TEXT,
+    data         BLOB,
+    content_type TEXT,
+    updated_at   TEXT    NOT NULL,
+    version      INTEGER NOT NULL DEFAULT 1,
+    expires_at   TEXT,
+    PRIMARY KEY (namespace, key),
+    CHECK ((value IS NULL) <> (data IS NULL))
+);
+
+-- 过期清理任务按过期时间扫描
+CREATE INDEX IF NOT EXISTS kv_store_expires_at_idx
+    ON kv_store (expires_at)
+    WHERE expires_at IS NOT NULL;
+
+-- 每次写操作追加一条记录，id 是 watch 的 resume token，AUTOINCREMENT 保证不会重复使用
+CREATE TABLE IF NOT EXISTS kv_history
+(
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    namespace    TEXT    NOT NULL DEFAULT 'default',
+    key          TEXT    NOT NULL,
+    value        TEXT,
+    data         BLOB,
+    content_type TEXT,
+    version      INTEGER NOT NULL,
+    operation    TEXT    NOT NULL,
+    expires_at   TEXT,
+    changed_at   TEXT    NOT NULL
+);
+
+-- 按 namespace 和 key 倒序查看历史
+CREATE INDEX IF NOT EXISTS kv_history_namespace_key_idx
+    ON kv_history (namespace, key, id);
+
+-- 每个 namespace 可以注册一个 JSON Schema
+CREATE TABLE IF NOT EXISTS kv_schema
+(
+    namespace  TEXT PRIMARY KEY,
+    schema     TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
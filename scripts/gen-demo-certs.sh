@@ -0,0 +1,40 @@
+#!/usr/bin/env bash
+# 生成 localhost 的演示证书：CA、服务端证书和 mTLS 客户端证书，默认写到 conf/tls。
+# 私钥不提交到仓库（见 .gitignore），每个人在本地生成自己的一套，不要用于生产环境。
+#
+# 用法：scripts/gen-demo-certs.sh [输出目录]
+set -euo pipefail
+
+dir="${1:-conf/tls}"
+days=36500
+mkdir -p "$dir"
+tmp="$(mktemp -d)"
+trap 'rm -rf "$tmp"' EXIT
+
+key() {
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$dir/$1.key"
+    chmod 600 "$dir/$1.key"
+}
+
+key ca
+openssl req -x509 -new -key "$dir/ca.key" -days "$days" -subj "/CN=kv demo CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign" \
+    -out "$dir/ca.crt"
+
+# 用法：sign <名称> <subject> <extensions>
+sign() {
+    key "$1"
+    openssl req -new -key "$dir/$1.key" -subj "$2" -out "$tmp/$1.csr"
+    printf '%s\n' "basicConstraints=CA:FALSE" "keyUsage=critical,digitalSignature" "$3" > "$tmp/$1.ext"
+    openssl x509 -req -in "$tmp/$1.csr" -CA "$dir/ca.crt" -CAkey "$dir/ca.key" -CAcreateserial \
+        -days "$days" -extfile "$tmp/$1.ext" -out "$dir/$1.crt"
+}
+
+sign server "/CN=localhost" "extendedKeyUsage=serverAuth
+subjectAltName=DNS:localhost,IP:127.0.0.1"
+sign client "/O=kv demo/CN=client-1" "extendedKeyUsage=clientAuth
+subjectAltName=URI:spiffe://kv/client-1"
+rm -f "$dir/ca.srl"
+
+echo "demo certificates written to $dir"
@@ -1,66 +1,570 @@
 use crate::error::AppError;
-use redis::{AsyncCommands, Client};
+use async_trait::async_trait;
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{AsyncCommands, Client, RedisResult, Script};
 use serde::{Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 use tracing::instrument;
 
-pub struct CacheClient {
-    client: Client,
-}
+/// 缓存抽象，handler 只依赖这个 trait，可以在 Redis 和进程内缓存之间切换。
+///
+/// trait 本身只处理序列化后的字符串，保证可以作为 `dyn KvCache` 使用；
+/// 泛型的 `get<T>`/`set<T>` 定义在 `dyn KvCache` 上。
+#[allow(dead_code)]
+#[async_trait]
+pub trait KvCache: Send + Sync {
+    async fn set_raw(&self, key: &str, value: String, ttl_secs: u64) -> Result<(), AppError>;
 
-impl CacheClient {
-    pub async fn new(redis_url: &str) -> Result<Self, AppError> {
-        let client = Client::open(redis_url)?;
-        Ok(Self { client })
+    async fn get_raw(&self, key: &str) -> Result<Option<String>, AppError>;
+
+    /// 批量读取，返回值和 `keys` 一一对应
+    async fn get_many_raw(&self, keys: &[String]) -> Result<Vec<Option<String>>, AppError> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get_raw(key).await?);
+        }
+        Ok(values)
     }
 
-    #[instrument(skip(self, value))]
+    async fn delete(&self, key: &str) -> Result<(), AppError>;
+
+    /// 读取 key 当前的失效版本号，不存在时为 0
+    async fn version(&self, key: &str) -> Result<u64, AppError>;
+
+    /// 批量读取失效版本号，返回值和 `keys` 一一对应
+    async fn versions(&self, keys: &[String]) -> Result<Vec<u64>, AppError> {
+        let mut versions = Vec::with_capacity(keys.len());
+        for key in keys {
+            versions.push(self.version(key).await?);
+        }
+        Ok(versions)
+    }
+
+    /// 删除缓存并递增失效版本号，写操作完成后调用
+    async fn invalidate(&self, key: &str) -> Result<(), AppError>;
+
+    /// 仅当失效版本号仍为 `version` 时写入缓存，返回是否写入成功。
+    ///
+    /// 读数据库之前先读取版本号，回填时带上，期间有写操作失效缓存的话回填会被拒绝。
+    async fn set_raw_if_version(
+        &self,
+        key: &str,
+        value: String,
+        ttl_secs: u64,
+        version: u64,
+    ) -> Result<bool, AppError>;
+
+    /// 尝试获取一个带过期时间的锁，用于跨实例合并回源请求
+    async fn try_lock(&self, key: &str, token: &str, ttl_ms: u64) -> Result<bool, AppError>;
+
+    /// 释放锁，只有持有者（token 相同）才能释放
+    async fn unlock(&self, key: &str, token: &str) -> Result<(), AppError>;
+}
+
+/// 失效版本号的过期时间，远大于一次读数据库的耗时即可
+const VERSION_TTL_SECS: i64 = 24 * 60 * 60;
+/// 进程内缓存清理过期条目和失效版本号的最小间隔
+const MEMORY_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+fn version_key(key: &str) -> String {
+    format!("{}:version", key)
+}
+
+impl dyn KvCache + '_ {
+    #[allow(dead_code)]
     pub async fn set<T: Serialize>(
         &self,
         key: &str,
         value: &T,
         ttl_secs: u64,
     ) -> Result<(), AppError> {
+        let serialized_value = serde_json::to_string(value)?;
+        self.set_raw(key, serialized_value, ttl_secs).await
+    }
+
+    pub async fn set_if_version<T: Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl_secs: u64,
+        version: u64,
+    ) -> Result<bool, AppError> {
+        let serialized_value = serde_json::to_string(value)?;
+        self.set_raw_if_version(key, serialized_value, ttl_secs, version)
+            .await
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, AppError> {
+        match self.get_raw(key).await? {
+            Some(value) => {
+                let deserialized_value: T = serde_json::from_str(&value)?;
+                Ok(Some(deserialized_value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_many<T: DeserializeOwned>(
+        &self,
+        keys: &[String],
+    ) -> Result<Vec<Option<T>>, AppError> {
+        self.get_many_raw(keys)
+            .await?
+            .into_iter()
+            .map(|value| match value {
+                Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+                None => Ok(None),
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheClientOptions {
+    /// 建立连接的超时时间，包括断线重连
+    pub connection_timeout: Duration,
+    /// 单个命令的响应超时时间
+    pub response_timeout: Duration,
+}
+
+impl Default for CacheClientOptions {
+    fn default() -> Self {
+        Self {
+            connection_timeout: Duration::from_secs(1),
+            response_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Redis 命令相关的指标
+struct RedisMetrics {
+    commands: Counter<u64>,
+    command_duration: Histogram<f64>,
+    in_flight: UpDownCounter<i64>,
+    connection_errors: Counter<u64>,
+}
+
+impl RedisMetrics {
+    fn new() -> Self {
+        let meter = opentelemetry::global::meter("hyper-tower-service");
+        Self {
+            commands: meter
+                .u64_counter("redis_commands_total")
+                .with_description("Total number of redis commands")
+                .build(),
+            command_duration: meter
+                .f64_histogram("redis_command_duration_milliseconds")
+                .with_description("Redis command duration in milliseconds")
+                .build(),
+            in_flight: meter
+                .i64_up_down_counter("redis_connection_in_flight_commands")
+                .with_description("Number of commands in flight on the shared redis connection")
+                .build(),
+            connection_errors: meter
+                .u64_counter("redis_connection_errors_total")
+                .with_description(
+                    "Number of redis commands that failed with a connection error, including timeouts",
+                )
+                .build(),
+        }
+    }
+}
+
+/// 基于 Redis 的 [`KvCache`] 实现
+///
+/// 所有命令共用一个长连接的 [`ConnectionManager`]，连接断开后自动重连，
+/// 不再每次调用都重新建立连接。
+pub struct CacheClient {
+    manager: ConnectionManager,
+    metrics: RedisMetrics,
+}
+
+impl CacheClient {
+    pub async fn new(redis_url: &str, options: CacheClientOptions) -> Result<Self, AppError> {
+        let client = Client::open(redis_url)?;
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(options.connection_timeout)
+            .set_response_timeout(options.response_timeout);
+        let manager = ConnectionManager::new_with_config(client, config).await?;
+        Ok(Self {
+            manager,
+            metrics: RedisMetrics::new(),
+        })
+    }
+
+    /// 在共享连接上执行命令并记录指标
+    async fn run<T, F, Fut>(&self, command: &'static str, f: F) -> Result<T, AppError>
+    where
+        F: FnOnce(ConnectionManager) -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        let start = Instant::now();
+        self.metrics.in_flight.add(1, &[]);
+        let result = f(self.manager.clone()).await;
+        self.metrics.in_flight.add(-1, &[]);
+
+        let status = if result.is_ok() { "ok" } else { "error" };
+        let attributes = [
+            KeyValue::new("command", command),
+            KeyValue::new("status", status),
+        ];
+        self.metrics.commands.add(1, &attributes);
+        self.metrics
+            .command_duration
+            .record(start.elapsed().as_millis_f64(), &attributes);
+
+        if let Err(e) = &result {
+            // 连接层面的错误（包括超时），不等于重连次数：
+            // 超时不一定断开连接，断开时 ConnectionManager 在后台重连
+            if e.is_io_error() || e.is_connection_dropped() || e.is_connection_refusal() {
+                self.metrics.connection_errors.add(1, &[]);
+                tracing::warn!(target: "redis::kv", command, error = %e, "redis connection error");
+            }
+        }
+        Ok(result?)
+    }
+}
+
+#[async_trait]
+impl KvCache for CacheClient {
+    #[instrument(skip(self, value))]
+    async fn set_raw(&self, key: &str, value: String, ttl_secs: u64) -> Result<(), AppError> {
         // TODO: record the value
         tracing::info!(target: "redis::kv", "set {} to redis", key);
 
-        // let mut con = self.client.get_async_connection().await?;
-        let mut con = self.client.get_multiplexed_async_connection().await?;
-        let serialized_value = serde_json::to_string(value)?;
-        // con.set(key, serialized_value).await?;
-        con.set_ex::<_, _, ()>(key, serialized_value, ttl_secs)
-            .await?;
+        self.run("set", |mut con| async move {
+            con.set_ex::<_, _, ()>(key, value, ttl_secs).await
+        })
+        .await?;
 
         tracing::info!(target: "redis::kv", "set {} to redis success", key);
         Ok(())
     }
 
     #[instrument(skip(self))]
-    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, AppError> {
+    async fn get_raw(&self, key: &str) -> Result<Option<String>, AppError> {
         tracing::info!(target: "redis::kv", "get {} from redis", key);
 
-        // let mut con = self.client.get_async_connection().await?;
-        let mut con = self.client.get_multiplexed_async_connection().await?;
-        let result: Option<String> = con.get(key).await?;
+        let result: Option<String> = self
+            .run("get", |mut con| async move { con.get(key).await })
+            .await?;
 
         tracing::info!(target: "redis::kv", "get {} from redis success", key);
-        match result {
-            Some(value) => {
-                let deserialized_value: T = serde_json::from_str(&value)?;
-                Ok(Some(deserialized_value))
-            }
-            None => Ok(None),
+        Ok(result)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_many_raw(&self, keys: &[String]) -> Result<Vec<Option<String>>, AppError> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
         }
+        tracing::info!(target: "redis::kv", "mget {} keys from redis", keys.len());
+
+        // 显式使用 MGET，只有一个 key 时返回值也是数组
+        let values: Vec<Option<String>> = self
+            .run("mget", |mut con| async move {
+                redis::cmd("MGET").arg(keys).query_async(&mut con).await
+            })
+            .await?;
+
+        tracing::info!(target: "redis::kv", "mget {} keys from redis success", keys.len());
+        Ok(values)
     }
 
     #[instrument(skip(self))]
-    pub async fn delete(&self, key: &str) -> Result<(), AppError> {
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
         tracing::info!(target: "redis::kv", "delete {} from redis", key);
 
-        // let mut con = self.client.get_async_connection().await?;
-        let mut con = self.client.get_multiplexed_async_connection().await?;
-        con.del::<_, ()>(key).await?;
+        self.run("del", |mut con| async move { con.del::<_, ()>(key).await })
+            .await?;
 
         tracing::info!(target: "redis::kv", "delete {} from redis success", key);
         Ok(())
     }
+
+    #[instrument(skip(self))]
+    async fn version(&self, key: &str) -> Result<u64, AppError> {
+        let version: Option<u64> = self
+            .run(
+                "get",
+                |mut con| async move { con.get(version_key(key)).await },
+            )
+            .await?;
+        Ok(version.unwrap_or(0))
+    }
+
+    #[instrument(skip(self))]
+    async fn versions(&self, keys: &[String]) -> Result<Vec<u64>, AppError> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let version_keys: Vec<String> = keys.iter().map(|key| version_key(key)).collect();
+        let versions: Vec<Option<u64>> = self
+            .run("mget", |mut con| async move {
+                redis::cmd("MGET")
+                    .arg(&version_keys)
+                    .query_async(&mut con)
+                    .await
+            })
+            .await?;
+        Ok(versions.into_iter().map(|v| v.unwrap_or(0)).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn invalidate(&self, key: &str) -> Result<(), AppError> {
+        tracing::info!(target: "redis::kv", "invalidate {} in redis", key);
+
+        let version_key = version_key(key);
+        self.run("invalidate", |mut con| async move {
+            redis::pipe()
+                .atomic()
+                .incr(&version_key, 1)
+                .expire(&version_key, VERSION_TTL_SECS)
+                .del(key)
+                .query_async::<()>(&mut con)
+                .await
+        })
+        .await?;
+
+        tracing::info!(target: "redis::kv", "invalidate {} in redis success", key);
+        Ok(())
+    }
+
+    #[instrument(skip(self, value))]
+    async fn set_raw_if_version(
+        &self,
+        key: &str,
+        value: String,
+        ttl_secs: u64,
+        version: u64,
+    ) -> Result<bool, AppError> {
+        // 比较版本号和写入必须是原子的，放在同一个 lua 脚本里执行
+        let script = Script::new(
+            r#"
+            local current = redis.call('GET', KEYS[2]) or '0'
+            if current ~= ARGV[3] then
+                return 0
+            end
+            redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+            return 1
+            "#,
+        );
+
+        let written: i32 = self
+            .run("set_if_version", |mut con| async move {
+                script
+                    .key(key)
+                    .key(version_key(key))
+                    .arg(value)
+                    .arg(ttl_secs)
+                    .arg(version)
+                    .invoke_async(&mut con)
+                    .await
+            })
+            .await?;
+
+        tracing::info!(target: "redis::kv", "set {} to redis if version {}: {}", key, version, written == 1);
+        Ok(written == 1)
+    }
+
+    #[instrument(skip(self))]
+    async fn try_lock(&self, key: &str, token: &str, ttl_ms: u64) -> Result<bool, AppError> {
+        let locked: Option<String> = self
+            .run("lock", |mut con| async move {
+                redis::cmd("SET")
+                    .arg(key)
+                    .arg(token)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(ttl_ms)
+                    .query_async(&mut con)
+                    .await
+            })
+            .await?;
+        Ok(locked.is_some())
+    }
+
+    #[instrument(skip(self))]
+    async fn unlock(&self, key: &str, token: &str) -> Result<(), AppError> {
+        let script = Script::new(
+            r#"
+            if redis.call('GET', KEYS[1]) == ARGV[1] then
+                return redis.call('DEL', KEYS[1])
+            end
+            return 0
+            "#,
+        );
+
+        self.run("unlock", |mut con| async move {
+            script
+                .key(key)
+                .arg(token)
+                .invoke_async::<()>(&mut con)
+                .await
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+/// 进程内的 [`KvCache`] 实现，支持 TTL，过期的条目在读取时惰性清理，
+/// 没有再被读取的条目和失效版本号在写入时定期清理
+pub struct MemoryCache {
+    entries: RwLock<HashMap<String, (String, Instant)>>,
+    // 失效版本号和过期时间，和 Redis 一样保留 VERSION_TTL_SECS，
+    // 同时需要两把锁时先锁 versions 再锁 entries
+    versions: RwLock<HashMap<String, (u64, Instant)>>,
+    locks: RwLock<HashMap<String, (String, Instant)>>,
+    next_prune: Mutex<Instant>,
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self {
+            entries: RwLock::default(),
+            versions: RwLock::default(),
+            locks: RwLock::default(),
+            next_prune: Mutex::new(Instant::now() + MEMORY_PRUNE_INTERVAL),
+        }
+    }
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn version_of(versions: &HashMap<String, (u64, Instant)>, key: &str) -> u64 {
+        match versions.get(key) {
+            Some((version, expires_at)) if *expires_at > Instant::now() => *version,
+            _ => 0,
+        }
+    }
+
+    /// 距离上次清理超过 [`MEMORY_PRUNE_INTERVAL`] 时删除所有过期的条目和失效版本号
+    fn prune(&self) {
+        let now = Instant::now();
+        {
+            let mut next_prune = self.next_prune.lock().unwrap();
+            if *next_prune > now {
+                return;
+            }
+            *next_prune = now + MEMORY_PRUNE_INTERVAL;
+        }
+        self.versions
+            .write()
+            .unwrap()
+            .retain(|_, (_, expires_at)| *expires_at > now);
+        self.entries
+            .write()
+            .unwrap()
+            .retain(|_, (_, expires_at)| *expires_at > now);
+        self.locks
+            .write()
+            .unwrap()
+            .retain(|_, (_, expires_at)| *expires_at > now);
+    }
+}
+
+#[async_trait]
+impl KvCache for MemoryCache {
+    #[instrument(skip(self, value))]
+    async fn set_raw(&self, key: &str, value: String, ttl_secs: u64) -> Result<(), AppError> {
+        tracing::info!(target: "memory::cache", "set {} to memory cache", key);
+        self.prune();
+        let expires_at = Instant::now() + Duration::from_secs(ttl_secs);
+        self.entries
+            .write()
+            .unwrap()
+            .insert(key.to_string(), (value, expires_at));
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_raw(&self, key: &str) -> Result<Option<String>, AppError> {
+        tracing::info!(target: "memory::cache", "get {} from memory cache", key);
+        let mut entries = self.entries.write().unwrap();
+        match entries.get(key) {
+            Some((value, expires_at)) if *expires_at > Instant::now() => Ok(Some(value.clone())),
+            Some(_) => {
+                entries.remove(key);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        tracing::info!(target: "memory::cache", "delete {} from memory cache", key);
+        self.entries.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn version(&self, key: &str) -> Result<u64, AppError> {
+        Ok(Self::version_of(&self.versions.read().unwrap(), key))
+    }
+
+    #[instrument(skip(self))]
+    async fn invalidate(&self, key: &str) -> Result<(), AppError> {
+        tracing::info!(target: "memory::cache", "invalidate {} in memory cache", key);
+        self.prune();
+        let mut versions = self.versions.write().unwrap();
+        let version = Self::version_of(&versions, key) + 1;
+        let expires_at = Instant::now() + Duration::from_secs(VERSION_TTL_SECS as u64);
+        versions.insert(key.to_string(), (version, expires_at));
+        self.entries.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    #[instrument(skip(self, value))]
+    async fn set_raw_if_version(
+        &self,
+        key: &str,
+        value: String,
+        ttl_secs: u64,
+        version: u64,
+    ) -> Result<bool, AppError> {
+        self.prune();
+        let versions = self.versions.read().unwrap();
+        if Self::version_of(&versions, key) != version {
+            return Ok(false);
+        }
+
+        let expires_at = Instant::now() + Duration::from_secs(ttl_secs);
+        self.entries
+            .write()
+            .unwrap()
+            .insert(key.to_string(), (value, expires_at));
+        Ok(true)
+    }
+
+    #[instrument(skip(self))]
+    async fn try_lock(&self, key: &str, token: &str, ttl_ms: u64) -> Result<bool, AppError> {
+        let mut locks = self.locks.write().unwrap();
+        if locks
+            .get(key)
+            .is_some_and(|(_, expires_at)| *expires_at > Instant::now())
+        {
+            return Ok(false);
+        }
+
+        let expires_at = Instant::now() + Duration::from_millis(ttl_ms);
+        locks.insert(key.to_string(), (token.to_string(), expires_at));
+        Ok(true)
+    }
+
+    #[instrument(skip(self))]
+    async fn unlock(&self, key: &str, token: &str) -> Result<(), AppError> {
+        let mut locks = self.locks.write().unwrap();
+        if locks.get(key).is_some_and(|(owner, _)| owner == token) {
+            locks.remove(key);
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,935 @@
+//! KV 服务的 cache-aside 读写协议，`kv_axum` 和 `kv_tower` 共用。
+//!
+//! - 读：先查缓存，未命中时读数据库并回填缓存
+//! - 写：先写数据库，再失效缓存（删除缓存并递增失效版本号），不在写路径上回填
+//!
+//! 回填时带上读数据库之前拿到的失效版本号，读数据库期间如果有写操作失效了缓存，
+//! 版本号已经变化，回填会被拒绝，慢读者不会把旧数据写回缓存。
+//!
+//! 缓存未命中时，同一个 key 的并发读在进程内合并为一次数据库读取；
+//! 开启回源锁后，还会通过缓存上的短期锁在多个实例之间合并。
+//!
+//! 数据库中不存在的 key 也会缓存一个墓碑（序列化为 `null`），过期时间单独配置，
+//! 写操作失效缓存时墓碑一起被删除。超过大小阈值的 value 不回填，每次都读数据库。
+//!
+//! 缓存只是加速，数据库才是数据源：缓存出错时读写都退回到数据库，不返回错误。
+//! 连续出错后熔断一段时间，期间跳过缓存，到期后探测成功再恢复。
+//! 缓存出错或熔断期间没能失效的 key 记下来，之后每次访问缓存之前先重放这些失效，
+//! 全部成功之前不使用缓存，缓存恢复后不会读到故障期间写入之前的旧值。
+//! 记录只在本实例内，其他实例在本实例重放之前仍可能读到旧值，最多到缓存过期。
+//!
+//! 缓存 key 带上 `db` 所在的 namespace，不同 namespace 的同名 key 互不影响。
+use crate::{
+    cache::KvCache, circuit_breaker::CircuitBreaker, error::AppError, kv_value, models::KvPair,
+    namespace::DEFAULT_NAMESPACE, single_flight::SingleFlight, store::KvStore,
+};
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::Counter;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tracing::{info, instrument, warn};
+
+/// KV 缓存的过期时间
+pub const KV_CACHE_TTL_SECS: u64 = 300;
+/// 不存在的 key 的墓碑过期时间
+pub const KV_CACHE_NEGATIVE_TTL_SECS: u64 = 10;
+/// 超过这个字节数的 value 不写入缓存
+pub const KV_CACHE_MAX_VALUE_BYTES: usize = 64 * 1024;
+/// 过期时间随机增加最多 10%，避免同一时间写入的 key 同时过期
+const KV_CACHE_TTL_JITTER_PERCENT: u64 = 10;
+/// 回源锁的过期时间，持有者异常退出时锁会自动释放
+const FILL_LOCK_TTL_MS: u64 = 3000;
+/// 没拿到回源锁时轮询等待其他实例回填缓存，间隔从 20ms 开始翻倍，最长 200ms，
+/// 一共最多等 [`FILL_LOCK_TTL_MS`]，到时锁也过期了
+const FILL_LOCK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+const FILL_LOCK_POLL_MAX_INTERVAL: Duration = Duration::from_millis(200);
+
+/// 默认 namespace 沿用原来的 `kv:{key}`，其他 namespace 为 `kv:{namespace}:{key}`
+///
+/// namespace 和 key 都不包含 `:`，不会冲突
+pub fn cache_key(namespace: &str, key: &str) -> String {
+    if namespace == DEFAULT_NAMESPACE {
+        format!("kv:{}", key)
+    } else {
+        format!("kv:{}:{}", namespace, key)
+    }
+}
+
+/// 在 `ttl_secs` 的基础上加上随机抖动
+pub fn jittered_ttl(ttl_secs: u64) -> u64 {
+    let max_jitter = ttl_secs * KV_CACHE_TTL_JITTER_PERCENT / 100;
+    ttl_secs + rand::rng().random_range(0..=max_jitter)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheAsideOptions {
+    /// 存在的 key 的缓存过期时间
+    pub ttl_secs: u64,
+    /// 不存在的 key 的墓碑过期时间
+    pub negative_ttl_secs: u64,
+    /// 为 true 时，缓存未命中会先获取缓存上的回源锁，跨实例合并回源
+    pub fill_lock: bool,
+    /// 缓存连续失败多少次后熔断
+    pub breaker_failure_threshold: u32,
+    /// 熔断持续时间，到期后放行一个探测请求
+    pub breaker_open_duration: Duration,
+    /// 超过这个字节数的 value 不回填缓存，大的二进制值只存在数据库里
+    pub max_value_bytes: usize,
+}
+
+impl Default for CacheAsideOptions {
+    fn default() -> Self {
+        Self {
+            ttl_secs: KV_CACHE_TTL_SECS,
+            negative_ttl_secs: KV_CACHE_NEGATIVE_TTL_SECS,
+            fill_lock: false,
+            breaker_failure_threshold: 5,
+            breaker_open_duration: Duration::from_secs(10),
+            max_value_bytes: KV_CACHE_MAX_VALUE_BYTES,
+        }
+    }
+}
+
+/// 失效失败、等待重放的缓存 key，值是记录时的序号，
+/// 重放期间同一个 key 再次失效失败时序号变化，重放成功也不能删除
+#[derive(Default)]
+struct PendingInvalidations {
+    seq: u64,
+    keys: HashMap<String, u64>,
+}
+
+pub struct CacheAside {
+    flights: SingleFlight<Option<KvPair>>,
+    options: RwLock<CacheAsideOptions>,
+    breaker: CircuitBreaker,
+    pending: Mutex<PendingInvalidations>,
+    degraded: Counter<u64>,
+}
+
+impl CacheAside {
+    pub fn new(options: CacheAsideOptions) -> Self {
+        let degraded = opentelemetry::global::meter("hyper-tower-service")
+            .u64_counter("kv_cache_degraded_total")
+            .with_description("Number of cache operations skipped or failed open to the database")
+            .build();
+        Self {
+            flights: SingleFlight::new(),
+            options: RwLock::new(options),
+            breaker: CircuitBreaker::new(
+                "kv_cache",
+                options.breaker_failure_threshold,
+                options.breaker_open_duration,
+            ),
+            pending: Mutex::default(),
+            degraded,
+        }
+    }
+
+    pub fn options(&self) -> CacheAsideOptions {
+        *self.options.read().unwrap()
+    }
+
+    /// 替换缓存过期时间、回源锁等选项，对之后的读写生效；熔断参数只在创建时生效
+    pub fn set_options(&self, options: CacheAsideOptions) {
+        *self.options.write().unwrap() = options;
+    }
+
+    /// 执行一次缓存操作，失败或熔断时返回 `None`，调用方退回到数据库。
+    ///
+    /// 有等待重放的失效时先重放，失败时这次操作也按失败处理，熔断器不会因此恢复
+    async fn guard<T>(
+        &self,
+        cache: &dyn KvCache,
+        op: &'static str,
+        call: impl Future<Output = Result<T, AppError>>,
+    ) -> Option<T> {
+        if !self.breaker.allow() {
+            self.degraded.add(
+                1,
+                &[
+                    KeyValue::new("op", op),
+                    KeyValue::new("reason", "circuit_open"),
+                ],
+            );
+            warn!(target: "service::kv", op, "⚠️ cache circuit open, fall back to db");
+            return None;
+        }
+        let result = match self.replay(cache).await {
+            Ok(()) => call.await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(value) => {
+                self.breaker.on_success();
+                Some(value)
+            }
+            Err(e) => {
+                // 只有缓存服务本身的错误才计入熔断，反序列化失败说明是单个 key 的脏数据
+                if matches!(e, AppError::Redis(_)) {
+                    self.breaker.on_failure();
+                }
+                self.degraded.add(
+                    1,
+                    &[KeyValue::new("op", op), KeyValue::new("reason", "error")],
+                );
+                warn!(target: "service::kv", op, error = %e, "⚠️ cache error, fall back to db");
+                None
+            }
+        }
+    }
+
+    /// 重放失效失败的 key，遇到错误时停止，剩下的留到下一次
+    async fn replay(&self, cache: &dyn KvCache) -> Result<(), AppError> {
+        let keys: Vec<(String, u64)> = {
+            let pending = self.pending.lock().unwrap();
+            if pending.keys.is_empty() {
+                return Ok(());
+            }
+            pending
+                .keys
+                .iter()
+                .map(|(key, seq)| (key.clone(), *seq))
+                .collect()
+        };
+        for (key, seq) in &keys {
+            cache.invalidate(key).await?;
+            let mut pending = self.pending.lock().unwrap();
+            if pending.keys.get(key) == Some(seq) {
+                pending.keys.remove(key);
+            }
+        }
+        info!(target: "service::kv", count = keys.len(), "✅ replayed pending cache invalidations");
+        Ok(())
+    }
+
+    /// 读 key，缓存未命中时从数据库读取并回填
+    ///
+    /// 缓存不可用时直接读数据库，不会因为缓存故障返回错误
+    #[instrument(skip(self, db, cache), target = "service::kv")]
+    pub async fn get(
+        &self,
+        db: &dyn KvStore,
+        cache: &dyn KvCache,
+        key: &str,
+    ) -> Result<Option<KvPair>, AppError> {
+        let cache_key = cache_key(db.namespace(), key);
+        let Some(cached) = self.read_cache(cache, &cache_key).await else {
+            return db.get(key).await;
+        };
+        match cached {
+            Some(Some(kv)) => {
+                info!(target: "service::kv", "✅ cache hit");
+                return Ok(Some(kv));
+            }
+            Some(None) => {
+                info!(target: "service::kv", "✅ negative cache hit");
+                return Ok(None);
+            }
+            None => info!(target: "service::kv", "⚠️ cache miss"),
+        }
+
+        // 必须在读数据库之前拿版本号。合并按版本号区分，写操作之后的读不会
+        // 加入写之前就开始的回源，读到写之前的旧值
+        let Some(version) = self
+            .guard(cache, "version", cache.version(&cache_key))
+            .await
+        else {
+            return db.get(key).await;
+        };
+        self.flights
+            .run(&format!("{}@{}", cache_key, version), || {
+                self.load(db, cache, key, version)
+            })
+            .await
+    }
+
+    /// 批量读 key，返回值和 `keys` 一一对应
+    ///
+    /// 一次 `get_many` 查缓存，未命中的 key 合并为一次数据库查询，再逐个带版本号回填
+    #[instrument(skip(self, db, cache), target = "service::kv")]
+    pub async fn get_many(
+        &self,
+        db: &dyn KvStore,
+        cache: &dyn KvCache,
+        keys: &[String],
+    ) -> Result<Vec<Option<KvPair>>, AppError> {
+        let cache_keys: Vec<String> = keys
+            .iter()
+            .map(|key| cache_key(db.namespace(), key))
+            .collect();
+        let cached = self
+            .guard(cache, "mget", cache.get_many::<Option<KvPair>>(&cache_keys))
+            .await
+            .unwrap_or_else(|| vec![None; keys.len()]);
+        let cached = cached
+            .into_iter()
+            .map(|cached| cached.map(|kv| kv.filter(|kv| !kv.is_expired())));
+
+        let mut results = Vec::with_capacity(keys.len());
+        let mut misses = Vec::new();
+        for (i, cached) in cached.enumerate() {
+            match cached {
+                Some(kv) => results.push(kv),
+                None => {
+                    results.push(None);
+                    misses.push(i);
+                }
+            }
+        }
+        info!(target: "service::kv", hits = keys.len() - misses.len(), misses = misses.len(), "✅ batch cache lookup");
+        if misses.is_empty() {
+            return Ok(results);
+        }
+
+        // 和单个读一样，版本号必须在读数据库之前拿到
+        let miss_keys: Vec<String> = misses.iter().map(|&i| keys[i].clone()).collect();
+        let miss_cache_keys: Vec<String> = misses.iter().map(|&i| cache_keys[i].clone()).collect();
+        let versions = self
+            .guard(cache, "versions", cache.versions(&miss_cache_keys))
+            .await;
+
+        let mut loaded: HashMap<String, KvPair> = db
+            .get_many(&miss_keys)
+            .await?
+            .into_iter()
+            .map(|kv| (kv.key.clone(), kv))
+            .collect();
+
+        for (n, &i) in misses.iter().enumerate() {
+            let kv = loaded.remove(&keys[i]);
+            if let Some(versions) = &versions
+                && self.cacheable(&kv)
+                && let Some(ttl) = self.refill_ttl(&kv)
+            {
+                self.guard(
+                    cache,
+                    "refill",
+                    cache.set_if_version(&cache_keys[i], &kv, ttl, versions[n]),
+                )
+                .await;
+            }
+            // 请求里有重复的 key 时，后面的也要拿到值
+            if let Some(kv) = &kv {
+                loaded.insert(kv.key.clone(), kv.clone());
+            }
+            results[i] = kv;
+        }
+        Ok(results)
+    }
+
+    async fn load(
+        &self,
+        db: &dyn KvStore,
+        cache: &dyn KvCache,
+        key: &str,
+        version: u64,
+    ) -> Result<Option<KvPair>, AppError> {
+        let cache_key = cache_key(db.namespace(), key);
+        // 上一轮合并的调用可能刚刚回填了缓存
+        if let Some(Some(kv)) = self.read_cache(cache, &cache_key).await {
+            return Ok(kv);
+        }
+
+        if !self.options().fill_lock {
+            return self.fill(db, cache, key, version).await;
+        }
+
+        let lock_key = format!("{}:lock", cache_key);
+        let token = format!("{:x}", rand::random::<u64>());
+        match self
+            .guard(
+                cache,
+                "lock",
+                cache.try_lock(&lock_key, &token, FILL_LOCK_TTL_MS),
+            )
+            .await
+        {
+            Some(true) => {
+                return self
+                    .fill_locked(db, cache, key, version, &lock_key, &token)
+                    .await;
+            }
+            // 拿锁失败时不等待，直接回源
+            None => return self.fill(db, cache, key, version).await,
+            Some(false) => {}
+        }
+
+        info!(target: "service::kv", "⏳ another instance is loading, wait for cache refill");
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(FILL_LOCK_TTL_MS);
+        let mut interval = FILL_LOCK_POLL_INTERVAL;
+        while tokio::time::Instant::now() + interval < deadline {
+            tokio::time::sleep(interval).await;
+            interval = (interval * 2).min(FILL_LOCK_POLL_MAX_INTERVAL);
+            match self.read_cache(cache, &cache_key).await {
+                Some(Some(kv)) => return Ok(kv),
+                Some(None) => {}
+                None => break,
+            }
+            // 持有者没有回填就释放了锁（value 太大、读数据库出错），由这里接着回源
+            if self
+                .guard(
+                    cache,
+                    "lock",
+                    cache.try_lock(&lock_key, &token, FILL_LOCK_TTL_MS),
+                )
+                .await
+                == Some(true)
+            {
+                return self
+                    .fill_locked(db, cache, key, version, &lock_key, &token)
+                    .await;
+            }
+        }
+        info!(target: "service::kv", "⚠️ wait for cache refill timed out, load from db");
+        self.fill(db, cache, key, version).await
+    }
+
+    /// 持有回源锁时回源，结束后释放锁
+    async fn fill_locked(
+        &self,
+        db: &dyn KvStore,
+        cache: &dyn KvCache,
+        key: &str,
+        version: u64,
+        lock_key: &str,
+        token: &str,
+    ) -> Result<Option<KvPair>, AppError> {
+        let result = self.fill(db, cache, key, version).await;
+        self.guard(cache, "unlock", cache.unlock(lock_key, token))
+            .await;
+        result
+    }
+
+    /// 读缓存，缓存里的 key 已经过期时按不存在处理
+    async fn read_cache(
+        &self,
+        cache: &dyn KvCache,
+        cache_key: &str,
+    ) -> Option<Option<Option<KvPair>>> {
+        self.guard(cache, "get", cache.get::<Option<KvPair>>(cache_key))
+            .await
+            .map(|cached| cached.map(|kv| kv.filter(|kv| !kv.is_expired())))
+    }
+
+    /// value 是否小到可以写入缓存，墓碑总是可以
+    fn cacheable(&self, kv: &Option<KvPair>) -> bool {
+        kv.as_ref().is_none_or(|kv| {
+            kv_value::size(&kv.value, kv.content_type.as_deref()) <= self.options().max_value_bytes
+        })
+    }
+
+    /// 回填缓存的过期时间，不超过 key 剩余的有效期，剩余不到一秒时不回填
+    fn refill_ttl(&self, kv: &Option<KvPair>) -> Option<u64> {
+        let Some(kv) = kv else {
+            return Some(jittered_ttl(self.options().negative_ttl_secs));
+        };
+        let ttl = jittered_ttl(self.options().ttl_secs);
+        match kv.expires_at {
+            Some(expires_at) => {
+                let remaining = (expires_at - chrono::Utc::now()).num_seconds();
+                (remaining > 0).then(|| ttl.min(remaining as u64))
+            }
+            None => Some(ttl),
+        }
+    }
+
+    /// 从数据库读取并带版本号回填缓存，key 不存在时回填墓碑
+    async fn fill(
+        &self,
+        db: &dyn KvStore,
+        cache: &dyn KvCache,
+        key: &str,
+        version: u64,
+    ) -> Result<Option<KvPair>, AppError> {
+        let kv = db.get(key).await?;
+
+        if !self.cacheable(&kv) {
+            info!(target: "service::kv", "⚠️ value is too large for the cache, skip refill");
+            return Ok(kv);
+        }
+        let Some(ttl) = self.refill_ttl(&kv) else {
+            info!(target: "service::kv", "⚠️ key is about to expire, skip refill");
+            return Ok(kv);
+        };
+        match &kv {
+            Some(_) => info!(target: "service::kv", "✏️ refill cache"),
+            None => info!(target: "service::kv", "✏️ refill cache with tombstone"),
+        }
+        if self
+            .guard(
+                cache,
+                "refill",
+                cache.set_if_version(&cache_key(db.namespace(), key), &kv, ttl, version),
+            )
+            .await
+            == Some(false)
+        {
+            info!(target: "service::kv", "⚠️ cache invalidated by a concurrent write, skip refill");
+        }
+        Ok(kv)
+    }
+
+    /// 写数据库成功后调用，失效 `db` 所在 namespace 中 key 的缓存
+    ///
+    /// 数据库已经写入成功，失效失败时不返回错误，记下 key，下一次访问缓存之前重放
+    #[instrument(skip(self, db, cache), target = "service::kv")]
+    pub async fn invalidate(&self, db: &dyn KvStore, cache: &dyn KvCache, key: &str) {
+        info!(target: "service::kv", "🗑️ invalidate cache");
+        let cache_key = cache_key(db.namespace(), key);
+        if self
+            .guard(cache, "invalidate", cache.invalidate(&cache_key))
+            .await
+            .is_none()
+        {
+            let mut pending = self.pending.lock().unwrap();
+            pending.seq += 1;
+            let seq = pending.seq;
+            pending.keys.insert(cache_key, seq);
+            warn!(target: "service::kv", pending = pending.keys.len(), "⚠️ cache not invalidated, retry before the cache is used again");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::MemoryCache,
+        models::CreateKv,
+        store::{Expiry, testing::HookedStore},
+    };
+    use async_trait::async_trait;
+    use chrono::Utc;
+    use serde_json::Value;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// 可以模拟宕机的缓存，同时记录调用次数
+    struct FlakyCache {
+        inner: MemoryCache,
+        down: AtomicBool,
+        calls: AtomicUsize,
+    }
+
+    impl FlakyCache {
+        fn check(&self) -> Result<(), AppError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.down.load(Ordering::SeqCst) {
+                return Err(redis::RedisError::from((redis::ErrorKind::IoError, "down")).into());
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl KvCache for FlakyCache {
+        async fn set_raw(&self, key: &str, value: String, ttl_secs: u64) -> Result<(), AppError> {
+            self.check()?;
+            self.inner.set_raw(key, value, ttl_secs).await
+        }
+
+        async fn get_raw(&self, key: &str) -> Result<Option<String>, AppError> {
+            self.check()?;
+            self.inner.get_raw(key).await
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), AppError> {
+            self.check()?;
+            self.inner.delete(key).await
+        }
+
+        async fn version(&self, key: &str) -> Result<u64, AppError> {
+            self.check()?;
+            self.inner.version(key).await
+        }
+
+        async fn invalidate(&self, key: &str) -> Result<(), AppError> {
+            self.check()?;
+            self.inner.invalidate(key).await
+        }
+
+        async fn set_raw_if_version(
+            &self,
+            key: &str,
+            value: String,
+            ttl_secs: u64,
+            version: u64,
+        ) -> Result<bool, AppError> {
+            self.check()?;
+            self.inner
+                .set_raw_if_version(key, value, ttl_secs, version)
+                .await
+        }
+
+        async fn try_lock(&self, key: &str, token: &str, ttl_ms: u64) -> Result<bool, AppError> {
+            self.check()?;
+            self.inner.try_lock(key, token, ttl_ms).await
+        }
+
+        async fn unlock(&self, key: &str, token: &str) -> Result<(), AppError> {
+            self.check()?;
+            self.inner.unlock(key, token).await
+        }
+    }
+
+    /// 读数据库很慢的存储，用来放大读写并发的窗口，同时记录读数据库的次数
+    fn slow_store(read_delay: Duration) -> HookedStore {
+        HookedStore {
+            read_delay,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_reader_does_not_refill_stale_value() {
+        let db = Arc::new(slow_store(Duration::from_millis(50)));
+        let cache: Arc<dyn KvCache> = Arc::new(MemoryCache::new());
+        let cache_aside = Arc::new(CacheAside::new(CacheAsideOptions::default()));
+        db.set(CreateKv {
+            key: "k".to_string(),
+            value: "v1".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // 读者在写之前读到 v1，但回填发生在写之后
+        let reader = {
+            let (db, cache, cache_aside) = (db.clone(), cache.clone(), cache_aside.clone());
+            tokio::spawn(async move { cache_aside.get(db.as_ref(), cache.as_ref(), "k").await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        db.update("k", &"v2".into(), None, Expiry::Keep)
+            .await
+            .unwrap();
+        cache_aside
+            .invalidate(db.as_ref(), cache.as_ref(), "k")
+            .await;
+
+        let stale = reader.await.unwrap().unwrap().unwrap();
+        assert_eq!(stale.value, "v1");
+
+        let kv = cache_aside
+            .get(db.as_ref(), cache.as_ref(), "k")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(kv.value, "v2");
+    }
+
+    #[tokio::test]
+    async fn large_values_skip_the_cache() {
+        let db = slow_store(Duration::ZERO);
+        let cache = MemoryCache::new();
+        let cache_aside = CacheAside::new(CacheAsideOptions {
+            max_value_bytes: 4,
+            ..Default::default()
+        });
+        for (key, data) in [("small", &b"1234"[..]), ("large", &b"12345"[..])] {
+            db.set(CreateKv {
+                key: key.to_string(),
+                value: crate::kv_value::encode(data),
+                content_type: Some("application/octet-stream".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        for _ in 0..2 {
+            for key in ["small", "large"] {
+                cache_aside.get(&db, &cache, key).await.unwrap().unwrap();
+            }
+        }
+        // small 第二次命中缓存，large 每次都读数据库
+        assert_eq!(db.reads.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_updates_and_gets_leave_no_stale_cache() {
+        let db = Arc::new(slow_store(Duration::from_millis(1)));
+        let cache: Arc<dyn KvCache> = Arc::new(MemoryCache::new());
+        let cache_aside = Arc::new(CacheAside::new(CacheAsideOptions::default()));
+        db.set(CreateKv {
+            key: "k".to_string(),
+            value: "0".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let mut tasks = Vec::new();
+        for writer in 0..4 {
+            let (db, cache, cache_aside) = (db.clone(), cache.clone(), cache_aside.clone());
+            tasks.push(tokio::spawn(async move {
+                for i in 0..50 {
+                    let value = Value::from(format!("{}-{}", writer, i));
+                    let written = db.update("k", &value, None, Expiry::Keep).await.unwrap();
+                    cache_aside
+                        .invalidate(db.as_ref(), cache.as_ref(), "k")
+                        .await;
+
+                    // 写完成后再读，不能读到比这次写更旧的值
+                    let kv = cache_aside
+                        .get(db.as_ref(), cache.as_ref(), "k")
+                        .await
+                        .unwrap()
+                        .unwrap();
+                    assert!(kv.updated_at >= written.updated_at);
+                }
+            }));
+        }
+        for _ in 0..8 {
+            let (db, cache, cache_aside) = (db.clone(), cache.clone(), cache_aside.clone());
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..100 {
+                    cache_aside
+                        .get(db.as_ref(), cache.as_ref(), "k")
+                        .await
+                        .unwrap();
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // 所有写结束后，缓存里的值必须和数据库一致
+        let latest = db.inner.get("k").await.unwrap().unwrap();
+        for _ in 0..3 {
+            let kv = cache_aside
+                .get(db.as_ref(), cache.as_ref(), "k")
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(kv.value, latest.value);
+            assert_eq!(kv.updated_at, latest.updated_at);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_misses_share_one_db_read() {
+        // 读数据库比轮询的第一个间隔慢得多，等待的实例仍然不回源
+        let db = Arc::new(slow_store(Duration::from_millis(500)));
+        let cache: Arc<dyn KvCache> = Arc::new(MemoryCache::new());
+        db.set(CreateKv {
+            key: "k".to_string(),
+            value: "v".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let mut tasks = Vec::new();
+        // 每个 CacheAside 代表一个实例，实例之间只能通过缓存上的回源锁合并
+        for _ in 0..32 {
+            let (db, cache) = (db.clone(), cache.clone());
+            let cache_aside = CacheAside::new(CacheAsideOptions {
+                fill_lock: true,
+                ..Default::default()
+            });
+            tasks.push(tokio::spawn(async move {
+                cache_aside.get(db.as_ref(), cache.as_ref(), "k").await
+            }));
+        }
+        for task in tasks {
+            assert_eq!(task.await.unwrap().unwrap().unwrap().value, "v");
+        }
+        assert_eq!(db.reads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn jittered_ttl_stays_within_bounds() {
+        for _ in 0..100 {
+            let ttl = jittered_ttl(KV_CACHE_TTL_SECS);
+            assert!((KV_CACHE_TTL_SECS..=KV_CACHE_TTL_SECS * 11 / 10).contains(&ttl));
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_key_is_remembered_until_set() {
+        let db = Arc::new(slow_store(Duration::ZERO));
+        let cache: Arc<dyn KvCache> = Arc::new(MemoryCache::new());
+        let cache_aside = CacheAside::new(CacheAsideOptions::default());
+
+        for _ in 0..3 {
+            let kv = cache_aside
+                .get(db.as_ref(), cache.as_ref(), "k")
+                .await
+                .unwrap();
+            assert!(kv.is_none());
+        }
+        assert_eq!(db.reads.load(Ordering::SeqCst), 1);
+
+        // 新建 key 之后墓碑必须失效
+        db.set(CreateKv {
+            key: "k".to_string(),
+            value: "v".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        cache_aside
+            .invalidate(db.as_ref(), cache.as_ref(), "k")
+            .await;
+
+        let kv = cache_aside
+            .get(db.as_ref(), cache.as_ref(), "k")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(kv.value, "v");
+    }
+
+    #[tokio::test]
+    async fn cache_outage_falls_back_to_db_and_recovers() {
+        let db = Arc::new(slow_store(Duration::ZERO));
+        let cache = Arc::new(FlakyCache {
+            inner: MemoryCache::new(),
+            down: AtomicBool::new(true),
+            calls: AtomicUsize::new(0),
+        });
+        let cache_aside = CacheAside::new(CacheAsideOptions {
+            breaker_failure_threshold: 2,
+            breaker_open_duration: Duration::from_millis(50),
+            ..Default::default()
+        });
+        db.set(CreateKv {
+            key: "k".to_string(),
+            value: "v".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // 缓存宕机时读写都不报错，连续失败后熔断，不再访问缓存
+        for _ in 0..5 {
+            let kv = cache_aside
+                .get(db.as_ref(), cache.as_ref(), "k")
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(kv.value, "v");
+        }
+        cache_aside
+            .invalidate(db.as_ref(), cache.as_ref(), "k")
+            .await;
+        assert_eq!(cache.calls.load(Ordering::SeqCst), 2);
+        assert!(cache_aside.breaker.is_open());
+
+        // 缓存恢复后，熔断到期的探测请求成功，重新开始使用缓存
+        cache.down.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        for _ in 0..3 {
+            cache_aside
+                .get(db.as_ref(), cache.as_ref(), "k")
+                .await
+                .unwrap();
+        }
+        assert!(!cache_aside.breaker.is_open());
+        assert_eq!(db.reads.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn invalidation_missed_during_outage_is_replayed_on_recovery() {
+        let db = Arc::new(slow_store(Duration::ZERO));
+        let cache = Arc::new(FlakyCache {
+            inner: MemoryCache::new(),
+            down: AtomicBool::new(false),
+            calls: AtomicUsize::new(0),
+        });
+        let cache_aside = CacheAside::new(CacheAsideOptions {
+            breaker_failure_threshold: 1,
+            breaker_open_duration: Duration::from_millis(50),
+            ..Default::default()
+        });
+        for key in ["a", "b"] {
+            db.set(CreateKv {
+                key: key.to_string(),
+                value: "v1".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+            cache_aside
+                .get(db.as_ref(), cache.as_ref(), key)
+                .await
+                .unwrap();
+        }
+
+        // a 的失效在缓存出错时失败，b 的失效在熔断期间被跳过
+        cache.down.store(true, Ordering::SeqCst);
+        for key in ["a", "b"] {
+            db.update(key, &"v2".into(), None, Expiry::Keep)
+                .await
+                .unwrap();
+            cache_aside
+                .invalidate(db.as_ref(), cache.as_ref(), key)
+                .await;
+        }
+        assert!(cache_aside.breaker.is_open());
+
+        // 缓存恢复后缓存里还是 v1，但读到的必须是数据库里的 v2
+        cache.down.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        for key in ["a", "b"] {
+            let kv = cache_aside
+                .get(db.as_ref(), cache.as_ref(), key)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(kv.value, "v2");
+        }
+        assert!(!cache_aside.breaker.is_open());
+        assert!(cache_aside.pending.lock().unwrap().keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn batch_get_reads_misses_in_one_query() {
+        let db = Arc::new(slow_store(Duration::ZERO));
+        let cache: Arc<dyn KvCache> = Arc::new(MemoryCache::new());
+        let cache_aside = CacheAside::new(CacheAsideOptions::default());
+        for key in ["a", "b"] {
+            db.set(CreateKv {
+                key: key.to_string(),
+                value: key.into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        let keys: Vec<String> = ["a", "b", "missing", "a"].map(String::from).to_vec();
+
+        for _ in 0..2 {
+            let items = cache_aside
+                .get_many(db.as_ref(), cache.as_ref(), &keys)
+                .await
+                .unwrap();
+            let values: Vec<Option<Value>> =
+                items.into_iter().map(|kv| kv.map(|kv| kv.value)).collect();
+            assert_eq!(
+                values,
+                [Some("a".into()), Some("b".into()), None, Some("a".into())]
+            );
+        }
+        // 第二次全部命中缓存，包括不存在的 key 的墓碑
+        assert_eq!(db.reads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn refill_ttl_is_clamped_to_key_lifetime() {
+        let cache_aside = CacheAside::new(CacheAsideOptions::default());
+        let mut kv = KvPair {
+            expires_at: Some(Utc::now() + chrono::TimeDelta::seconds(30)),
+            ..Default::default()
+        };
+        let ttl = cache_aside.refill_ttl(&Some(kv.clone())).unwrap();
+        assert!((29..=30).contains(&ttl));
+
+        kv.expires_at = Some(Utc::now() + chrono::TimeDelta::milliseconds(500));
+        assert_eq!(cache_aside.refill_ttl(&Some(kv)), None);
+        assert!(cache_aside.refill_ttl(&None).is_some());
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// 正常放行，记录连续失败次数
+    Closed { failures: u32 },
+    /// 熔断中，到期之前直接拒绝
+    Open { until: Instant },
+    /// 熔断到期，只放行一个探测请求
+    HalfOpen { since: Instant },
+}
+
+/// 简单的熔断器：连续失败 `failure_threshold` 次后熔断 `open_duration`，
+/// 到期后放行一个探测请求，探测成功则恢复，失败则继续熔断。
+pub struct CircuitBreaker {
+    name: &'static str,
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            name,
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// 是否放行这次调用
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if now >= until => {
+                info!(target: "service::circuit_breaker", name = self.name, "🔍 circuit half-open, probing");
+                *state = State::HalfOpen { since: now };
+                true
+            }
+            State::Open { .. } => false,
+            // 探测请求被取消时不会上报结果，超时后允许再探测一次
+            State::HalfOpen { since } if now >= since + self.open_duration => {
+                *state = State::HalfOpen { since: now };
+                true
+            }
+            State::HalfOpen { .. } => false,
+        }
+    }
+
+    pub fn on_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, State::Closed { .. }) {
+            info!(target: "service::circuit_breaker", name = self.name, "✅ circuit closed");
+        }
+        *state = State::Closed { failures: 0 };
+    }
+
+    pub fn on_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { failures } if failures + 1 < self.failure_threshold => {
+                *state = State::Closed {
+                    failures: failures + 1,
+                };
+            }
+            State::Closed { .. } | State::HalfOpen { .. } => {
+                warn!(target: "service::circuit_breaker", name = self.name, open_duration = ?self.open_duration, "🔌 circuit opened");
+                *state = State::Open {
+                    until: Instant::now() + self.open_duration,
+                };
+            }
+            State::Open { .. } => {}
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state.lock().unwrap(), State::Closed { .. })
+    }
+}
@@ -0,0 +1,835 @@
+//! 启动配置：先读 TOML 配置文件，再用环境变量覆盖，最后用命令行参数覆盖，读完后统一校验。
+//!
+//! 环境变量沿用原来的名字（`DATABASE_URL`、`REDIS_URL`、`KV_CACHE_*` 等），
+//! 每个命令行参数都有对应的环境变量，见 `--help`。
+//! 各子系统仍然使用自己的选项类型，这里只负责把配置转换过去。
+use crate::{
+    cache::CacheClientOptions, cache_aside::CacheAsideOptions, db::DBPoolOptions,
+    kv_value::ValueLimits, kv_watch, middleware_tower::ratelimit::RateLimit,
+    namespace::NamespaceQuota,
+};
+use clap::Parser;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use hyper_util::server::conn::auto;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+use tracing_subscriber::filter::LevelFilter;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub http2: Http2Config,
+    pub tls: TlsConfig,
+    pub database: DatabaseConfig,
+    pub redis: RedisConfig,
+    pub cache: CacheConfig,
+    pub kv: KvConfig,
+    pub middleware: MiddlewareConfig,
+    pub telemetry: TelemetryConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// 监听地址
+    pub listen: SocketAddr,
+    /// 退出时等待连接关闭的最长时间，超过后直接中断剩下的连接
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+}
+
+/// 同一个端口上同时支持 HTTP/1.1 和 h2c（明文 HTTP/2），这里是 HTTP/2 连接的参数
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Http2Config {
+    /// 每个连接上同时处理的最大 stream 数
+    pub max_concurrent_streams: u32,
+    /// stream 和连接的初始流控窗口
+    pub initial_stream_window_size: u32,
+    pub initial_connection_window_size: u32,
+    /// 根据 BDP 自动调整流控窗口，开启后忽略上面两个窗口大小
+    pub adaptive_window: bool,
+    /// 发送 PING 保活的间隔，不配置时不发送
+    pub keep_alive_interval_secs: Option<u64>,
+    /// 发送 PING 后等待 ACK 的超时时间，超时后关闭连接
+    pub keep_alive_timeout_secs: u64,
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        // 和 hyper 的默认值一致
+        Self {
+            max_concurrent_streams: 200,
+            initial_stream_window_size: 1024 * 1024,
+            initial_connection_window_size: 1024 * 1024,
+            adaptive_window: false,
+            keep_alive_interval_secs: None,
+            keep_alive_timeout_secs: 20,
+        }
+    }
+}
+
+impl Http2Config {
+    /// 按请求的前几个字节自动选择 HTTP/1.1 或 HTTP/2 的连接 builder
+    pub fn connection_builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(self.max_concurrent_streams)
+            .initial_stream_window_size(self.initial_stream_window_size)
+            .initial_connection_window_size(self.initial_connection_window_size)
+            .adaptive_window(self.adaptive_window)
+            .keep_alive_interval(self.keep_alive_interval_secs.map(Duration::from_secs))
+            .keep_alive_timeout(Duration::from_secs(self.keep_alive_timeout_secs));
+        builder
+    }
+}
+
+/// 配置了证书和私钥时监听端口使用 TLS，通过 ALPN 协商 h2 或 http/1.1
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM 格式的证书链，服务端证书在前
+    pub cert_path: Option<PathBuf>,
+    /// PEM 格式的私钥
+    pub key_path: Option<PathBuf>,
+    /// 配置后校验客户端证书（mTLS），PEM 格式的 CA 证书
+    pub client_ca_path: Option<PathBuf>,
+    /// 允许不带证书的客户端连接，带了证书时仍然校验
+    pub client_auth_optional: bool,
+    /// 检查证书文件是否变化的间隔，变化后重新加载，不需要重启
+    pub reload_interval_secs: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert_path: None,
+            key_path: None,
+            client_ca_path: None,
+            client_auth_optional: false,
+            reload_interval_secs: 10,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn enabled(&self) -> bool {
+        self.cert_path.is_some()
+    }
+
+    pub fn reload_interval(&self) -> Duration {
+        Duration::from_secs(self.reload_interval_secs)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// 不配置时使用内存存储，`sqlite:` 开头时使用 SQLite，其他使用 Postgres
+    pub url: Option<String>,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// 从连接池获取连接的超时时间
+    pub acquire_timeout_ms: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        let options = DBPoolOptions::default();
+        Self {
+            url: None,
+            max_connections: options.max_connections,
+            min_connections: options.min_connections,
+            acquire_timeout_ms: options.acquire_timeout.as_millis() as u64,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn pool_options(&self) -> DBPoolOptions {
+        DBPoolOptions {
+            max_connections: self.max_connections,
+            min_connections: self.min_connections,
+            acquire_timeout: Duration::from_millis(self.acquire_timeout_ms),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    /// 不配置时使用进程内缓存
+    pub url: Option<String>,
+    pub connection_timeout_ms: u64,
+    pub response_timeout_ms: u64,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        let options = CacheClientOptions::default();
+        Self {
+            url: None,
+            connection_timeout_ms: options.connection_timeout.as_millis() as u64,
+            response_timeout_ms: options.response_timeout.as_millis() as u64,
+        }
+    }
+}
+
+impl RedisConfig {
+    pub fn client_options(&self) -> CacheClientOptions {
+        CacheClientOptions {
+            connection_timeout: Duration::from_millis(self.connection_timeout_ms),
+            response_timeout: Duration::from_millis(self.response_timeout_ms),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub ttl_secs: u64,
+    pub negative_ttl_secs: u64,
+    /// 通过缓存上的锁跨实例合并缓存未命中的回源请求
+    pub fill_lock: bool,
+    pub breaker_failures: u32,
+    pub breaker_open_ms: u64,
+    /// 写入缓存的 value 的最大字节数，更大的 value 只读数据库
+    pub max_value_bytes: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        let options = CacheAsideOptions::default();
+        Self {
+            ttl_secs: options.ttl_secs,
+            negative_ttl_secs: options.negative_ttl_secs,
+            fill_lock: options.fill_lock,
+            breaker_failures: options.breaker_failure_threshold,
+            breaker_open_ms: options.breaker_open_duration.as_millis() as u64,
+            max_value_bytes: options.max_value_bytes,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn cache_aside_options(&self) -> CacheAsideOptions {
+        CacheAsideOptions {
+            ttl_secs: self.ttl_secs,
+            negative_ttl_secs: self.negative_ttl_secs,
+            fill_lock: self.fill_lock,
+            breaker_failure_threshold: self.breaker_failures,
+            breaker_open_duration: Duration::from_millis(self.breaker_open_ms),
+            max_value_bytes: self.max_value_bytes,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KvConfig {
+    /// JSON 值的最大字节数
+    pub max_value_bytes: usize,
+    /// 二进制值的最大字节数
+    pub max_binary_bytes: usize,
+    /// 每个 namespace 的配额，不配置时不限制
+    pub namespace_max_keys: Option<u64>,
+    pub namespace_max_bytes: Option<u64>,
+    /// 过期 key 的清理间隔
+    pub sweep_interval_secs: u64,
+}
+
+impl Default for KvConfig {
+    fn default() -> Self {
+        let limits = ValueLimits::default();
+        Self {
+            max_value_bytes: limits.max_json_bytes,
+            max_binary_bytes: limits.max_binary_bytes,
+            namespace_max_keys: None,
+            namespace_max_bytes: None,
+            sweep_interval_secs: 60,
+        }
+    }
+}
+
+impl KvConfig {
+    pub fn limits(&self) -> ValueLimits {
+        ValueLimits {
+            max_json_bytes: self.max_value_bytes,
+            max_binary_bytes: self.max_binary_bytes,
+        }
+    }
+
+    pub fn quota(&self) -> NamespaceQuota {
+        NamespaceQuota {
+            max_keys: self.namespace_max_keys,
+            max_bytes: self.namespace_max_bytes,
+        }
+    }
+
+    pub fn sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.sweep_interval_secs)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiddlewareConfig {
+    /// hyper+tower kv service 的请求超时
+    pub kv_timeout_ms: u64,
+    /// `/health`、`/echo` 的请求超时
+    pub echo_timeout_ms: u64,
+    /// 允许的 `Auth-Key`，为空时只要求请求带上 `Auth-Key`
+    pub auth_keys: Vec<String>,
+    /// kv 接口每秒允许的请求数，不配置时不限流
+    pub rate_limit_per_sec: Option<u32>,
+    /// 允许的突发请求数，默认等于 `rate_limit_per_sec`
+    pub rate_limit_burst: Option<u32>,
+}
+
+impl Default for MiddlewareConfig {
+    fn default() -> Self {
+        Self {
+            kv_timeout_ms: 501,
+            echo_timeout_ms: 1000,
+            auth_keys: Vec::new(),
+            rate_limit_per_sec: None,
+            rate_limit_burst: None,
+        }
+    }
+}
+
+impl MiddlewareConfig {
+    pub fn kv_timeout(&self) -> Duration {
+        Duration::from_millis(self.kv_timeout_ms)
+    }
+
+    pub fn echo_timeout(&self) -> Duration {
+        Duration::from_millis(self.echo_timeout_ms)
+    }
+
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit_per_sec.map(|per_sec| RateLimit {
+            per_sec,
+            burst: self.rate_limit_burst.unwrap_or(per_sec),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub service_name: String,
+    /// OTLP gRPC 链路导出地址（Jaeger）
+    pub traces_endpoint: String,
+    /// OTLP HTTP 指标导出地址（Prometheus）
+    pub metrics_endpoint: String,
+    /// 终端日志级别：off、error、warn、info、debug、trace
+    pub log_level: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            service_name: "hyper-tower-service".to_string(),
+            traces_endpoint: "http://localhost:4317".to_string(),
+            metrics_endpoint: "http://localhost:19090/api/v1/otlp/v1/metrics".to_string(),
+            log_level: "info".to_string(),
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub fn log_level(&self) -> Result<LevelFilter, ConfigError> {
+        self.log_level
+            .parse()
+            .map_err(|_| ConfigError::Invalid(format!("unknown log level {}", self.log_level)))
+    }
+}
+
+/// 命令行参数，没有指定的参数再从对应的环境变量读取，都没有时使用配置文件中的值
+#[derive(Clone, Debug, Default, Parser)]
+#[command(version, about = "KV store over hyper and tower")]
+pub struct Cli {
+    /// TOML 配置文件
+    #[arg(long, env = "KV_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "KV_LISTEN")]
+    pub listen: Option<SocketAddr>,
+    #[arg(long, env = "KV_SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+
+    #[arg(long, env = "KV_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    #[arg(long, env = "KV_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    #[arg(long, env = "KV_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<PathBuf>,
+    #[arg(long, env = "KV_TLS_CLIENT_AUTH_OPTIONAL")]
+    pub tls_client_auth_optional: Option<bool>,
+    #[arg(long, env = "KV_TLS_RELOAD_INTERVAL_SECS")]
+    pub tls_reload_interval_secs: Option<u64>,
+
+    #[arg(long, env = "KV_HTTP2_MAX_CONCURRENT_STREAMS")]
+    pub http2_max_concurrent_streams: Option<u32>,
+    #[arg(long, env = "KV_HTTP2_INITIAL_STREAM_WINDOW_SIZE")]
+    pub http2_initial_stream_window_size: Option<u32>,
+    #[arg(long, env = "KV_HTTP2_INITIAL_CONNECTION_WINDOW_SIZE")]
+    pub http2_initial_connection_window_size: Option<u32>,
+    #[arg(long, env = "KV_HTTP2_ADAPTIVE_WINDOW")]
+    pub http2_adaptive_window: Option<bool>,
+    #[arg(long, env = "KV_HTTP2_KEEP_ALIVE_INTERVAL_SECS")]
+    pub http2_keep_alive_interval_secs: Option<u64>,
+    #[arg(long, env = "KV_HTTP2_KEEP_ALIVE_TIMEOUT_SECS")]
+    pub http2_keep_alive_timeout_secs: Option<u64>,
+
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: Option<String>,
+    #[arg(long, env = "DATABASE_MAX_CONNECTIONS")]
+    pub database_max_connections: Option<u32>,
+    #[arg(long, env = "DATABASE_MIN_CONNECTIONS")]
+    pub database_min_connections: Option<u32>,
+    #[arg(long, env = "DATABASE_ACQUIRE_TIMEOUT_MS")]
+    pub database_acquire_timeout_ms: Option<u64>,
+
+    #[arg(long, env = "REDIS_URL")]
+    pub redis_url: Option<String>,
+    #[arg(long, env = "REDIS_CONNECTION_TIMEOUT_MS")]
+    pub redis_connection_timeout_ms: Option<u64>,
+    #[arg(long, env = "REDIS_RESPONSE_TIMEOUT_MS")]
+    pub redis_response_timeout_ms: Option<u64>,
+
+    #[arg(long, env = "KV_CACHE_TTL_SECS")]
+    pub cache_ttl_secs: Option<u64>,
+    #[arg(long, env = "KV_CACHE_NEGATIVE_TTL_SECS")]
+    pub cache_negative_ttl_secs: Option<u64>,
+    #[arg(long, env = "KV_CACHE_FILL_LOCK")]
+    pub cache_fill_lock: Option<bool>,
+    #[arg(long, env = "KV_CACHE_BREAKER_FAILURES")]
+    pub cache_breaker_failures: Option<u32>,
+    #[arg(long, env = "KV_CACHE_BREAKER_OPEN_MS")]
+    pub cache_breaker_open_ms: Option<u64>,
+    #[arg(long, env = "KV_CACHE_MAX_VALUE_BYTES")]
+    pub cache_max_value_bytes: Option<usize>,
+
+    #[arg(long, env = "KV_MAX_VALUE_BYTES")]
+    pub max_value_bytes: Option<usize>,
+    #[arg(long, env = "KV_MAX_BINARY_BYTES")]
+    pub max_binary_bytes: Option<usize>,
+    #[arg(long, env = "KV_NAMESPACE_MAX_KEYS")]
+    pub namespace_max_keys: Option<u64>,
+    #[arg(long, env = "KV_NAMESPACE_MAX_BYTES")]
+    pub namespace_max_bytes: Option<u64>,
+    #[arg(long, env = "KV_SWEEP_INTERVAL_SECS")]
+    pub sweep_interval_secs: Option<u64>,
+
+    #[arg(long, env = "KV_TIMEOUT_MS")]
+    pub kv_timeout_ms: Option<u64>,
+    #[arg(long, env = "KV_ECHO_TIMEOUT_MS")]
+    pub echo_timeout_ms: Option<u64>,
+    /// 逗号分隔的 Auth-Key 列表
+    #[arg(long, env = "KV_AUTH_KEYS", value_delimiter = ',')]
+    pub auth_keys: Option<Vec<String>>,
+    #[arg(long, env = "KV_RATE_LIMIT_PER_SEC")]
+    pub rate_limit_per_sec: Option<u32>,
+    #[arg(long, env = "KV_RATE_LIMIT_BURST")]
+    pub rate_limit_burst: Option<u32>,
+
+    #[arg(long, env = "OTEL_SERVICE_NAME")]
+    pub service_name: Option<String>,
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")]
+    pub traces_endpoint: Option<String>,
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_METRICS_ENDPOINT")]
+    pub metrics_endpoint: Option<String>,
+    #[arg(long, env = "KV_LOG_LEVEL")]
+    pub log_level: Option<String>,
+}
+
+impl Config {
+    /// `cli.config` 指定的配置文件打底，再覆盖 `cli` 中指定的值，最后校验
+    pub fn from_cli(cli: Cli) -> Result<Self, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&content).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn apply(&mut self, cli: Cli) {
+        fn set<T>(field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+
+        set(&mut self.server.listen, cli.listen);
+        set(
+            &mut self.server.shutdown_timeout_secs,
+            cli.shutdown_timeout_secs,
+        );
+
+        let tls = &mut self.tls;
+        tls.cert_path = cli.tls_cert.or(tls.cert_path.take());
+        tls.key_path = cli.tls_key.or(tls.key_path.take());
+        tls.client_ca_path = cli.tls_client_ca.or(tls.client_ca_path.take());
+        set(&mut tls.client_auth_optional, cli.tls_client_auth_optional);
+        set(&mut tls.reload_interval_secs, cli.tls_reload_interval_secs);
+
+        let http2 = &mut self.http2;
+        set(
+            &mut http2.max_concurrent_streams,
+            cli.http2_max_concurrent_streams,
+        );
+        set(
+            &mut http2.initial_stream_window_size,
+            cli.http2_initial_stream_window_size,
+        );
+        set(
+            &mut http2.initial_connection_window_size,
+            cli.http2_initial_connection_window_size,
+        );
+        set(&mut http2.adaptive_window, cli.http2_adaptive_window);
+        http2.keep_alive_interval_secs = cli
+            .http2_keep_alive_interval_secs
+            .or(http2.keep_alive_interval_secs);
+        set(
+            &mut http2.keep_alive_timeout_secs,
+            cli.http2_keep_alive_timeout_secs,
+        );
+
+        let database = &mut self.database;
+        database.url = cli.database_url.or(database.url.take());
+        set(&mut database.max_connections, cli.database_max_connections);
+        set(&mut database.min_connections, cli.database_min_connections);
+        set(
+            &mut database.acquire_timeout_ms,
+            cli.database_acquire_timeout_ms,
+        );
+
+        let redis = &mut self.redis;
+        redis.url = cli.redis_url.or(redis.url.take());
+        set(
+            &mut redis.connection_timeout_ms,
+            cli.redis_connection_timeout_ms,
+        );
+        set(
+            &mut redis.response_timeout_ms,
+            cli.redis_response_timeout_ms,
+        );
+
+        let cache = &mut self.cache;
+        set(&mut cache.ttl_secs, cli.cache_ttl_secs);
+        set(&mut cache.negative_ttl_secs, cli.cache_negative_ttl_secs);
+        set(&mut cache.fill_lock, cli.cache_fill_lock);
+        set(&mut cache.breaker_failures, cli.cache_breaker_failures);
+        set(&mut cache.breaker_open_ms, cli.cache_breaker_open_ms);
+        set(&mut cache.max_value_bytes, cli.cache_max_value_bytes);
+
+        let kv = &mut self.kv;
+        set(&mut kv.max_value_bytes, cli.max_value_bytes);
+        set(&mut kv.max_binary_bytes, cli.max_binary_bytes);
+        kv.namespace_max_keys = cli.namespace_max_keys.or(kv.namespace_max_keys);
+        kv.namespace_max_bytes = cli.namespace_max_bytes.or(kv.namespace_max_bytes);
+        set(&mut kv.sweep_interval_secs, cli.sweep_interval_secs);
+
+        let middleware = &mut self.middleware;
+        set(&mut middleware.kv_timeout_ms, cli.kv_timeout_ms);
+        set(&mut middleware.echo_timeout_ms, cli.echo_timeout_ms);
+        set(&mut middleware.auth_keys, cli.auth_keys);
+        middleware.rate_limit_per_sec = cli.rate_limit_per_sec.or(middleware.rate_limit_per_sec);
+        middleware.rate_limit_burst = cli.rate_limit_burst.or(middleware.rate_limit_burst);
+
+        let telemetry = &mut self.telemetry;
+        set(&mut telemetry.service_name, cli.service_name);
+        set(&mut telemetry.traces_endpoint, cli.traces_endpoint);
+        set(&mut telemetry.metrics_endpoint, cli.metrics_endpoint);
+        set(&mut telemetry.log_level, cli.log_level);
+    }
+
+    /// 启动时校验，不合法的配置不启动服务
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Invalid(message.to_string()));
+
+        if let Some(url) = &self.database.url
+            && !["postgres://", "postgresql://", "sqlite:"]
+                .iter()
+                .any(|scheme| url.starts_with(scheme))
+        {
+            return invalid("database.url must start with postgres://, postgresql:// or sqlite:");
+        }
+        if self.database.max_connections == 0 {
+            return invalid("database.max_connections must be positive");
+        }
+        if self.database.min_connections > self.database.max_connections {
+            return invalid("database.min_connections must not exceed database.max_connections");
+        }
+        if let Some(url) = &self.redis.url
+            && !["redis://", "rediss://", "redis+unix://", "unix://"]
+                .iter()
+                .any(|scheme| url.starts_with(scheme))
+        {
+            return invalid("redis.url must start with redis://, rediss:// or unix://");
+        }
+        if self.tls.cert_path.is_some() != self.tls.key_path.is_some() {
+            return invalid("tls.cert_path and tls.key_path must be set together");
+        }
+        if self.tls.client_ca_path.is_some() && !self.tls.enabled() {
+            return invalid("tls.client_ca_path requires tls.cert_path and tls.key_path");
+        }
+        if self.http2.max_concurrent_streams == 0 {
+            return invalid("http2.max_concurrent_streams must be positive");
+        }
+        // 流控窗口不小于 HTTP/2 默认的 65535，不超过协议允许的 2^31-1
+        for (name, size) in [
+            (
+                "http2.initial_stream_window_size",
+                self.http2.initial_stream_window_size,
+            ),
+            (
+                "http2.initial_connection_window_size",
+                self.http2.initial_connection_window_size,
+            ),
+        ] {
+            if !(65_535..=i32::MAX as u32).contains(&size) {
+                return invalid(&format!("{} must be between 65535 and 2147483647", name));
+            }
+        }
+        if self.http2.keep_alive_interval_secs == Some(0) {
+            return invalid("http2.keep_alive_interval_secs must be positive");
+        }
+        let timeouts = [
+            (
+                "server.shutdown_timeout_secs",
+                self.server.shutdown_timeout_secs,
+            ),
+            (
+                "database.acquire_timeout_ms",
+                self.database.acquire_timeout_ms,
+            ),
+            (
+                "redis.connection_timeout_ms",
+                self.redis.connection_timeout_ms,
+            ),
+            ("redis.response_timeout_ms", self.redis.response_timeout_ms),
+            ("cache.ttl_secs", self.cache.ttl_secs),
+            ("cache.negative_ttl_secs", self.cache.negative_ttl_secs),
+            ("cache.breaker_open_ms", self.cache.breaker_open_ms),
+            ("kv.sweep_interval_secs", self.kv.sweep_interval_secs),
+            ("middleware.kv_timeout_ms", self.middleware.kv_timeout_ms),
+            (
+                "middleware.echo_timeout_ms",
+                self.middleware.echo_timeout_ms,
+            ),
+        ];
+        if let Some((name, _)) = timeouts.iter().find(|(_, value)| *value == 0) {
+            return invalid(&format!("{} must be positive", name));
+        }
+        // `/kv/watch` 的长轮询在请求超时内完成，否则每次没有新变更的轮询都会超时
+        let min_kv_timeout = kv_watch::POLL_WAIT + kv_watch::POLL_READ_MARGIN;
+        if self.middleware.kv_timeout() <= min_kv_timeout {
+            return invalid(&format!(
+                "middleware.kv_timeout_ms must be greater than {} (watch long-poll wait plus read margin)",
+                min_kv_timeout.as_millis()
+            ));
+        }
+        if self.cache.breaker_failures == 0 {
+            return invalid("cache.breaker_failures must be positive");
+        }
+        if self.middleware.auth_keys.iter().any(|key| key.is_empty()) {
+            return invalid("middleware.auth_keys must not contain empty keys");
+        }
+        if self.middleware.rate_limit_per_sec == Some(0)
+            || self.middleware.rate_limit_burst == Some(0)
+        {
+            return invalid("middleware.rate_limit_per_sec and rate_limit_burst must be positive");
+        }
+        if self.kv.max_value_bytes == 0 || self.kv.max_binary_bytes == 0 {
+            return invalid("kv.max_value_bytes and kv.max_binary_bytes must be positive");
+        }
+        for (name, endpoint) in [
+            ("telemetry.traces_endpoint", &self.telemetry.traces_endpoint),
+            (
+                "telemetry.metrics_endpoint",
+                &self.telemetry.metrics_endpoint,
+            ),
+        ] {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                return invalid(&format!("{} must be an http(s) URL", name));
+            }
+        }
+        self.telemetry.log_level()?;
+        Ok(())
+    }
+
+    /// 和 `other` 相比变化的配置项，按 `section.key` 排序。
+    /// 数据库和 Redis 的 URL、auth_keys 可能包含密码，只记录有变化，不记录值
+    pub fn diff(&self, other: &Config) -> Vec<ConfigChange> {
+        let mut old = Vec::new();
+        let mut new = Vec::new();
+        flatten("", &toml::Value::try_from(self).unwrap(), &mut old);
+        flatten("", &toml::Value::try_from(other).unwrap(), &mut new);
+        let mut keys: Vec<&String> = old.iter().chain(&new).map(|(key, _)| key).collect();
+        keys.sort();
+        keys.dedup();
+
+        let lookup = |values: &[(String, String)], key: &str| {
+            values
+                .iter()
+                .find(|(k, _)| k == key)
+                .map_or_else(|| "unset".to_string(), |(_, v)| v.clone())
+        };
+        keys.into_iter()
+            .filter_map(|key| {
+                let (old, new) = (lookup(&old, key), lookup(&new, key));
+                if old == new {
+                    return None;
+                }
+                let secret = key.ends_with(".url") || key == "middleware.auth_keys";
+                Some(ConfigChange {
+                    key: key.clone(),
+                    old: if secret { "***".to_string() } else { old },
+                    new: if secret { "***".to_string() } else { new },
+                })
+            })
+            .collect()
+    }
+}
+
+/// 一项配置的变化，值是 TOML 格式
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigChange {
+    pub key: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl std::fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} -> {}", self.key, self.old, self.new)
+    }
+}
+
+/// 把嵌套的表展开成 `section.key = value`
+fn flatten(prefix: &str, value: &toml::Value, out: &mut Vec<(String, String)>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        value => out.push((prefix.to_string(), value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let path = std::env::temp_dir().join(format!("kv-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [server]
+            listen = "0.0.0.0:8080"
+
+            [cache]
+            ttl_secs = 60
+            fill_lock = true
+
+            [kv]
+            namespace_max_keys = 100
+            "#,
+        )
+        .unwrap();
+        let config_arg = format!("--config={}", path.display());
+
+        // 文件中没有的值保持默认，命令行参数覆盖文件中的值
+        let cli = Cli::try_parse_from(["kv", &config_arg, "--cache-ttl-secs", "30"]).unwrap();
+        let config = Config::from_cli(cli).unwrap();
+        assert_eq!(config.server.listen.port(), 8080);
+        assert_eq!(config.cache.ttl_secs, 30);
+        assert!(config.cache.cache_aside_options().fill_lock);
+        assert_eq!(config.kv.quota().max_keys, Some(100));
+        assert_eq!(config.middleware.kv_timeout_ms, 501);
+
+        // 不合法的值在启动时报错
+        let cli = Cli::try_parse_from(["kv", &config_arg, "--kv-timeout-ms", "0"]).unwrap();
+        assert!(matches!(
+            Config::from_cli(cli),
+            Err(ConfigError::Invalid(_))
+        ));
+        // kv 请求超时要能容纳 watch 长轮询的等待
+        let cli = Cli::try_parse_from(["kv", &config_arg, "--kv-timeout-ms", "500"]).unwrap();
+        assert!(matches!(
+            Config::from_cli(cli),
+            Err(ConfigError::Invalid(_))
+        ));
+        let cli = Cli {
+            database_url: Some("mysql://localhost/kv".into()),
+            ..Default::default()
+        };
+        assert!(Config::from_cli(cli).is_err());
+        std::fs::write(&path, "[server]\nlisten = \"localhost\"\n").unwrap();
+        let cli = Cli::try_parse_from(["kv", &config_arg]).unwrap();
+        assert!(matches!(
+            Config::from_cli(cli),
+            Err(ConfigError::Parse { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
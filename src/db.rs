@@ -1,23 +1,384 @@
 use crate::{
     error::AppError,
-    models::{CreateKv, KvPair},
+    kv_value,
+    models::{CreateKv, ImportMode, KvEvent, KvHistoryEntry, KvPair, NamespaceUsage},
+    namespace::DEFAULT_NAMESPACE,
+    store::{Expiry, KvStore, QuotaLock, resolve_expiry},
 };
-use sqlx::PgPool;
-use tracing::instrument;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{
+    Connection, FromRow, PgPool, Postgres,
+    pool::PoolConnection,
+    postgres::{PgListener, PgPoolOptions},
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{instrument, warn};
+
+/// `kv_store` 的触发器发送变更通知的通道
+const CHANGE_CHANNEL: &str = "kv_changes";
+/// 监听连接断开后重新连接的间隔
+const LISTEN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// 配额锁 advisory lock 的第一个 key，第二个 key 是 namespace 的 hash
+const QUOTA_LOCK_CLASS: i32 = 0x6b76;
+/// 配额锁被其他连接持有时第一次重试的间隔，之后每次翻倍
+const QUOTA_LOCK_RETRY: Duration = Duration::from_millis(5);
+/// 配额锁重试间隔的上限
+const QUOTA_LOCK_RETRY_MAX: Duration = Duration::from_millis(200);
+/// 配额锁连接池的大小，也是能同时持有配额锁的 namespace 数量
+const QUOTA_LOCK_CONNECTIONS: u32 = 4;
 
 pub struct DBClient {
     pool: PgPool,
+    /// 只用来持有配额锁的连接池。持有锁的请求还要从 `pool` 拿连接检查配额和写入，
+    /// 两者共用一个连接池时，锁连接占满连接池后所有写入都拿不到连接
+    locks: PgPool,
+    namespace: String,
+}
+
+/// 配额锁，advisory lock 属于会话，持有锁的连接在释放之前不还给连接池
+struct PgQuotaLock {
+    conn: Option<PoolConnection<Postgres>>,
+    namespace: String,
+}
+
+#[async_trait]
+impl QuotaLock for PgQuotaLock {
+    async fn release(mut self: Box<Self>) {
+        let Some(conn) = self.conn.as_mut() else {
+            return;
+        };
+        let unlocked = sqlx::query("SELECT pg_advisory_unlock($1, hashtext($2))")
+            .bind(QUOTA_LOCK_CLASS)
+            .bind(&self.namespace)
+            .execute(&mut **conn)
+            .await;
+        match unlocked {
+            // 锁已经释放，连接可以还给连接池
+            Ok(_) => drop(self.conn.take()),
+            Err(e) => {
+                warn!(target: "db::kv", namespace = self.namespace, %e, "⚠️ quota unlock failed")
+            }
+        }
+    }
+}
+
+impl Drop for PgQuotaLock {
+    fn drop(&mut self) {
+        // 没有释放（请求被取消或解锁失败）时关闭连接，会话结束时 Postgres 会释放锁
+        if let Some(conn) = self.conn.take() {
+            tokio::spawn(conn.detach().close());
+        }
+    }
+}
+
+/// 数据库连接池配置，Postgres 和 SQLite 共用
+#[derive(Clone, Copy, Debug)]
+pub struct DBPoolOptions {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// 从连接池获取连接的超时时间
+    pub acquire_timeout: Duration,
+}
+
+impl Default for DBPoolOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+        }
+    }
 }
 
 impl DBClient {
-    pub async fn new(database_url: &str) -> Result<Self, AppError> {
-        let pool = PgPool::connect(database_url).await?;
+    pub async fn new(database_url: &str, options: DBPoolOptions) -> Result<Self, AppError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(options.max_connections)
+            .min_connections(options.min_connections)
+            .acquire_timeout(options.acquire_timeout)
+            .connect(database_url)
+            .await?;
         sqlx::migrate!("./migrations").run(&pool).await?;
-        Ok(Self { pool })
+        let locks = PgPoolOptions::new()
+            .max_connections(QUOTA_LOCK_CONNECTIONS)
+            .min_connections(0)
+            .acquire_timeout(options.acquire_timeout)
+            .connect_lazy(database_url)?;
+        Ok(Self {
+            pool,
+            locks,
+            namespace: DEFAULT_NAMESPACE.to_string(),
+        })
+    }
+}
+
+/// `kv_store` 中的一行，二进制值存在 `data` 里，`value` 为空
+#[derive(FromRow)]
+pub struct KvRow {
+    key: String,
+    value: Option<Value>,
+    data: Option<Vec<u8>>,
+    content_type: Option<String>,
+    updated_at: DateTime<Utc>,
+    version: i64,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<KvRow> for KvPair {
+    fn from(row: KvRow) -> Self {
+        KvPair {
+            key: row.key,
+            value: value_of(row.value, row.data),
+            content_type: row.content_type,
+            updated_at: row.updated_at,
+            version: row.version,
+            expires_at: row.expires_at,
+        }
+    }
+}
+
+/// `kv_history` 中的一行
+#[derive(FromRow)]
+pub struct HistoryRow {
+    key: String,
+    value: Option<Value>,
+    data: Option<Vec<u8>>,
+    content_type: Option<String>,
+    version: i64,
+    operation: String,
+    expires_at: Option<DateTime<Utc>>,
+    changed_at: DateTime<Utc>,
+}
+
+impl From<HistoryRow> for KvHistoryEntry {
+    fn from(row: HistoryRow) -> Self {
+        KvHistoryEntry {
+            key: row.key,
+            value: value_of(row.value, row.data),
+            content_type: row.content_type,
+            version: row.version,
+            operation: row.operation,
+            expires_at: row.expires_at,
+            changed_at: row.changed_at,
+        }
+    }
+}
+
+/// upsert 写入的行，`created` 表示新插入或覆盖了已过期的行
+#[derive(FromRow)]
+pub struct UpsertRow {
+    #[sqlx(flatten)]
+    row: KvRow,
+    created: bool,
+}
+
+/// `kv_history` 中的一行连同变更序号和 namespace
+#[derive(FromRow)]
+pub struct ChangeRow {
+    id: i64,
+    namespace: String,
+    #[sqlx(flatten)]
+    entry: HistoryRow,
+}
+
+impl From<ChangeRow> for KvEvent {
+    fn from(row: ChangeRow) -> Self {
+        KvEvent {
+            id: row.id,
+            namespace: row.namespace,
+            operation: row.entry.operation.clone(),
+            kv: KvHistoryEntry::from(row.entry).into_kv(),
+        }
+    }
+}
+
+/// 监听 [`CHANGE_CHANNEL`]，收到通知时改变 `tx` 的值，所有订阅者都退出后结束。
+///
+/// 断线期间的通知会丢失，所以每次（重新）连接成功后也改变一次，让订阅者自己读取错过的变更
+async fn listen(pool: PgPool, tx: watch::Sender<u64>) {
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!(target: "db::kv", error = %e, "⚠️ failed to connect change listener");
+                tokio::select! {
+                    _ = tokio::time::sleep(LISTEN_RETRY_INTERVAL) => continue,
+                    _ = tx.closed() => return,
+                }
+            }
+        };
+        if let Err(e) = listener.listen(CHANGE_CHANNEL).await {
+            warn!(target: "db::kv", error = %e, "⚠️ failed to listen for changes");
+            tokio::select! {
+                _ = tokio::time::sleep(LISTEN_RETRY_INTERVAL) => continue,
+                _ = tx.closed() => return,
+            }
+        }
+        tracing::info!(target: "db::kv", "👂 listening for changes on {}", CHANGE_CHANNEL);
+        tx.send_modify(|n| *n += 1);
+
+        loop {
+            tokio::select! {
+                notification = listener.try_recv() => match notification {
+                    Ok(Some(_)) => tx.send_modify(|n| *n += 1),
+                    // 连接断开，下一次 try_recv 会自动重新连接并重新 LISTEN
+                    Ok(None) => {
+                        warn!(target: "db::kv", "⚠️ change listener disconnected, reconnecting");
+                        tx.send_modify(|n| *n += 1);
+                    }
+                    Err(e) => {
+                        warn!(target: "db::kv", error = %e, "⚠️ change listener failed");
+                        break;
+                    }
+                },
+                _ = tx.closed() => return,
+            }
+        }
+    }
+}
+
+/// 二进制值转成 base64 字符串，和 [`MemoryStore`](crate::store::MemoryStore) 中的表示一致
+fn value_of(value: Option<Value>, data: Option<Vec<u8>>) -> Value {
+    match data {
+        Some(data) => kv_value::encode(&data),
+        None => value.unwrap_or_default(),
+    }
+}
+
+/// 拆成写入 `value` 和 `data` 两列的值，二进制值从 base64 解码后写入 `data`
+pub fn columns<'a>(
+    value: &'a Value,
+    content_type: Option<&str>,
+) -> Result<(Option<&'a Value>, Option<Vec<u8>>), AppError> {
+    match content_type {
+        Some(_) => Ok((None, Some(kv_value::decode(value)?))),
+        None => Ok((Some(value), None)),
+    }
+}
+
+#[async_trait]
+impl KvStore for DBClient {
+    fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn scoped(&self, namespace: &str) -> Arc<dyn KvStore> {
+        // 连接池内部是 Arc，所有 namespace 共用同一个连接池
+        Arc::new(Self {
+            pool: self.pool.clone(),
+            locks: self.locks.clone(),
+            namespace: namespace.to_string(),
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn usage(&self) -> Result<NamespaceUsage, AppError> {
+        tracing::info!(target: "db::kv", "get usage of {} from db", self.namespace);
+        let (keys, bytes): (i64, i64) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*),
+                   COALESCE(SUM(octet_length(key) + COALESCE(octet_length(data), octet_length(
+                       CASE jsonb_typeof(value) WHEN 'string' THEN value #>> '{}' ELSE value::TEXT END
+                   ))), 0)::BIGINT
+            FROM kv_store
+            WHERE namespace = $1
+              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            "#,
+        )
+        .bind(&self.namespace)
+        .fetch_one(&self.pool)
+        .await?;
+
+        tracing::info!(target: "db::kv", "get usage of {} from db success", self.namespace);
+        Ok(NamespaceUsage {
+            namespace: self.namespace.clone(),
+            keys,
+            bytes,
+            ..Default::default()
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn lock_quota(&self) -> Result<Box<dyn QuotaLock>, AppError> {
+        // 用 try 而不是阻塞的 pg_advisory_lock：等锁时不占着锁连接，
+        // 否则等同一个 namespace 的请求会占满锁连接池，其他 namespace 拿不到锁
+        let deadline = tokio::time::Instant::now() + self.locks.options().get_acquire_timeout();
+        let mut retry = QUOTA_LOCK_RETRY;
+        loop {
+            let mut conn = self.locks.acquire().await?;
+            let (locked,): (bool,) =
+                sqlx::query_as("SELECT pg_try_advisory_lock($1, hashtext($2))")
+                    .bind(QUOTA_LOCK_CLASS)
+                    .bind(&self.namespace)
+                    .fetch_one(&mut *conn)
+                    .await?;
+            if locked {
+                return Ok(Box::new(PgQuotaLock {
+                    conn: Some(conn),
+                    namespace: self.namespace.clone(),
+                }));
+            }
+            drop(conn);
+            if tokio::time::Instant::now() >= deadline {
+                warn!(target: "db::kv", namespace = self.namespace, "⚠️ quota lock timed out");
+                return Err(sqlx::Error::PoolTimedOut.into());
+            }
+            tokio::time::sleep(retry).await;
+            retry = (retry * 2).min(QUOTA_LOCK_RETRY_MAX);
+        }
     }
 
     #[instrument(skip(self))]
-    pub async fn set(&self, input: CreateKv) -> Result<KvPair, AppError> {
+    async fn schema(&self) -> Result<Option<Value>, AppError> {
+        tracing::info!(target: "db::kv", "get schema of {} from db", self.namespace);
+        let schema = sqlx::query_scalar("SELECT schema FROM kv_schema WHERE namespace = $1")
+            .bind(&self.namespace)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(schema)
+    }
+
+    #[instrument(skip(self, schema))]
+    async fn set_schema(&self, schema: Option<Value>) -> Result<bool, AppError> {
+        tracing::info!(target: "db::kv", "set schema of {} in db", self.namespace);
+        let existed = match schema {
+            Some(schema) => {
+                // xmax 不为 0 说明是冲突后更新的已有行
+                sqlx::query_scalar::<_, bool>(
+                    r#"
+                    INSERT INTO kv_schema (namespace, schema)
+                    VALUES ($1, $2)
+                    ON CONFLICT (namespace)
+                    DO UPDATE SET schema = EXCLUDED.schema,
+                                  updated_at = CURRENT_TIMESTAMP
+                    RETURNING xmax::TEXT <> '0'
+                    "#,
+                )
+                .bind(&self.namespace)
+                .bind(schema)
+                .fetch_one(&self.pool)
+                .await?
+            }
+            None => {
+                sqlx::query("DELETE FROM kv_schema WHERE namespace = $1")
+                    .bind(&self.namespace)
+                    .execute(&self.pool)
+                    .await?
+                    .rows_affected()
+                    > 0
+            }
+        };
+
+        tracing::info!(target: "db::kv", "set schema of {} in db success", self.namespace);
+        Ok(existed)
+    }
+
+    #[instrument(skip(self))]
+    async fn set(&self, input: CreateKv) -> Result<KvPair, AppError> {
         // let kv = sqlx::query_as!(
         //     KvPair,
         //     r#"
@@ -36,27 +397,56 @@ impl DBClient {
         // Ok(kv)
 
         tracing::info!(target: "db::kv", "set {:?} to db", input);
-        let kv = sqlx::query_as::<_, KvPair>(
+        let expires_at = resolve_expiry(input.ttl_seconds, input.expires_at)?;
+        let (value, data) = columns(&input.value, input.content_type.as_deref())?;
+        let key = input.key.clone();
+        // 已过期的行按不存在处理，直接覆盖；未过期的行冲突时不返回行，key 已存在
+        // 写入和历史记录在同一条语句里完成，没有写入时也不会记录历史
+        // 新插入的行从历史记录里最大的版本号继续递增，删除后重建的 key 不会复用旧的版本号和 ETag
+        let kv = sqlx::query_as::<_, KvRow>(
             r#"
-            INSERT INTO kv_store (key, value)
-            VALUES ($1, $2)
-            ON CONFLICT (key)
-            DO NOTHING
-            RETURNING key, value, updated_at
+            WITH written AS (
+                INSERT INTO kv_store (namespace, key, value, data, content_type, expires_at, version)
+                VALUES ($6, $1, $2, $3, $4, $5,
+                        (SELECT COALESCE(MAX(version), 0) + 1 FROM kv_history WHERE namespace = $6 AND key = $1))
+                ON CONFLICT (namespace, key)
+                DO UPDATE SET value = EXCLUDED.value,
+                              data = EXCLUDED.data,
+                              content_type = EXCLUDED.content_type,
+                              updated_at = CURRENT_TIMESTAMP,
+                              version = kv_store.version + 1,
+                              expires_at = EXCLUDED.expires_at
+                WHERE kv_store.expires_at <= CURRENT_TIMESTAMP
+                RETURNING namespace, key, value, data, content_type, updated_at, version, expires_at
+            ), history AS (
+                INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+                SELECT namespace, key, value, data, content_type, version, 'create', expires_at, updated_at FROM written
+            )
+            SELECT key, value, data, content_type, updated_at, version, expires_at FROM written
             "#,
         )
-        .bind(input.key)
-        .bind(input.value)
-        .fetch_one(&self.pool)
-        .await?;
-        // .map_err(|_| AppError::InvalidInput("Key already exists".to_string()))?;
+        .bind(&input.key)
+        .bind(value)
+        .bind(data)
+        .bind(&input.content_type)
+        .bind(expires_at)
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::Conflict(format!("Key {} already exists", key)))?;
 
         tracing::info!(target: "db::kv", "set success!");
-        Ok(kv)
+        Ok(kv.into())
     }
 
     #[instrument(skip(self))]
-    pub async fn update(&self, key: &str, value: &str) -> Result<KvPair, AppError> {
+    async fn update(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        expiry: Expiry,
+    ) -> Result<KvPair, AppError> {
         // let kv = sqlx::query_as!(
         //     KvPair,
         //     r#"
@@ -74,26 +464,148 @@ impl DBClient {
         // Ok(kv)
 
         tracing::info!(target: "db::kv", "update db, {} to {}", key, value);
-        let kv = sqlx::query_as::<_, KvPair>(
+        let (value, data) = columns(value, content_type)?;
+        let kv = sqlx::query_as::<_, KvRow>(
             r#"
-            UPDATE kv_store
-            SET value = $2, updated_at = CURRENT_TIMESTAMP
-            WHERE key = $1
-            RETURNING key, value, updated_at
+            WITH written AS (
+                UPDATE kv_store
+                SET value = $2,
+                    data = $3,
+                    content_type = $4,
+                    updated_at = CURRENT_TIMESTAMP,
+                    version = version + 1,
+                    expires_at = CASE WHEN $7 THEN expires_at ELSE $5 END
+                WHERE namespace = $6 AND key = $1
+                  AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                RETURNING namespace, key, value, data, content_type, updated_at, version, expires_at
+            ), history AS (
+                INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+                SELECT namespace, key, value, data, content_type, version, 'update', expires_at, updated_at FROM written
+            )
+            SELECT key, value, data, content_type, updated_at, version, expires_at FROM written
             "#,
         )
         .bind(key)
         .bind(value)
+        .bind(data)
+        .bind(content_type)
+        .bind(expiry.at())
+        .bind(&self.namespace)
+        .bind(expiry.keeps())
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Key {} not found", key)))?;
+
+        tracing::info!(target: "db::kv", "update db success");
+        Ok(kv.into())
+    }
+
+    #[instrument(skip(self))]
+    async fn upsert(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        expiry: Expiry,
+    ) -> Result<(KvPair, bool), AppError> {
+        tracing::info!(target: "db::kv", "upsert db, {} to {}", key, value);
+        let (value, data) = columns(value, content_type)?;
+        // 覆盖已过期的行等同于新建，过期时间重新开始，版本号仍然递增
+        // 插入时 xmax 为 0，冲突后更新的行 xmax 是当前事务，`old` 是语句开始时的快照
+        let kv = sqlx::query_as::<_, UpsertRow>(
+            r#"
+            WITH old AS (
+                SELECT expires_at FROM kv_store WHERE namespace = $6 AND key = $1
+            ), written AS (
+                INSERT INTO kv_store (namespace, key, value, data, content_type, expires_at, version)
+                VALUES ($6, $1, $2, $3, $4, $5,
+                        (SELECT COALESCE(MAX(version), 0) + 1 FROM kv_history WHERE namespace = $6 AND key = $1))
+                ON CONFLICT (namespace, key)
+                DO UPDATE SET value = EXCLUDED.value,
+                              data = EXCLUDED.data,
+                              content_type = EXCLUDED.content_type,
+                              updated_at = CURRENT_TIMESTAMP,
+                              version = kv_store.version + 1,
+                              expires_at = CASE
+                                  WHEN kv_store.expires_at <= CURRENT_TIMESTAMP OR NOT $7 THEN EXCLUDED.expires_at
+                                  ELSE kv_store.expires_at
+                              END
+                RETURNING namespace, key, value, data, content_type, updated_at, version, expires_at,
+                          xmax::TEXT = '0' OR EXISTS (SELECT 1 FROM old WHERE old.expires_at <= CURRENT_TIMESTAMP) AS created
+            ), history AS (
+                INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+                SELECT namespace, key, value, data, content_type, version,
+                       CASE WHEN created THEN 'create' ELSE 'update' END,
+                       expires_at, updated_at
+                FROM written
+            )
+            SELECT key, value, data, content_type, updated_at, version, expires_at, created FROM written
+            "#,
+        )
+        .bind(key)
+        .bind(value)
+        .bind(data)
+        .bind(content_type)
+        .bind(expiry.at())
+        .bind(&self.namespace)
+        .bind(expiry.keeps())
         .fetch_one(&self.pool)
         .await?;
-        // .map_err(|_| AppError::NotFound(format!("Key {} not found", key)))?;
 
-        tracing::info!(target: "db::kv", "update db success");
-        Ok(kv)
+        let UpsertRow { row: kv, created } = kv;
+        tracing::info!(target: "db::kv", "upsert db success, created: {}", created);
+        Ok((kv.into(), created))
+    }
+
+    #[instrument(skip(self))]
+    async fn update_if_version(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        version: i64,
+        expiry: Expiry,
+    ) -> Result<KvPair, AppError> {
+        tracing::info!(target: "db::kv", "update db if version {}, {} to {}", version, key, value);
+        let (value, data) = columns(value, content_type)?;
+        let kv = sqlx::query_as::<_, KvRow>(
+            r#"
+            WITH written AS (
+                UPDATE kv_store
+                SET value = $2,
+                    data = $3,
+                    content_type = $4,
+                    updated_at = CURRENT_TIMESTAMP,
+                    version = version + 1,
+                    expires_at = CASE WHEN $8 THEN expires_at ELSE $6 END
+                WHERE namespace = $7 AND key = $1 AND version = $5
+                  AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                RETURNING namespace, key, value, data, content_type, updated_at, version, expires_at
+            ), history AS (
+                INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+                SELECT namespace, key, value, data, content_type, version, 'update', expires_at, updated_at FROM written
+            )
+            SELECT key, value, data, content_type, updated_at, version, expires_at FROM written
+            "#,
+        )
+        .bind(key)
+        .bind(value)
+        .bind(data)
+        .bind(content_type)
+        .bind(version)
+        .bind(expiry.at())
+        .bind(&self.namespace)
+        .bind(expiry.keeps())
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::PreconditionFailed(format!("Key {} has changed", key)))?;
+
+        tracing::info!(target: "db::kv", "update db if version success");
+        Ok(kv.into())
     }
 
     #[instrument(skip(self))]
-    pub async fn get(&self, key: &str) -> Result<Option<KvPair>, AppError> {
+    async fn get(&self, key: &str) -> Result<Option<KvPair>, AppError> {
         // let kv = sqlx::query_as!(
         //     KvPair,
         //     r#"
@@ -108,23 +620,25 @@ impl DBClient {
         // Ok(kv)
 
         tracing::info!(target: "db::kv", "get {} from db", key);
-        let kv = sqlx::query_as::<_, KvPair>(
+        let kv = sqlx::query_as::<_, KvRow>(
             r#"
-            SELECT key, value, updated_at
+            SELECT key, value, data, content_type, updated_at, version, expires_at
             FROM kv_store
-            WHERE key = $1
+            WHERE namespace = $2 AND key = $1
+              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
             "#,
         )
         .bind(key)
+        .bind(&self.namespace)
         .fetch_optional(&self.pool)
         .await?;
 
         tracing::info!(target: "db::kv", "get {} from db success", key);
-        Ok(kv)
+        Ok(kv.map(KvPair::from))
     }
 
     #[instrument(skip(self))]
-    pub async fn delete(&self, key: &str) -> Result<bool, AppError> {
+    async fn delete(&self, key: &str) -> Result<bool, AppError> {
         // let result = sqlx::query!(
         //     r#"
         //     DELETE FROM kv_store
@@ -137,16 +651,555 @@ impl DBClient {
         // Ok(result.rows_affected() > 0)
 
         tracing::info!(target: "db::kv", "delete {} from db", key);
-        let result = sqlx::query(
+        let deleted: Option<String> = sqlx::query_scalar(
             r#"
-            DELETE FROM kv_store
-            WHERE key = $1
+            WITH deleted AS (
+                DELETE FROM kv_store
+                WHERE namespace = $2 AND key = $1
+                  AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                RETURNING namespace, key, value, data, content_type, version, expires_at
+            ), history AS (
+                INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at)
+                SELECT namespace, key, value, data, content_type, version, 'delete', expires_at FROM deleted
+            )
+            SELECT key FROM deleted
             "#,
         )
         .bind(key)
-        .execute(&self.pool)
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
         .await?;
         tracing::info!(target: "db::kv", "delete {} from db success", key);
-        Ok(result.rows_affected() > 0)
+        Ok(deleted.is_some())
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_if_version(&self, key: &str, version: i64) -> Result<(), AppError> {
+        tracing::info!(target: "db::kv", "delete {} from db if version {}", key, version);
+        let deleted: Option<String> = sqlx::query_scalar(
+            r#"
+            WITH deleted AS (
+                DELETE FROM kv_store
+                WHERE namespace = $3 AND key = $1 AND version = $2
+                  AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                RETURNING namespace, key, value, data, content_type, version, expires_at
+            ), history AS (
+                INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at)
+                SELECT namespace, key, value, data, content_type, version, 'delete', expires_at FROM deleted
+            )
+            SELECT key FROM deleted
+            "#,
+        )
+        .bind(key)
+        .bind(version)
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await?;
+        if deleted.is_none() {
+            return Err(AppError::PreconditionFailed(format!(
+                "Key {} has changed",
+                key
+            )));
+        }
+
+        tracing::info!(target: "db::kv", "delete {} from db if version success", key);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_many(&self, keys: &[String]) -> Result<Vec<KvPair>, AppError> {
+        tracing::info!(target: "db::kv", "get {} keys from db", keys.len());
+        let items = sqlx::query_as::<_, KvRow>(
+            r#"
+            SELECT key, value, data, content_type, updated_at, version, expires_at
+            FROM kv_store
+            WHERE namespace = $2 AND key = ANY($1)
+              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            "#,
+        )
+        .bind(keys)
+        .bind(&self.namespace)
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::info!(target: "db::kv", "get {} keys from db success", items.len());
+        Ok(items.into_iter().map(KvPair::from).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn set_many(&self, items: Vec<CreateKv>) -> Result<Vec<Option<KvPair>>, AppError> {
+        tracing::info!(target: "db::kv", "set {} keys to db", items.len());
+        let expires_at = items
+            .iter()
+            .map(|input| resolve_expiry(input.ttl_seconds, input.expires_at))
+            .collect::<Result<Vec<_>, _>>()?;
+        // 任何一条语句出错时事务在 drop 时回滚，key 已存在不算错误
+        let mut tx = self.pool.begin().await?;
+        let mut results = Vec::with_capacity(items.len());
+        for (input, expires_at) in items.into_iter().zip(expires_at) {
+            let (value, data) = columns(&input.value, input.content_type.as_deref())?;
+            let kv = sqlx::query_as::<_, KvRow>(
+                r#"
+                WITH written AS (
+                    INSERT INTO kv_store (namespace, key, value, data, content_type, expires_at, version)
+                    VALUES ($6, $1, $2, $3, $4, $5,
+                            (SELECT COALESCE(MAX(version), 0) + 1 FROM kv_history WHERE namespace = $6 AND key = $1))
+                    ON CONFLICT (namespace, key)
+                    DO UPDATE SET value = EXCLUDED.value,
+                                  data = EXCLUDED.data,
+                                  content_type = EXCLUDED.content_type,
+                                  updated_at = CURRENT_TIMESTAMP,
+                                  version = kv_store.version + 1,
+                                  expires_at = EXCLUDED.expires_at
+                    WHERE kv_store.expires_at <= CURRENT_TIMESTAMP
+                    RETURNING namespace, key, value, data, content_type, updated_at, version, expires_at
+                ), history AS (
+                    INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+                    SELECT namespace, key, value, data, content_type, version, 'create', expires_at, updated_at FROM written
+                )
+                SELECT key, value, data, content_type, updated_at, version, expires_at FROM written
+                "#,
+            )
+            .bind(&input.key)
+            .bind(value)
+            .bind(data)
+            .bind(&input.content_type)
+            .bind(expires_at)
+            .bind(&self.namespace)
+            .fetch_optional(&mut *tx)
+            .await?;
+            results.push(kv.map(KvPair::from));
+        }
+        tx.commit().await?;
+
+        tracing::info!(target: "db::kv", "set {} keys to db success", results.len());
+        Ok(results)
+    }
+
+    #[instrument(skip(self, items))]
+    async fn import_many(
+        &self,
+        items: Vec<CreateKv>,
+        mode: ImportMode,
+    ) -> Result<Vec<Option<KvPair>>, AppError> {
+        tracing::info!(target: "db::kv", "import {} keys to db, mode: {:?}", items.len(), mode);
+        let expires_at = items
+            .iter()
+            .map(|input| resolve_expiry(input.ttl_seconds, input.expires_at))
+            .collect::<Result<Vec<_>, _>>()?;
+        // 覆盖时和 upsert 一样更新版本号，但过期时间总是以导入的为准
+        // 不覆盖时和 set_many 一样，只有已过期的行可以被替换
+        let sql = match mode {
+            ImportMode::Overwrite => {
+                r#"
+                WITH old AS (
+                    SELECT expires_at FROM kv_store WHERE namespace = $6 AND key = $1
+                ), written AS (
+                    INSERT INTO kv_store (namespace, key, value, data, content_type, expires_at, version)
+                    VALUES ($6, $1, $2, $3, $4, $5,
+                            (SELECT COALESCE(MAX(version), 0) + 1 FROM kv_history WHERE namespace = $6 AND key = $1))
+                    ON CONFLICT (namespace, key)
+                    DO UPDATE SET value = EXCLUDED.value,
+                                  data = EXCLUDED.data,
+                                  content_type = EXCLUDED.content_type,
+                                  updated_at = CURRENT_TIMESTAMP,
+                                  version = kv_store.version + 1,
+                                  expires_at = EXCLUDED.expires_at
+                    RETURNING namespace, key, value, data, content_type, updated_at, version, expires_at,
+                              xmax::TEXT = '0' OR EXISTS (SELECT 1 FROM old WHERE old.expires_at <= CURRENT_TIMESTAMP) AS created
+                ), history AS (
+                    INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+                    SELECT namespace, key, value, data, content_type, version,
+                           CASE WHEN created THEN 'create' ELSE 'update' END,
+                           expires_at, updated_at
+                    FROM written
+                )
+                SELECT key, value, data, content_type, updated_at, version, expires_at FROM written
+                "#
+            }
+            ImportMode::Skip | ImportMode::Fail => {
+                r#"
+                WITH written AS (
+                    INSERT INTO kv_store (namespace, key, value, data, content_type, expires_at, version)
+                    VALUES ($6, $1, $2, $3, $4, $5,
+                            (SELECT COALESCE(MAX(version), 0) + 1 FROM kv_history WHERE namespace = $6 AND key = $1))
+                    ON CONFLICT (namespace, key)
+                    DO UPDATE SET value = EXCLUDED.value,
+                                  data = EXCLUDED.data,
+                                  content_type = EXCLUDED.content_type,
+                                  updated_at = CURRENT_TIMESTAMP,
+                                  version = kv_store.version + 1,
+                                  expires_at = EXCLUDED.expires_at
+                    WHERE kv_store.expires_at <= CURRENT_TIMESTAMP
+                    RETURNING namespace, key, value, data, content_type, updated_at, version, expires_at
+                ), history AS (
+                    INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+                    SELECT namespace, key, value, data, content_type, version, 'create', expires_at, updated_at FROM written
+                )
+                SELECT key, value, data, content_type, updated_at, version, expires_at FROM written
+                "#
+            }
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let mut results = Vec::with_capacity(items.len());
+        for (input, expires_at) in items.into_iter().zip(expires_at) {
+            let (value, data) = columns(&input.value, input.content_type.as_deref())?;
+            let kv = sqlx::query_as::<_, KvRow>(sql)
+                .bind(&input.key)
+                .bind(value)
+                .bind(data)
+                .bind(&input.content_type)
+                .bind(expires_at)
+                .bind(&self.namespace)
+                .fetch_optional(&mut *tx)
+                .await?;
+            if kv.is_none() && mode == ImportMode::Fail {
+                tx.rollback().await?;
+                tracing::info!(target: "db::kv", "import rolled back, key {} already exists", input.key);
+                return Ok(vec![None; results.len() + 1]);
+            }
+            results.push(kv.map(KvPair::from));
+        }
+        tx.commit().await?;
+
+        tracing::info!(target: "db::kv", "import {} keys to db success", results.len());
+        Ok(results)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_many(&self, keys: &[String]) -> Result<Vec<bool>, AppError> {
+        tracing::info!(target: "db::kv", "delete {} keys from db", keys.len());
+        let mut tx = self.pool.begin().await?;
+        let mut results = Vec::with_capacity(keys.len());
+        for key in keys {
+            let deleted: Option<String> = sqlx::query_scalar(
+                r#"
+                WITH deleted AS (
+                    DELETE FROM kv_store
+                    WHERE namespace = $2 AND key = $1
+                      AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                    RETURNING namespace, key, value, data, content_type, version, expires_at
+                ), history AS (
+                    INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at)
+                    SELECT namespace, key, value, data, content_type, version, 'delete', expires_at FROM deleted
+                )
+                SELECT key FROM deleted
+                "#,
+            )
+            .bind(key)
+            .bind(&self.namespace)
+            .fetch_optional(&mut *tx)
+            .await?;
+            results.push(deleted.is_some());
+        }
+        tx.commit().await?;
+
+        tracing::info!(target: "db::kv", "delete {} keys from db success", keys.len());
+        Ok(results)
+    }
+
+    #[instrument(skip(self))]
+    async fn list(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>, AppError> {
+        tracing::info!(target: "db::kv", "list {} after {:?} from db", prefix, after);
+        // keyset 分页：按主键顺序从游标之后继续扫描，不使用 OFFSET
+        // 前缀中的 LIKE 通配符需要转义
+        let pattern = format!(
+            "{}%",
+            prefix
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let items = sqlx::query_as::<_, KvRow>(
+            r#"
+            SELECT key, value, data, content_type, updated_at, version, expires_at
+            FROM kv_store
+            WHERE namespace = $4 AND key LIKE $1 ESCAPE '\'
+              AND ($2::VARCHAR IS NULL OR key > $2)
+              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            ORDER BY key
+            LIMIT $3
+            "#,
+        )
+        .bind(pattern)
+        .bind(after)
+        .bind(limit as i64)
+        .bind(&self.namespace)
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::info!(target: "db::kv", "list {} keys from db success", items.len());
+        Ok(items.into_iter().map(KvPair::from).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn purge_expired(&self, limit: u32) -> Result<Vec<(String, String)>, AppError> {
+        // 分批删除，避免一次删除太多行长时间持有锁
+        // 每个被清理的 key 记录一条 expire 历史，和其他写操作一样产生变更通知
+        let keys: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            WITH purged AS (
+                DELETE FROM kv_store
+                WHERE (namespace, key) IN (
+                    SELECT namespace, key
+                    FROM kv_store
+                    WHERE expires_at <= CURRENT_TIMESTAMP
+                    LIMIT $1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING namespace, key, value, data, content_type, version, expires_at
+            ), history AS (
+                INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at)
+                SELECT namespace, key, value, data, content_type, version, 'expire', expires_at FROM purged
+            )
+            SELECT namespace, key FROM purged
+            "#,
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::info!(target: "db::kv", "purge {} expired keys from db", keys.len());
+        Ok(keys)
+    }
+
+    #[instrument(skip(self))]
+    async fn incr(&self, key: &str, delta: i64) -> Result<KvPair, AppError> {
+        tracing::info!(target: "db::kv", "incr {} by {} in db", key, delta);
+        // 一条语句完成读取、计算和写入，冲突的行由 ON CONFLICT 加锁，并发自增不会丢失更新
+        // 已过期的行按不存在处理，从 0 开始；当前值不是整数、是二进制值（value 为空）或结果溢出时不更新，也不返回行
+        // 整数字符串自增后仍是字符串，JSON 整数自增后仍是整数
+        // AND 的求值顺序不确定，类型转换放在 CASE 里，保证只转换通过了正则检查的值
+        let kv = sqlx::query_as::<_, KvRow>(
+            r#"
+            WITH old AS (
+                SELECT expires_at FROM kv_store WHERE namespace = $3 AND key = $1
+            ), written AS (
+                INSERT INTO kv_store (namespace, key, value, version)
+                VALUES ($3, $1, to_jsonb($2::TEXT),
+                        (SELECT COALESCE(MAX(version), 0) + 1 FROM kv_history WHERE namespace = $3 AND key = $1))
+                ON CONFLICT (namespace, key)
+                DO UPDATE SET value = CASE
+                                  WHEN kv_store.expires_at <= CURRENT_TIMESTAMP THEN EXCLUDED.value
+                                  WHEN jsonb_typeof(kv_store.value) = 'number'
+                                      THEN to_jsonb(((kv_store.value #>> '{}')::NUMERIC + $2)::BIGINT)
+                                  ELSE to_jsonb(((kv_store.value #>> '{}')::NUMERIC + $2)::BIGINT::TEXT)
+                              END,
+                              data = NULL,
+                              content_type = NULL,
+                              updated_at = CURRENT_TIMESTAMP,
+                              version = kv_store.version + 1,
+                              expires_at = CASE
+                                  WHEN kv_store.expires_at <= CURRENT_TIMESTAMP THEN NULL
+                                  ELSE kv_store.expires_at
+                              END
+                WHERE kv_store.expires_at <= CURRENT_TIMESTAMP
+                   OR CASE
+                          WHEN jsonb_typeof(kv_store.value) IN ('string', 'number')
+                           AND kv_store.value #>> '{}' ~ '^[+-]?[0-9]+$'
+                          THEN (kv_store.value #>> '{}')::NUMERIC + $2
+                               BETWEEN -9223372036854775808 AND 9223372036854775807
+                          ELSE FALSE
+                      END
+                RETURNING namespace, key, value, data, content_type, updated_at, version, expires_at,
+                          xmax::TEXT = '0' OR EXISTS (SELECT 1 FROM old WHERE old.expires_at <= CURRENT_TIMESTAMP) AS created
+            ), history AS (
+                INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+                SELECT namespace, key, value, data, content_type, version,
+                       CASE WHEN created THEN 'create' ELSE 'update' END,
+                       expires_at, updated_at
+                FROM written
+            )
+            SELECT key, value, data, content_type, updated_at, version, expires_at FROM written
+            "#,
+        )
+        .bind(key)
+        .bind(delta)
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::Conflict(format!(
+                "Value of key {} is not an integer or would overflow",
+                key
+            ))
+        })?;
+
+        let kv = KvPair::from(kv);
+        tracing::info!(target: "db::kv", "incr db success, {} is now {}", key, kv.value);
+        Ok(kv)
+    }
+
+    #[instrument(skip(self))]
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &Value,
+        value: &Value,
+    ) -> Result<KvPair, AppError> {
+        tracing::info!(target: "db::kv", "cas {} from {} to {} in db", key, expected, value);
+        // 条件更新，比较和写入在同一条语句里完成
+        let kv = sqlx::query_as::<_, KvRow>(
+            r#"
+            WITH written AS (
+                UPDATE kv_store
+                SET value = $3,
+                    updated_at = CURRENT_TIMESTAMP,
+                    version = version + 1
+                WHERE namespace = $4 AND key = $1 AND value = $2
+                  AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                RETURNING namespace, key, value, data, content_type, updated_at, version, expires_at
+            ), history AS (
+                INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+                SELECT namespace, key, value, data, content_type, version, 'update', expires_at, updated_at FROM written
+            )
+            SELECT key, value, data, content_type, updated_at, version, expires_at FROM written
+            "#,
+        )
+        .bind(key)
+        .bind(expected)
+        .bind(value)
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::Conflict(format!("Value of key {} does not match", key)))?;
+
+        tracing::info!(target: "db::kv", "cas db success");
+        Ok(kv.into())
+    }
+
+    #[instrument(skip(self))]
+    async fn history(&self, key: &str, limit: u32) -> Result<Vec<KvHistoryEntry>, AppError> {
+        tracing::info!(target: "db::kv", "get history of {} from db", key);
+        let entries = sqlx::query_as::<_, HistoryRow>(
+            r#"
+            SELECT key, value, data, content_type, version, operation, expires_at, changed_at
+            FROM kv_history
+            WHERE namespace = $3 AND key = $1
+            ORDER BY id DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(key)
+        .bind(limit as i64)
+        .bind(&self.namespace)
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::info!(target: "db::kv", "get {} history entries from db success", entries.len());
+        Ok(entries.into_iter().map(KvHistoryEntry::from).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn history_version(
+        &self,
+        key: &str,
+        version: i64,
+    ) -> Result<Option<KvHistoryEntry>, AppError> {
+        tracing::info!(target: "db::kv", "get version {} of {} from db", version, key);
+        let entry = sqlx::query_as::<_, HistoryRow>(
+            r#"
+            SELECT key, value, data, content_type, version, operation, expires_at, changed_at
+            FROM kv_history
+            WHERE namespace = $3 AND key = $1 AND version = $2 AND operation NOT IN ('delete', 'expire')
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(key)
+        .bind(version)
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        tracing::info!(target: "db::kv", "get version {} of {} from db success", version, key);
+        Ok(entry.map(KvHistoryEntry::from))
+    }
+
+    #[instrument(skip(self))]
+    async fn history_at(
+        &self,
+        key: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<KvHistoryEntry>, AppError> {
+        tracing::info!(target: "db::kv", "get {} at {} from db", key, at);
+        let entry = sqlx::query_as::<_, HistoryRow>(
+            r#"
+            SELECT key, value, data, content_type, version, operation, expires_at, changed_at
+            FROM kv_history
+            WHERE namespace = $3 AND key = $1 AND changed_at <= $2
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(key)
+        .bind(at)
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        tracing::info!(target: "db::kv", "get {} at {} from db success", key, at);
+        Ok(entry.map(KvHistoryEntry::from))
+    }
+
+    #[instrument(skip(self))]
+    async fn changes(&self, after: i64, limit: u32) -> Result<Vec<KvEvent>, AppError> {
+        tracing::info!(target: "db::kv", "get changes after {} from db", after);
+        let events = sqlx::query_as::<_, ChangeRow>(
+            r#"
+            SELECT id, namespace, key, value, data, content_type, version, operation, expires_at, changed_at
+            FROM kv_history
+            WHERE id > $1
+            ORDER BY id
+            LIMIT $2
+            "#,
+        )
+        .bind(after)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::info!(target: "db::kv", "get {} changes from db success", events.len());
+        Ok(events.into_iter().map(KvEvent::from).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn last_change_id(&self) -> Result<i64, AppError> {
+        let (id,): (i64,) = sqlx::query_as("SELECT COALESCE(MAX(id), 0) FROM kv_history")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(id)
+    }
+
+    async fn write_horizon(&self) -> Result<i64, AppError> {
+        // 序号由 kv_history 的默认值分配，写 kv_history 之前已经写过 kv_store，事务已经有了 txid，
+        // 所以占用序号的事务的 txid 都小于这里新分配的 txid。
+        // 快照的 xmax 不行，它是最近结束的事务加 1，不包括还在进行中的更大的 txid
+        let (xid,): (i64,) = sqlx::query_as("SELECT pg_current_xact_id()::TEXT::BIGINT")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(xid)
+    }
+
+    async fn writes_finished(&self, horizon: i64) -> Result<bool, AppError> {
+        // xmin 是最早的还在进行中的事务，小于它的事务都已经结束
+        let (finished,): (bool,) =
+            sqlx::query_as("SELECT pg_snapshot_xmin(pg_current_snapshot())::TEXT::BIGINT >= $1")
+                .bind(horizon)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(finished)
+    }
+
+    fn subscribe(&self) -> watch::Receiver<u64> {
+        let (tx, rx) = watch::channel(0);
+        tokio::spawn(listen(self.pool.clone(), tx));
+        rx
     }
 }
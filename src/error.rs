@@ -18,6 +18,16 @@ pub enum AppError {
     InvalidInput(String),
     #[error("Not Found: {0}")]
     NotFound(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+    /// namespace 的 key 数量超出配额
+    #[error("Key quota exceeded: {0}")]
+    KeyQuotaExceeded(String),
+    /// namespace 的总字节数超出配额
+    #[error("Size quota exceeded: {0}")]
+    SizeQuotaExceeded(String),
 }
 
 impl IntoResponse for AppError {
@@ -27,6 +37,19 @@ impl IntoResponse for AppError {
                 (StatusCode::BAD_REQUEST, format!("Invalid input: {}", msg))
             }
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, format!("Not Found: {}", msg)),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, format!("Conflict: {}", msg)),
+            AppError::PreconditionFailed(msg) => (
+                StatusCode::PRECONDITION_FAILED,
+                format!("Precondition failed: {}", msg),
+            ),
+            AppError::KeyQuotaExceeded(msg) => (
+                StatusCode::FORBIDDEN,
+                format!("Key quota exceeded: {}", msg),
+            ),
+            AppError::SizeQuotaExceeded(msg) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Size quota exceeded: {}", msg),
+            ),
             // AppError::Serialization(err) => (
             //     StatusCode::INTERNAL_SERVER_ERROR,
             //     format!("Serialization error: {}", err),
@@ -56,6 +79,19 @@ impl AppError {
                 (StatusCode::BAD_REQUEST, format!("Invalid input: {}", msg))
             }
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, format!("Not Found: {}", msg)),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, format!("Conflict: {}", msg)),
+            AppError::PreconditionFailed(msg) => (
+                StatusCode::PRECONDITION_FAILED,
+                format!("Precondition failed: {}", msg),
+            ),
+            AppError::KeyQuotaExceeded(msg) => (
+                StatusCode::FORBIDDEN,
+                format!("Key quota exceeded: {}", msg),
+            ),
+            AppError::SizeQuotaExceeded(msg) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Size quota exceeded: {}", msg),
+            ),
             AppError::Database(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             AppError::MigrateError(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             AppError::Redis(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
@@ -0,0 +1,298 @@
+//! ETag 与条件请求，`kv_axum` 和 `kv_tower` 共用。
+//!
+//! ETag 由行版本号生成。带 `If-Match`/`If-None-Match` 的写请求先读出当前值检查条件，
+//! 再按读到的版本号做条件更新或删除，两步之间被其他请求修改时同样返回 412。
+use crate::{
+    error::AppError,
+    models::{CreateKv, KvPair},
+    store::{Expiry, KvStore},
+};
+use hyper::header::{HeaderMap, IF_MATCH, IF_NONE_MATCH};
+use serde_json::Value;
+use tracing::{info, instrument};
+
+/// 强 ETag，只有版本号完全相同才匹配
+pub fn etag(kv: &KvPair) -> String {
+    format!("\"{}\"", kv.version)
+}
+
+/// 判断 `header` 中的 ETag 列表是否包含 `etag`，`weak` 为 true 时忽略 `W/` 前缀
+fn contains(header: &str, etag: &str, weak: bool) -> bool {
+    header.split(',').map(str::trim).any(|tag| {
+        if tag == "*" {
+            return true;
+        }
+        match tag.strip_prefix("W/") {
+            Some(tag) => weak && tag == etag,
+            None => tag == etag,
+        }
+    })
+}
+
+/// 请求中的条件头
+#[derive(Debug, Default)]
+pub struct Preconditions {
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+}
+
+impl Preconditions {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            if_match: get(IF_MATCH),
+            if_none_match: get(IF_NONE_MATCH),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.if_match.is_none() && self.if_none_match.is_none()
+    }
+
+    /// 用 key 的当前值检查条件，不满足时返回 [`AppError::PreconditionFailed`]
+    pub fn check(&self, current: Option<&KvPair>) -> Result<(), AppError> {
+        let current_etag = current.map(etag);
+        if let Some(if_match) = &self.if_match {
+            // If-Match 使用强比较，key 不存在时任何 ETag 都不匹配
+            if !current_etag
+                .as_deref()
+                .is_some_and(|etag| contains(if_match, etag, false))
+            {
+                return Err(AppError::PreconditionFailed(format!(
+                    "If-Match {} does not match",
+                    if_match
+                )));
+            }
+        }
+        if let Some(if_none_match) = &self.if_none_match
+            && current_etag
+                .as_deref()
+                .is_some_and(|etag| contains(if_none_match, etag, true))
+        {
+            return Err(AppError::PreconditionFailed(format!(
+                "If-None-Match {} matches",
+                if_none_match
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 带条件的更新，返回值的第二项表示是否新建
+///
+/// `upsert` 为 true 时 key 不存在会新建，否则返回 404。
+/// `content_type` 不为空时写入二进制值，过期时间按 `expiry` 处理。
+/// 没有条件头时等同于 [`KvStore::update`] 或 [`KvStore::upsert`]
+#[instrument(skip(db), target = "service::kv")]
+pub async fn update(
+    db: &dyn KvStore,
+    key: &str,
+    value: &Value,
+    content_type: Option<&str>,
+    expiry: Expiry,
+    preconditions: &Preconditions,
+    upsert: bool,
+) -> Result<(KvPair, bool), AppError> {
+    if preconditions.is_empty() {
+        return match upsert {
+            true => db.upsert(key, value, content_type, expiry).await,
+            false => Ok((db.update(key, value, content_type, expiry).await?, false)),
+        };
+    }
+
+    let current = db.get(key).await?;
+    preconditions.check(current.as_ref())?;
+    match current {
+        Some(current) => {
+            info!(target: "service::kv", version = current.version, "✅ preconditions passed");
+            let kv = db
+                .update_if_version(key, value, content_type, current.version, expiry)
+                .await?;
+            Ok((kv, false))
+        }
+        // 检查条件时 key 不存在，新建时被其他请求抢先创建同样算条件不满足
+        None if upsert => {
+            let input = CreateKv {
+                key: key.to_string(),
+                value: value.clone(),
+                content_type: content_type.map(str::to_string),
+                expires_at: expiry.at(),
+                ..Default::default()
+            };
+            match db.set(input).await {
+                Ok(kv) => Ok((kv, true)),
+                Err(AppError::Conflict(msg)) => Err(AppError::PreconditionFailed(msg)),
+                Err(e) => Err(e),
+            }
+        }
+        None => Err(AppError::NotFound(format!("Key {} not found", key))),
+    }
+}
+
+/// 带条件的删除，返回 key 是否存在，没有条件头时等同于 [`KvStore::delete`]
+#[instrument(skip(db), target = "service::kv")]
+pub async fn delete(
+    db: &dyn KvStore,
+    key: &str,
+    preconditions: &Preconditions,
+) -> Result<bool, AppError> {
+    if preconditions.is_empty() {
+        return db.delete(key).await;
+    }
+
+    let current = db.get(key).await?;
+    preconditions.check(current.as_ref())?;
+    let Some(current) = current else {
+        return Ok(false);
+    };
+    info!(target: "service::kv", version = current.version, "✅ preconditions passed");
+    db.delete_if_version(key, current.version).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::CreateKv, store::MemoryStore};
+
+    fn preconditions(if_match: Option<&str>, if_none_match: Option<&str>) -> Preconditions {
+        Preconditions {
+            if_match: if_match.map(str::to_string),
+            if_none_match: if_none_match.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn conditional_writes_follow_the_etag() {
+        let db = MemoryStore::new();
+        let kv = db
+            .set(CreateKv {
+                key: "k".to_string(),
+                value: "v1".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let first = etag(&kv);
+
+        let (kv, _) = update(
+            &db,
+            "k",
+            &"v2".into(),
+            None,
+            Expiry::Keep,
+            &preconditions(Some(&first), None),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(kv.value, "v2");
+
+        // 旧的 ETag 已经失效
+        let stale = update(
+            &db,
+            "k",
+            &"v3".into(),
+            None,
+            Expiry::Keep,
+            &preconditions(Some(&first), None),
+            false,
+        )
+        .await;
+        assert!(matches!(stale, Err(AppError::PreconditionFailed(_))));
+        let exists = update(
+            &db,
+            "k",
+            &"v3".into(),
+            None,
+            Expiry::Keep,
+            &preconditions(None, Some("*")),
+            false,
+        )
+        .await;
+        assert!(matches!(exists, Err(AppError::PreconditionFailed(_))));
+        let weak = update(
+            &db,
+            "k",
+            &"v3".into(),
+            None,
+            Expiry::Keep,
+            &preconditions(Some(&format!("W/{}", etag(&kv))), None),
+            false,
+        )
+        .await;
+        assert!(matches!(weak, Err(AppError::PreconditionFailed(_))));
+
+        let deleted = delete(
+            &db,
+            "k",
+            &preconditions(Some(&format!("\"0\", {}", etag(&kv))), None),
+        )
+        .await
+        .unwrap();
+        assert!(deleted);
+        let missing = delete(&db, "k", &preconditions(Some("*"), None)).await;
+        assert!(matches!(missing, Err(AppError::PreconditionFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn upsert_creates_missing_key_once() {
+        let db = MemoryStore::new();
+        let create_only = preconditions(None, Some("*"));
+
+        let (kv, created) = update(
+            &db,
+            "k",
+            &"v1".into(),
+            None,
+            Expiry::Keep,
+            &create_only,
+            true,
+        )
+        .await
+        .unwrap();
+        assert!(created);
+        assert_eq!(kv.version, 1);
+        let again = update(
+            &db,
+            "k",
+            &"v2".into(),
+            None,
+            Expiry::Keep,
+            &create_only,
+            true,
+        )
+        .await;
+        assert!(matches!(again, Err(AppError::PreconditionFailed(_))));
+
+        let (kv, created) = update(
+            &db,
+            "k",
+            &"v2".into(),
+            None,
+            Expiry::Keep,
+            &Preconditions::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        assert!(!created);
+        assert_eq!(kv.version, 2);
+        let missing = update(
+            &db,
+            "other",
+            &"v".into(),
+            None,
+            Expiry::Keep,
+            &Preconditions::default(),
+            false,
+        )
+        .await;
+        assert!(matches!(missing, Err(AppError::NotFound(_))));
+    }
+}
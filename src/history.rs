@@ -0,0 +1,188 @@
+//! 历史版本读取和回滚，`kv_axum` 和 `kv_tower` 共用。
+//!
+//! 每次写操作都会在 `kv_history` 里追加一条记录。按版本或时间点读取直接查历史表，不经过缓存；
+//! 回滚把旧版本的值作为一次新的写入，版本号继续递增，已有的历史记录不会被改写。
+//! key 删除后重建时版本号接着删除前的递增，`?version=` 和回滚总是指向唯一的一次写入。
+use crate::{
+    error::AppError,
+    etag::{self, Preconditions},
+    kv_value::{self, ValueLimits},
+    models::{GetKvQuery, HistoryQuery, KvHistoryEntry, KvPair},
+    namespace::NamespaceQuota,
+    store::{Expiry, KvStore, LIST_DEFAULT_LIMIT, LIST_MAX_LIMIT},
+};
+use tracing::{info, instrument};
+
+/// 按 `version` 或 `at` 读取 key 的历史值，找不到时返回 404
+#[instrument(skip(db), target = "service::kv")]
+pub async fn get(db: &dyn KvStore, key: &str, query: &GetKvQuery) -> Result<KvPair, AppError> {
+    let entry = match (query.version, query.at) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::InvalidInput(
+                "version and at are mutually exclusive".into(),
+            ));
+        }
+        (Some(version), None) => db.history_version(key, version).await?,
+        // 那个时间点 key 已被删除、已过期或已被清理，都按不存在处理
+        (None, Some(at)) => db.history_at(key, at).await?.filter(|entry| {
+            !matches!(entry.operation.as_str(), "delete" | "expire")
+                && entry.expires_at.is_none_or(|expires_at| expires_at > at)
+        }),
+        (None, None) => {
+            return Err(AppError::InvalidInput("version or at is required".into()));
+        }
+    };
+    entry
+        .map(KvHistoryEntry::into_kv)
+        .ok_or_else(|| AppError::NotFound(format!("Key {} not found", key)))
+}
+
+/// 按写入顺序倒序列出 key 的历史记录
+#[instrument(skip(db), target = "service::kv")]
+pub async fn list(
+    db: &dyn KvStore,
+    key: &str,
+    query: &HistoryQuery,
+) -> Result<Vec<KvHistoryEntry>, AppError> {
+    let limit = query.limit.unwrap_or(LIST_DEFAULT_LIMIT);
+    if limit == 0 || limit > LIST_MAX_LIMIT {
+        return Err(AppError::InvalidInput(format!(
+            "limit must be between 1 and {}",
+            LIST_MAX_LIMIT
+        )));
+    }
+    db.history(key, limit).await
+}
+
+/// 把 key 回滚到 `version` 的值，返回值的第二项表示是否新建
+///
+/// key 已被删除时重新创建。过期时间不回滚：key 仍存在时保留当前的过期时间，重新创建时永不过期。
+#[instrument(skip(db), target = "service::kv")]
+pub async fn restore(
+    db: &dyn KvStore,
+    quota: &NamespaceQuota,
+    limits: &ValueLimits,
+    key: &str,
+    version: i64,
+    preconditions: &Preconditions,
+) -> Result<(KvPair, bool), AppError> {
+    let entry = db.history_version(key, version).await?.ok_or_else(|| {
+        AppError::NotFound(format!("Version {} of key {} not found", version, key))
+    })?;
+    // 旧值可能早于当前的 schema 和大小限制，同样需要校验
+    let content_type = entry.content_type.as_deref();
+    kv_value::check(db, limits, &entry.value, content_type).await?;
+    let size = kv_value::size(&entry.value, content_type);
+    info!(target: "service::kv", version, "⏪ restore key");
+    let write = etag::update(
+        db,
+        key,
+        &entry.value,
+        content_type,
+        Expiry::Keep,
+        preconditions,
+        true,
+    );
+    quota.write(db, &[(key, size)], write).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::CreateKv, store::MemoryStore};
+    use chrono::{DateTime, Utc};
+
+    /// 新建 k = v1，更新为 v2 后删除，返回新建之后、更新之前的时刻
+    async fn deleted_key(db: &MemoryStore) -> DateTime<Utc> {
+        db.set(CreateKv {
+            key: "k".to_string(),
+            value: "v1".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let after_create = Utc::now();
+        db.update("k", &"v2".into(), None, Expiry::Keep)
+            .await
+            .unwrap();
+        db.delete("k").await.unwrap();
+        after_create
+    }
+
+    async fn restore_version(db: &MemoryStore, version: i64) -> Result<(KvPair, bool), AppError> {
+        let (quota, limits) = (NamespaceQuota::default(), ValueLimits::default());
+        restore(db, &quota, &limits, "k", version, &Preconditions::default()).await
+    }
+
+    #[tokio::test]
+    async fn history_lists_writes_and_reads_old_versions() {
+        let db = MemoryStore::new();
+        let after_create = deleted_key(&db).await;
+
+        let entries = list(&db, "k", &HistoryQuery::default()).await.unwrap();
+        let operations: Vec<_> = entries.iter().map(|e| e.operation.as_str()).collect();
+        assert_eq!(operations, ["delete", "update", "create"]);
+
+        let by_version = GetKvQuery {
+            version: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(get(&db, "k", &by_version).await.unwrap().value, "v1");
+        let by_time = GetKvQuery {
+            version: None,
+            at: Some(after_create),
+            ..Default::default()
+        };
+        assert_eq!(get(&db, "k", &by_time).await.unwrap().value, "v1");
+        // 删除之后的时刻读到的是删除记录，按不存在处理
+        let now = GetKvQuery {
+            version: None,
+            at: Some(Utc::now()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            get(&db, "k", &now).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn restore_recreates_deleted_key_and_updates_existing_one() {
+        let db = MemoryStore::new();
+        deleted_key(&db).await;
+
+        // 删除后回滚会重新创建，版本号接着删除前的继续递增，再回滚到 v2 是一次普通更新
+        let (kv, created) = restore_version(&db, 1).await.unwrap();
+        assert!(created);
+        assert_eq!((kv.value.as_str(), kv.version), (Some("v1"), 3));
+        let (kv, created) = restore_version(&db, 2).await.unwrap();
+        assert!(!created);
+        assert_eq!((kv.value.as_str(), kv.version), (Some("v2"), 4));
+        assert!(matches!(
+            restore_version(&db, 9).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn version_is_unambiguous_after_delete_and_recreate() {
+        let db = MemoryStore::new();
+        let create = |value: &str| CreateKv {
+            key: "k".to_string(),
+            value: value.into(),
+            ..Default::default()
+        };
+        db.set(create("old")).await.unwrap();
+        db.delete("k").await.unwrap();
+        let kv = db.set(create("new")).await.unwrap();
+        assert_eq!(kv.version, 2);
+
+        // 版本 1 仍然是删除前的值，不会和重建后的写入混在一起
+        let version = |version| GetKvQuery {
+            version: Some(version),
+            ..Default::default()
+        };
+        assert_eq!(get(&db, "k", &version(1)).await.unwrap().value, "old");
+        assert_eq!(get(&db, "k", &version(2)).await.unwrap().value, "new");
+    }
+}
@@ -1,22 +1,28 @@
+use crate::config::TelemetryConfig;
 use opentelemetry::global;
 use opentelemetry::trace::TracerProvider;
 use opentelemetry_otlp::{Protocol, WithExportConfig};
 use opentelemetry_sdk::Resource;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
 use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::{Layer, Registry};
+use tracing_subscriber::{Layer, Registry, reload};
 
-// 初始化 Tracing和OpenTelemetry
-pub async fn init_tracing()
--> Result<(SdkTracerProvider, SdkMeterProvider), Box<dyn std::error::Error>> {
+/// 修改终端日志级别，重新加载配置时使用
+pub type LogLevelHandle = reload::Handle<LevelFilter, Registry>;
+
+// 初始化 Tracing和OpenTelemetry，导出地址、服务名和终端日志级别来自配置
+pub async fn init_tracing(
+    config: &TelemetryConfig,
+) -> Result<(SdkTracerProvider, SdkMeterProvider, LogLevelHandle), Box<dyn std::error::Error>> {
     // 配置 tracer的 OTLP 导出器
     // Initialize OTLP exporter using gRPC (Tonic)
     let otlp_exporter = opentelemetry_otlp::SpanExporter::builder()
         // .with_tonic()
         // .build()?;
         .with_tonic()
-        .with_endpoint("http://localhost:4317")
+        .with_endpoint(&config.traces_endpoint)
         .build()?;
 
     // Create a tracer provider with the exporter
@@ -29,7 +35,7 @@ pub async fn init_tracing()
         .with_batch_exporter(otlp_exporter)
         .with_resource(
             Resource::builder()
-                .with_service_name("hyper-tower-service")
+                .with_service_name(config.service_name.clone())
                 .build(),
         )
         .build();
@@ -45,7 +51,7 @@ pub async fn init_tracing()
     //     ))
     //     .build();
 
-    let provider_tracer = tracer_provider.tracer("hyper-tower-service");
+    let provider_tracer = tracer_provider.tracer(config.service_name.clone());
 
     // 设置全局 TracerProvider（追踪）
     // opentelemetry::global::set_meter_provider(tracer_provider);
@@ -62,7 +68,7 @@ pub async fn init_tracing()
     let otlp_metrics_exporter = opentelemetry_otlp::MetricExporter::builder()
         .with_http()
         .with_protocol(Protocol::HttpBinary)
-        .with_endpoint("http://localhost:19090/api/v1/otlp/v1/metrics")
+        .with_endpoint(&config.metrics_endpoint)
         .build()?;
 
     // 使用OTLP Metrics 导出器创建 Meter Provider
@@ -70,7 +76,7 @@ pub async fn init_tracing()
         .with_periodic_exporter(otlp_metrics_exporter)
         .with_resource(
             Resource::builder()
-                .with_service_name("hyper-tower-service")
+                .with_service_name(config.service_name.clone())
                 .build(),
         )
         .build();
@@ -83,8 +89,9 @@ pub async fn init_tracing()
     // 配置 tracing 订阅者
     let telemetry_layer = tracing_opentelemetry::layer().with_tracer(provider_tracer);
     // 配置终端输出
-    let fmt_layer =
-        tracing_subscriber::fmt::layer().with_filter(tracing_subscriber::filter::LevelFilter::INFO);
+    // 日志级别可以在运行时通过 handle 修改
+    let (level_filter, log_level) = reload::Layer::new(config.log_level()?);
+    let fmt_layer = tracing_subscriber::fmt::layer().with_filter(level_filter);
     // let fmt_layer = tracing_subscriber::fmt().with_max_level(tracing::Level::INFO);
     // .with_span_events(tracing_subscriber::fmt::format::FmtSpan::FULL)
     // .finish();
@@ -108,7 +115,7 @@ pub async fn init_tracing()
         exporter_backend = "opentelemetry metrics export",
         exporter_protocol = "otlp",
         exporter_destination = "prometheus",
-        exporter_endpoint = "http://localhost:19090/api/v1/otlp/v1/metrics",
+        exporter_endpoint = %config.metrics_endpoint,
         "OTLP metrics exporter initialized and connected to Prometheus http endpoint"
     );
 
@@ -118,7 +125,7 @@ pub async fn init_tracing()
         exporter_backend = "opentelemetry tracing export",
         exporter_protocol = "otlp",
         exporter_destination = "jaeger",
-        exporter_endpoint = "http://localhost:4317",
+        exporter_endpoint = %config.traces_endpoint,
         "OTLP exporter initialized and connected to Jaeger grpc endpoint"
     );
 
@@ -127,5 +134,5 @@ pub async fn init_tracing()
     //     "Initializing OTLP exporter and connecting to Jaeger endpoint at http://localhost:4317"
     // );
 
-    Ok((tracer_provider, meter_provider))
+    Ok((tracer_provider, meter_provider, log_level))
 }
@@ -0,0 +1,248 @@
+//! JSON Schema 校验，只实现常用的关键字。
+//!
+//! 支持 `type`、`enum`、`const`、`properties`、`required`、`additionalProperties`、
+//! `items`、`minItems`/`maxItems`、`minLength`/`maxLength`、`minimum`/`maximum`，
+//! 不认识的关键字按规范忽略。`$ref`、`pattern`、`allOf`/`anyOf` 等不支持。
+use serde_json::{Map, Value};
+
+/// 检查 schema 本身是否合法：必须是对象或布尔值，已支持的关键字类型要正确
+pub fn check_schema(schema: &Value) -> Result<(), String> {
+    let schema = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(schema) => schema,
+        _ => return Err("schema must be an object or a boolean".into()),
+    };
+    if let Some(types) = schema.get("type") {
+        let valid = match types {
+            Value::String(name) => is_type_name(name),
+            Value::Array(names) => names
+                .iter()
+                .all(|name| name.as_str().is_some_and(is_type_name)),
+            _ => false,
+        };
+        if !valid {
+            return Err(format!("invalid type {}", types));
+        }
+    }
+    if let Some(properties) = schema.get("properties") {
+        let properties = properties
+            .as_object()
+            .ok_or("properties must be an object")?;
+        for property in properties.values() {
+            check_schema(property)?;
+        }
+    }
+    if let Some(required) = schema.get("required")
+        && !required
+            .as_array()
+            .is_some_and(|names| names.iter().all(Value::is_string))
+    {
+        return Err("required must be an array of strings".into());
+    }
+    for keyword in ["additionalProperties", "items"] {
+        if let Some(sub) = schema.get(keyword) {
+            check_schema(sub)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_type_name(name: &str) -> bool {
+    matches!(
+        name,
+        "null" | "boolean" | "object" | "array" | "number" | "integer" | "string"
+    )
+}
+
+/// 用 `schema` 校验 `value`，不通过时返回第一个错误，带出错位置的 JSON Pointer
+pub fn validate(schema: &Value, value: &Value) -> Result<(), String> {
+    validate_at(schema, value, "")
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(format!("{}: no value is allowed", at(path))),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(types) = schema.get("type") {
+        let matches = match types {
+            Value::String(name) => has_type(value, name),
+            Value::Array(names) => names
+                .iter()
+                .any(|name| name.as_str().is_some_and(|name| has_type(value, name))),
+            _ => true,
+        };
+        if !matches {
+            return Err(format!("{}: expected type {}", at(path), types));
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum")
+        && !allowed.contains(value)
+    {
+        return Err(format!(
+            "{}: value is not one of {}",
+            at(path),
+            Value::Array(allowed.clone())
+        ));
+    }
+    if let Some(expected) = schema.get("const")
+        && expected != value
+    {
+        return Err(format!("{}: expected {}", at(path), expected));
+    }
+
+    match value {
+        Value::Object(object) => validate_object(schema, object, path),
+        Value::Array(items) => {
+            check_len(schema, "minItems", "maxItems", items.len(), "items", path)?;
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{}/{}", path, i))?;
+                }
+            }
+            Ok(())
+        }
+        Value::String(s) => check_len(
+            schema,
+            "minLength",
+            "maxLength",
+            s.chars().count(),
+            "characters",
+            path,
+        ),
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64)
+                && n < minimum
+            {
+                return Err(format!("{}: must be at least {}", at(path), minimum));
+            }
+            if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64)
+                && n > maximum
+            {
+                return Err(format!("{}: must be at most {}", at(path), maximum));
+            }
+            Ok(())
+        }
+        Value::Null | Value::Bool(_) => Ok(()),
+    }
+}
+
+fn validate_object(
+    schema: &Map<String, Value>,
+    object: &Map<String, Value>,
+    path: &str,
+) -> Result<(), String> {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                return Err(format!("{}: missing required property {}", at(path), name));
+            }
+        }
+    }
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for (name, value) in object {
+        let path = format!("{}/{}", path, escape(name));
+        match properties.and_then(|properties| properties.get(name)) {
+            Some(property) => validate_at(property, value, &path)?,
+            None => {
+                if let Some(additional) = schema.get("additionalProperties") {
+                    validate_at(additional, value, &path)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_len(
+    schema: &Map<String, Value>,
+    min: &str,
+    max: &str,
+    len: usize,
+    unit: &str,
+    path: &str,
+) -> Result<(), String> {
+    if let Some(min) = schema.get(min).and_then(Value::as_u64)
+        && (len as u64) < min
+    {
+        return Err(format!("{}: must have at least {} {}", at(path), min, unit));
+    }
+    if let Some(max) = schema.get(max).and_then(Value::as_u64)
+        && (len as u64) > max
+    {
+        return Err(format!("{}: must have at most {} {}", at(path), max, unit));
+    }
+    Ok(())
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "number" => value.is_number(),
+        // 1.0 这样没有小数部分的数同样算整数
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        "string" => value.is_string(),
+        _ => false,
+    }
+}
+
+/// 错误信息里的位置，根节点写作 `/`
+fn at(path: &str) -> &str {
+    if path.is_empty() { "/" } else { path }
+}
+
+/// JSON Pointer 的转义规则：`~` 写作 `~0`，`/` 写作 `~1`
+fn escape(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn validates_nested_objects_and_reports_the_path() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name"],
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}, "maxItems": 2},
+                "age": {"type": "integer", "minimum": 0}
+            },
+            "additionalProperties": false
+        });
+        check_schema(&schema).unwrap();
+
+        validate(&schema, &json!({"name": "x", "tags": ["a"], "age": 3})).unwrap();
+        let errors = [
+            (json!("x"), "/: expected type"),
+            (json!({}), "/: missing required property name"),
+            (json!({"name": ""}), "/name: must have at least 1"),
+            (
+                json!({"name": "x", "tags": ["c"]}),
+                "/tags/0: value is not one of",
+            ),
+            (json!({"name": "x", "age": 1.5}), "/age: expected type"),
+            (json!({"name": "x", "a/b": 1}), "/a~1b: no value is allowed"),
+        ];
+        for (value, error) in errors {
+            let e = validate(&schema, &value).unwrap_err();
+            assert!(e.starts_with(error), "{} => {}", value, e);
+        }
+
+        assert!(check_schema(&json!("object")).is_err());
+        assert!(check_schema(&json!({"type": "thing"})).is_err());
+        assert!(check_schema(&json!({"properties": {"a": 1}})).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! 原子计数器和 compare-and-swap，`kv_axum` 和 `kv_tower` 共用。
+//!
+//! 读取、比较和写入都在存储层的一次操作里完成，不会像先 `GET` 再 `PUT` 那样丢失并发更新。
+//! 写入成功后和其他写路径一样只失效缓存，由读路径回填。
+use crate::{
+    cache::KvCache,
+    cache_aside::CacheAside,
+    error::AppError,
+    kv_value::{self, ValueLimits, validate_key},
+    models::{CasKv, CreateKv, KvPair},
+    namespace::NamespaceQuota,
+    store::KvStore,
+};
+use tracing::{info, instrument};
+
+/// 按整数自增，key 不存在时从 0 开始
+///
+/// incr 和 cas 都可能新建 key，需要和新建接口同样的校验
+#[instrument(skip(db, cache, cache_aside), target = "service::kv")]
+pub async fn incr(
+    db: &dyn KvStore,
+    cache: &dyn KvCache,
+    cache_aside: &CacheAside,
+    quota: &NamespaceQuota,
+    key: &str,
+    delta: i64,
+) -> Result<KvPair, AppError> {
+    validate_key(key)?;
+    // 新值的长度按 delta 估算，只在新建 key 时才可能超出配额
+    let kv = quota
+        .write(db, &[(key, delta.to_string().len())], db.incr(key, delta))
+        .await?;
+    info!(target: "service::kv", value = %kv.value, "🔢 incr successful");
+
+    cache_aside.invalidate(db, cache, key).await;
+    Ok(kv)
+}
+
+/// 当前值等于 `expected` 时写入新值，返回值的第二项表示是否新建
+///
+/// `expected` 为空表示期望 key 不存在，key 已存在时同样返回 [`AppError::Conflict`]。
+#[instrument(skip(db, cache, cache_aside), target = "service::kv")]
+pub async fn compare_and_swap(
+    db: &dyn KvStore,
+    cache: &dyn KvCache,
+    cache_aside: &CacheAside,
+    quota: &NamespaceQuota,
+    limits: &ValueLimits,
+    key: &str,
+    input: CasKv,
+) -> Result<(KvPair, bool), AppError> {
+    validate_key(key)?;
+    kv_value::check(db, limits, &input.value, None).await?;
+    let size = kv_value::size(&input.value, None);
+    let write = async {
+        match input.expected {
+            Some(expected) => Ok((
+                db.compare_and_swap(key, &expected, &input.value).await?,
+                false,
+            )),
+            None => {
+                let input = CreateKv {
+                    key: key.to_string(),
+                    value: input.value,
+                    ..Default::default()
+                };
+                Ok((db.set(input).await?, true))
+            }
+        }
+    };
+    let (kv, created) = quota.write(db, &[(key, size)], write).await?;
+    info!(target: "service::kv", created, "🔁 cas successful");
+
+    cache_aside.invalidate(db, cache, key).await;
+    Ok((kv, created))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::MemoryCache, cache_aside::CacheAsideOptions, store::MemoryStore};
+    use std::sync::Arc;
+
+    /// 一个实例用到的存储、缓存和 cache-aside，配额和值大小不限制
+    #[derive(Clone)]
+    struct Instance {
+        db: Arc<MemoryStore>,
+        cache: Arc<MemoryCache>,
+        cache_aside: Arc<CacheAside>,
+    }
+
+    impl Instance {
+        fn new() -> Self {
+            Self {
+                db: Arc::new(MemoryStore::new()),
+                cache: Arc::new(MemoryCache::new()),
+                cache_aside: Arc::new(CacheAside::new(CacheAsideOptions::default())),
+            }
+        }
+
+        async fn incr(&self, key: &str, delta: i64) -> Result<KvPair, AppError> {
+            let quota = NamespaceQuota::default();
+            incr(
+                self.db.as_ref(),
+                self.cache.as_ref(),
+                &self.cache_aside,
+                &quota,
+                key,
+                delta,
+            )
+            .await
+        }
+
+        async fn cas(&self, key: &str, input: CasKv) -> Result<(KvPair, bool), AppError> {
+            let (quota, limits) = (NamespaceQuota::default(), ValueLimits::default());
+            compare_and_swap(
+                self.db.as_ref(),
+                self.cache.as_ref(),
+                &self.cache_aside,
+                &quota,
+                &limits,
+                key,
+                input,
+            )
+            .await
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_incrs_do_not_lose_updates() {
+        let instance = Instance::new();
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let instance = instance.clone();
+                tokio::spawn(async move { instance.incr("hits", 2).await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        let kv = instance
+            .cache_aside
+            .get(instance.db.as_ref(), instance.cache.as_ref(), "hits")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((kv.value.as_str(), kv.version), (Some("40"), 20));
+    }
+
+    #[tokio::test]
+    async fn cas_creates_missing_key_once_and_hands_over_once() {
+        let instance = Instance::new();
+        // 期望 key 不存在时只有第一个请求能拿到 leader
+        let acquire = |value: &str| CasKv {
+            expected: None,
+            value: value.into(),
+        };
+        let (_, created) = instance.cas("leader", acquire("a")).await.unwrap();
+        assert!(created);
+        assert!(matches!(
+            instance.cas("leader", acquire("b")).await,
+            Err(AppError::Conflict(_))
+        ));
+
+        let hand_over = CasKv {
+            expected: Some("a".into()),
+            value: "b".into(),
+        };
+        let (kv, created) = instance.cas("leader", hand_over.clone()).await.unwrap();
+        assert_eq!((kv.value.as_str(), created), (Some("b"), false));
+        assert!(matches!(
+            instance.cas("leader", hand_over).await,
+            Err(AppError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn incr_rejects_non_integer_values() {
+        let instance = Instance::new();
+        let acquire = CasKv {
+            expected: None,
+            value: "a".into(),
+        };
+        instance.cas("leader", acquire).await.unwrap();
+        assert!(matches!(
+            instance.incr("leader", 1).await,
+            Err(AppError::Conflict(_))
+        ));
+    }
+}
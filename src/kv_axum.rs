@@ -1,23 +1,98 @@
 use crate::{
-    cache::CacheClient,
-    db::DBClient,
+    cache::KvCache,
+    cache_aside::CacheAside,
     error::AppError,
-    models::{CreateKv, KvPair},
+    etag::{self, Preconditions},
+    history, kv_atomic, kv_batch, kv_bulk, kv_json,
+    kv_value::{self, ValueLimits},
+    kv_watch::{ChangeFeed, Watcher},
+    models::{
+        BatchKeys, BatchResult, BatchSetKv, CasKv, CreateKv, ExportQuery, GetKvQuery, HistoryQuery,
+        ImportQuery, ImportReport, IncrKv, KvEvent, KvHistoryEntry, KvPage, KvPair, ListKvQuery,
+        NamespaceUsage, RestoreKv, UpdateKvQuery, WatchQuery,
+    },
+    namespace::{self, DEFAULT_NAMESPACE, NamespaceQuota},
+    store::{self, KvStore},
 };
 use axum::{
     Json, Router,
-    extract::{Path, State},
-    http::StatusCode,
+    body::Body,
+    extract::{
+        DefaultBodyLimit, FromRequestParts, Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, HeaderName, StatusCode, header, request::Parts},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
     routing::{get, post},
 };
-use std::sync::Arc;
+use bytes::Bytes;
+use futures::Stream;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc};
 use tracing::instrument;
 
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct AppState {
-    pub db: Arc<DBClient>,
-    pub cache: Arc<CacheClient>,
+    pub db: Arc<dyn KvStore>,
+    pub cache: Arc<dyn KvCache>,
+    pub cache_aside: Arc<CacheAside>,
+    pub quota: NamespaceQuota,
+    pub limits: ValueLimits,
+    pub feed: Arc<ChangeFeed>,
+}
+
+/// 路径里的 `{namespace}`，不带 `/ns/{namespace}` 前缀的路由使用 [`DEFAULT_NAMESPACE`]
+pub struct Namespace(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for Namespace {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let params = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| AppError::InvalidInput(e.body_text()))?;
+        let namespace = params
+            .0
+            .get("namespace")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string());
+        namespace::validate(&namespace)?;
+        Ok(Self(namespace))
+    }
+}
+
+/// 路径里的 `{key}`，按名字取，带不带 namespace 前缀的路由都能用
+#[derive(Deserialize)]
+pub struct KeyPath {
+    key: String,
+}
+
+/// axum 默认的请求体大小限制
+const AXUM_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// 带 `ETag` 响应头的 JSON 响应
+type WithEtag<T> = ([(HeaderName, String); 1], Json<T>);
+
+fn with_etag(kv: KvPair) -> WithEtag<KvPair> {
+    ([(header::ETAG, etag::etag(&kv))], Json(kv))
+}
+
+/// `GET /kv/{key}` 的响应，二进制值按上传时的 Content-Type 原样返回
+fn kv_response(kv: KvPair) -> Result<Response, AppError> {
+    let Some(content_type) = kv.content_type.clone() else {
+        return Ok(with_etag(kv).into_response());
+    };
+    let data = kv_value::decode(&kv.value)?;
+    let headers = [
+        (header::CONTENT_TYPE, content_type),
+        (header::ETAG, etag::etag(&kv)),
+    ];
+    Ok((headers, data).into_response())
 }
 
 #[utoipa::path(
@@ -25,9 +100,12 @@ pub struct AppState {
     path = "/kv",
     request_body = CreateKv,
     responses(
-        (status = 201, description = "Key-value pair created", body = KvPair),
+        (status = 201, description = "Key-value pair created", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
         (status = 400, description = "Invalid input"),
-        (status = 409, description = "Key already exists")
+        (status = 403, description = "Namespace key quota exceeded"),
+        (status = 409, description = "Key already exists"),
+        (status = 413, description = "Namespace size quota exceeded")
     )
 )]
 #[instrument(
@@ -37,145 +115,886 @@ pub struct AppState {
 )]
 pub async fn set_kv(
     State(state): State<AppState>,
+    Namespace(namespace): Namespace,
     Json(payload): Json<CreateKv>,
-) -> Result<(StatusCode, Json<KvPair>), AppError> {
+) -> Result<(StatusCode, WithEtag<KvPair>), AppError> {
     tracing::info!(target: "service::kv", %payload, "📥 incoming set key-value request");
-    if payload.key.is_empty()
-        || payload.key.len() > 50
-        || !payload.key.chars().all(|c| c.is_alphanumeric() || c == '_')
-    {
-        return Err(AppError::InvalidInput("Invalid key".into()));
-    }
-    if payload.value.is_empty() || payload.value.len() > 1000 {
-        return Err(AppError::InvalidInput("Invalid value".into()));
-    }
+    let db = state.db.scoped(&namespace);
+    kv_value::validate_key(&payload.key)?;
+    let content_type = payload.content_type.as_deref();
+    kv_value::check(db.as_ref(), &state.limits, &payload.value, content_type).await?;
 
     // set to db
+    let size = kv_value::size(&payload.value, content_type);
     tracing::info!(target: "service::kv", %payload, "✏️ update db");
-    let kv = state.db.set(payload.clone()).await?;
+    let kv = state
+        .quota
+        .write(
+            db.as_ref(),
+            &[(&payload.key, size)],
+            db.set(payload.clone()),
+        )
+        .await?;
 
-    // update cache
-    tracing::info!(target: "service::kv", %payload, "✏️ update cache");
-    state.cache.set(&format!("kv:{}", kv.key), &kv, 300).await?;
+    // 写路径只失效缓存，由读路径回填
+    tracing::info!(target: "service::kv", %payload, "🗑️ invalidate cache");
+    state
+        .cache_aside
+        .invalidate(db.as_ref(), state.cache.as_ref(), &kv.key)
+        .await;
     tracing::info!(target: "service::kv", %payload, "📦 set key-value successful");
-    Ok((StatusCode::CREATED, Json(kv)))
+    Ok((StatusCode::CREATED, with_etag(kv)))
 }
 
 #[utoipa::path(
     put,
     path = "/kv/{key}",
     params(
-        ("key", Path, description = "Key to update")
+        ("key", Path, description = "Key to update"),
+        UpdateKvQuery,
+        ("If-Match" = Option<String>, Header, description = "Only update if the current ETag matches"),
+        ("If-None-Match" = Option<String>, Header, description = "Only update if the current ETag does not match")
     ),
     request_body(
-        content = String,
-        description = "New value for the key as a JSON string",
-        content_type = "application/json",
-        example = json!("new_value_of_the_key")
+        content(
+            (Object = "application/json", example = json!("new_value_of_the_key")),
+            (Vec<u8> = "application/octet-stream")
+        ),
+        description = "New value for the key. A JSON body (a JSON string or any other JSON value) \
+            is stored as JSON, any other Content-Type stores the raw body as a binary value"
     ),
     responses(
-        (status = 200, description = "Key-value pair updated", body = KvPair),
+        (status = 200, description = "Key-value pair updated", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
+        (status = 201, description = "Key-value pair created (upsert only)", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
         (status = 400, description = "Invalid input"),
-        (status = 404, description = "Key not found")
+        (status = 403, description = "Namespace key quota exceeded"),
+        (status = 404, description = "Key not found"),
+        (status = 412, description = "Precondition failed"),
+        (status = 413, description = "Namespace size quota exceeded")
     )
 )]
-#[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
+#[instrument(skip(state, body), fields(layer = "kv_axum"), target = "service::kv")]
 pub async fn update_kv(
     State(state): State<AppState>,
-    Path(key): Path<String>,
-    Json(value): Json<String>,
-) -> Result<Json<KvPair>, AppError> {
-    // TODO: 这里更新会造成缓存脏读的问题，需要后续优化
-    tracing::info!(target: "service::kv", %key, %value, "📥 incoming update request");
-    if value.is_empty() || value.len() > 1000 {
-        return Err(AppError::InvalidInput("Invalid value".into()));
+    Namespace(namespace): Namespace,
+    Path(KeyPath { key }): Path<KeyPath>,
+    Query(query): Query<UpdateKvQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, WithEtag<KvPair>), AppError> {
+    // 不用 Json 提取器，Content-Type 不是 JSON 时请求体作为二进制值保存
+    let request_content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let (value, content_type) = kv_value::from_body(request_content_type, &body)?;
+    let content_type = content_type.as_deref();
+    tracing::info!(target: "service::kv", %key, ?content_type, size = body.len(), "📥 incoming update request");
+    let db = state.db.scoped(&namespace);
+    // upsert 可能新建 key，key 需要和新建接口同样的校验
+    if query.upsert {
+        kv_value::validate_key(&key)?;
     }
+    kv_value::check(db.as_ref(), &state.limits, &value, content_type).await?;
 
     // update db
-    tracing::info!(target: "service::kv", %key, %value, "✏️ update db");
-    let kv = state.db.update(&key, &value).await?;
-    // update cache
-    tracing::info!(target: "service::kv", %key, %value, "✏️ update cache");
-    state.cache.set(&format!("kv:{}", key), &kv, 300).await?;
-    tracing::info!(target: "service::kv", %key, %value, "📦 update successful");
-    Ok(Json(kv))
+    tracing::info!(target: "service::kv", %key, "✏️ update db");
+    let expiry = store::resolve_update_expiry(&query)?;
+    let preconditions = Preconditions::from_headers(&headers);
+    let write = etag::update(
+        db.as_ref(),
+        &key,
+        &value,
+        content_type,
+        expiry,
+        &preconditions,
+        query.upsert,
+    );
+    let size = kv_value::size(&value, content_type);
+    let (kv, created) = state
+        .quota
+        .write(db.as_ref(), &[(&key, size)], write)
+        .await?;
+    // 先写数据库再失效缓存，不直接写缓存，避免并发写入时缓存脏读
+    tracing::info!(target: "service::kv", %key, "🗑️ invalidate cache");
+    state
+        .cache_aside
+        .invalidate(db.as_ref(), state.cache.as_ref(), &key)
+        .await;
+    tracing::info!(target: "service::kv", %key, created, "📦 update successful");
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, with_etag(kv)))
 }
 
 #[utoipa::path(
     get,
     path = "/kv/{key}",
     params(
-        ("key", Path, description = "Key to retrieve")
+        ("key", Path, description = "Key to retrieve"),
+        GetKvQuery
     ),
     responses(
-        (status = 200, description = "Key-value pair found", body = KvPair),
+        (status = 200, description = "Key-value pair found. Binary values are returned verbatim \
+            with the Content-Type they were uploaded with", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
+        (status = 400, description = "Invalid input"),
         (status = 404, description = "Key not found")
     )
 )]
 #[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
 pub async fn get_kv(
     State(state): State<AppState>,
-    Path(key): Path<String>,
-) -> Result<Json<KvPair>, AppError> {
+    Namespace(namespace): Namespace,
+    Path(KeyPath { key }): Path<KeyPath>,
+    Query(query): Query<GetKvQuery>,
+) -> Result<Response, AppError> {
     tracing::info!(target: "service::kv", %key, "📥 incoming get request");
+    let db = state.db.scoped(&namespace);
 
-    let cache_key = format!("kv:{}", key);
-    if let Some(kv) = state.cache.get::<KvPair>(&cache_key).await? {
-        tracing::info!(target: "service::kv", %key, "✅ cache hit");
-
-        return Ok(Json(kv));
+    // 历史值直接查历史表，不经过缓存
+    if query.is_history() {
+        let kv = history::get(db.as_ref(), &key, &query).await?;
+        let kv = kv_json::select(kv, query.path.as_deref().unwrap_or_default())?;
+        tracing::info!(target: "service::kv", %key, version = kv.version, "📦 get history successful");
+        return kv_response(kv);
     }
-    tracing::info!(target: "service::kv", %key, "⚠️ cache miss");
 
-    let kv = state.db.get(&key).await?.ok_or_else(|| {
-        tracing::warn!(target: "service::kv", %key, "⚠️  key not found in db");
-        AppError::NotFound(format!("Key {} not found", key))
-    })?;
+    let kv = state
+        .cache_aside
+        .get(db.as_ref(), state.cache.as_ref(), &key)
+        .await?
+        .ok_or_else(|| {
+            tracing::warn!(target: "service::kv", %key, "⚠️  key not found");
+            AppError::NotFound(format!("Key {} not found", key))
+        })?;
+    let kv = kv_json::select(kv, query.path.as_deref().unwrap_or_default())?;
+    tracing::info!(target: "service::kv", %key, "📦 get successful");
 
-    tracing::info!(target: "service::kv", %key, "✏️ update cache");
-    state.cache.set(&cache_key, &kv, 300).await?;
-    tracing::info!(target: "service::kv", %key, "📦 fetched from db");
+    kv_response(kv)
+}
 
-    Ok(Json(kv))
+#[utoipa::path(
+    patch,
+    path = "/kv/{key}",
+    params(
+        ("key", Path, description = "Key to patch"),
+        ("If-Match" = Option<String>, Header, description = "Only patch if the current ETag matches")
+    ),
+    request_body(
+        content = Object,
+        description = "JSON merge-patch (RFC 7396), null removes a field",
+        content_type = "application/merge-patch+json",
+        example = json!({"address": {"city": "new city", "zip": null}})
+    ),
+    responses(
+        (status = 200, description = "Key-value pair patched", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
+        (status = 400, description = "Invalid patch or the result does not match the schema"),
+        (status = 403, description = "Namespace key quota exceeded"),
+        (status = 404, description = "Key not found"),
+        (status = 409, description = "Key holds a binary value"),
+        (status = 412, description = "Precondition failed"),
+        (status = 413, description = "Namespace size quota exceeded")
+    )
+)]
+#[instrument(skip(state, body), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn patch_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Path(KeyPath { key }): Path<KeyPath>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<WithEtag<KvPair>, AppError> {
+    tracing::info!(target: "service::kv", %key, "📥 incoming patch request");
+    let db = state.db.scoped(&namespace);
+    // 不用 Json 提取器，merge-patch 的 Content-Type 是 application/merge-patch+json
+    let patch: Value = serde_json::from_slice(&body)
+        .map_err(|e| AppError::InvalidInput(format!("Invalid JSON: {}", e)))?;
+
+    let preconditions = Preconditions::from_headers(&headers);
+    let kv = kv_json::patch(
+        db.as_ref(),
+        &state.quota,
+        &state.limits,
+        &key,
+        &patch,
+        &preconditions,
+    )
+    .await?;
+
+    tracing::info!(target: "service::kv", %key, "🗑️ invalidate cache");
+    state
+        .cache_aside
+        .invalidate(db.as_ref(), state.cache.as_ref(), &key)
+        .await;
+    tracing::info!(target: "service::kv", %key, version = kv.version, "📦 patch successful");
+    Ok(with_etag(kv))
 }
 
 #[utoipa::path(
     delete,
     path = "/kv/{key}",
     params(
-        ("key", Path, description = "Key to delete")
+        ("key", Path, description = "Key to delete"),
+        ("If-Match" = Option<String>, Header, description = "Only delete if the current ETag matches"),
+        ("If-None-Match" = Option<String>, Header, description = "Only delete if the current ETag does not match")
     ),
     responses(
         (status = 204, description = "Key deleted"),
-        (status = 404, description = "Key not found")
+        (status = 404, description = "Key not found"),
+        (status = 412, description = "Precondition failed")
     )
 )]
 #[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
 pub async fn delete_kv(
     State(state): State<AppState>,
-    Path(key): Path<String>,
+    Namespace(namespace): Namespace,
+    Path(KeyPath { key }): Path<KeyPath>,
+    headers: HeaderMap,
 ) -> Result<StatusCode, AppError> {
     tracing::info!(target: "service::kv", %key, "📥 incoming delete request");
+    let db = state.db.scoped(&namespace);
 
     tracing::info!(target: "service::kv", %key, "🗑️ delete from db");
-    let deleted = state.db.delete(&key).await?;
+    let preconditions = Preconditions::from_headers(&headers);
+    let deleted = etag::delete(db.as_ref(), &key, &preconditions).await?;
 
     if !deleted {
         return Err(AppError::NotFound(format!("Key {} not found", key)));
     }
 
-    tracing::info!(target: "service::kv", %key, "🗑️ delete from cache");
-    state.cache.delete(&format!("kv:{}", key)).await?;
+    tracing::info!(target: "service::kv", %key, "🗑️ invalidate cache");
+    state
+        .cache_aside
+        .invalidate(db.as_ref(), state.cache.as_ref(), &key)
+        .await;
 
     tracing::info!(target: "service::kv", %key, "📦 delete successful");
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[utoipa::path(
+    get,
+    path = "/kv/{key}/history",
+    params(
+        ("key", Path, description = "Key to show the history of"),
+        HistoryQuery
+    ),
+    responses(
+        (status = 200, description = "Writes to the key, newest first", body = [KvHistoryEntry]),
+        (status = 400, description = "Invalid input")
+    )
+)]
+#[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn kv_history(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Path(KeyPath { key }): Path<KeyPath>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<KvHistoryEntry>>, AppError> {
+    tracing::info!(target: "service::kv", %key, "📥 incoming history request");
+    let db = state.db.scoped(&namespace);
+
+    let entries = history::list(db.as_ref(), &key, &query).await?;
+
+    tracing::info!(target: "service::kv", %key, count = entries.len(), "📦 history successful");
+    Ok(Json(entries))
+}
+
+#[utoipa::path(
+    post,
+    path = "/kv/{key}/restore",
+    params(
+        ("key", Path, description = "Key to restore"),
+        ("If-Match" = Option<String>, Header, description = "Only restore if the current ETag matches")
+    ),
+    request_body = RestoreKv,
+    responses(
+        (status = 200, description = "Key restored to the old value as a new version", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
+        (status = 201, description = "Deleted key recreated with the old value", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
+        (status = 403, description = "Namespace key quota exceeded"),
+        (status = 404, description = "Version not found"),
+        (status = 412, description = "Precondition failed"),
+        (status = 413, description = "Namespace size quota exceeded")
+    )
+)]
+#[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn restore_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Path(KeyPath { key }): Path<KeyPath>,
+    headers: HeaderMap,
+    Json(payload): Json<RestoreKv>,
+) -> Result<(StatusCode, WithEtag<KvPair>), AppError> {
+    tracing::info!(target: "service::kv", %key, version = payload.version, "📥 incoming restore request");
+    let db = state.db.scoped(&namespace);
+
+    let preconditions = Preconditions::from_headers(&headers);
+    let (kv, created) = history::restore(
+        db.as_ref(),
+        &state.quota,
+        &state.limits,
+        &key,
+        payload.version,
+        &preconditions,
+    )
+    .await?;
+
+    tracing::info!(target: "service::kv", %key, "🗑️ invalidate cache");
+    state
+        .cache_aside
+        .invalidate(db.as_ref(), state.cache.as_ref(), &key)
+        .await;
+    tracing::info!(target: "service::kv", %key, created, "📦 restore successful");
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, with_etag(kv)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/kv/{key}/incr",
+    params(
+        ("key", Path, description = "Counter key, created from 0 if missing")
+    ),
+    request_body = IncrKv,
+    responses(
+        (status = 200, description = "Counter incremented", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
+        (status = 400, description = "Invalid input"),
+        (status = 403, description = "Namespace key quota exceeded"),
+        (status = 409, description = "Value is not an integer or would overflow"),
+        (status = 413, description = "Namespace size quota exceeded")
+    )
+)]
+#[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn incr_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Path(KeyPath { key }): Path<KeyPath>,
+    Json(payload): Json<IncrKv>,
+) -> Result<WithEtag<KvPair>, AppError> {
+    tracing::info!(target: "service::kv", %key, delta = payload.delta, "📥 incoming incr request");
+    let db = state.db.scoped(&namespace);
+
+    let kv = kv_atomic::incr(
+        db.as_ref(),
+        state.cache.as_ref(),
+        &state.cache_aside,
+        &state.quota,
+        &key,
+        payload.delta,
+    )
+    .await?;
+
+    tracing::info!(target: "service::kv", %key, "📦 incr successful");
+    Ok(with_etag(kv))
+}
+
+#[utoipa::path(
+    post,
+    path = "/kv/{key}/cas",
+    params(
+        ("key", Path, description = "Key to compare and swap")
+    ),
+    request_body = CasKv,
+    responses(
+        (status = 200, description = "Value swapped", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
+        (status = 201, description = "Key created (expected is null)", body = KvPair,
+            headers(("ETag" = String, description = "Version of the key-value pair"))),
+        (status = 400, description = "Invalid input"),
+        (status = 403, description = "Namespace key quota exceeded"),
+        (status = 409, description = "Current value does not match expected"),
+        (status = 413, description = "Namespace size quota exceeded")
+    )
+)]
+#[instrument(
+    skip(state, payload),
+    fields(layer = "kv_axum"),
+    target = "service::kv"
+)]
+pub async fn cas_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Path(KeyPath { key }): Path<KeyPath>,
+    Json(payload): Json<CasKv>,
+) -> Result<(StatusCode, WithEtag<KvPair>), AppError> {
+    tracing::info!(target: "service::kv", %key, "📥 incoming cas request");
+    let db = state.db.scoped(&namespace);
+
+    let (kv, created) = kv_atomic::compare_and_swap(
+        db.as_ref(),
+        state.cache.as_ref(),
+        &state.cache_aside,
+        &state.quota,
+        &state.limits,
+        &key,
+        payload,
+    )
+    .await?;
+
+    tracing::info!(target: "service::kv", %key, created, "📦 cas successful");
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, with_etag(kv)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/kv",
+    params(ListKvQuery),
+    responses(
+        (status = 200, description = "Key-value pairs in key order", body = KvPage),
+        (status = 400, description = "Invalid input")
+    )
+)]
+#[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn list_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Query(query): Query<ListKvQuery>,
+) -> Result<Json<KvPage>, AppError> {
+    tracing::info!(target: "service::kv", ?query, "📥 incoming list request");
+    let db = state.db.scoped(&namespace);
+
+    let page = store::list_page(db.as_ref(), &query).await?;
+
+    tracing::info!(target: "service::kv", count = page.items.len(), "📦 list successful");
+    Ok(Json(page))
+}
+
+#[utoipa::path(
+    post,
+    path = "/kv/batch/get",
+    request_body = BatchKeys,
+    responses(
+        (status = 200, description = "Per-key results, 200 or 404 for each key", body = BatchResult),
+        (status = 400, description = "Invalid input")
+    )
+)]
+#[instrument(
+    skip(state, payload),
+    fields(layer = "kv_axum"),
+    target = "service::kv"
+)]
+pub async fn batch_get_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Json(payload): Json<BatchKeys>,
+) -> Result<Json<BatchResult>, AppError> {
+    tracing::info!(target: "service::kv", count = payload.keys.len(), "📥 incoming batch get request");
+    let db = state.db.scoped(&namespace);
+
+    let result = kv_batch::get_many(
+        db.as_ref(),
+        state.cache.as_ref(),
+        &state.cache_aside,
+        payload.keys,
+    )
+    .await?;
+
+    tracing::info!(target: "service::kv", "📦 batch get successful");
+    Ok(Json(result))
+}
+
+#[utoipa::path(
+    post,
+    path = "/kv/batch/set",
+    request_body = BatchSetKv,
+    responses(
+        (status = 200, description = "Per-key results, 201, 400 or 409 for each key", body = BatchResult),
+        (status = 400, description = "Invalid input"),
+        (status = 403, description = "Namespace key quota exceeded"),
+        (status = 413, description = "Namespace size quota exceeded")
+    )
+)]
+#[instrument(
+    skip(state, payload),
+    fields(layer = "kv_axum"),
+    target = "service::kv"
+)]
+pub async fn batch_set_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Json(payload): Json<BatchSetKv>,
+) -> Result<Json<BatchResult>, AppError> {
+    tracing::info!(target: "service::kv", count = payload.items.len(), "📥 incoming batch set request");
+    let db = state.db.scoped(&namespace);
+
+    let result = kv_batch::set_many(
+        db.as_ref(),
+        state.cache.as_ref(),
+        &state.cache_aside,
+        &state.quota,
+        &state.limits,
+        payload.items,
+    )
+    .await?;
+
+    tracing::info!(target: "service::kv", "📦 batch set successful");
+    Ok(Json(result))
+}
+
+#[utoipa::path(
+    post,
+    path = "/kv/batch/delete",
+    request_body = BatchKeys,
+    responses(
+        (status = 200, description = "Per-key results, 204 or 404 for each key", body = BatchResult),
+        (status = 400, description = "Invalid input")
+    )
+)]
+#[instrument(
+    skip(state, payload),
+    fields(layer = "kv_axum"),
+    target = "service::kv"
+)]
+pub async fn batch_delete_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Json(payload): Json<BatchKeys>,
+) -> Result<Json<BatchResult>, AppError> {
+    tracing::info!(target: "service::kv", count = payload.keys.len(), "📥 incoming batch delete request");
+    let db = state.db.scoped(&namespace);
+
+    let result = kv_batch::delete_many(
+        db.as_ref(),
+        state.cache.as_ref(),
+        &state.cache_aside,
+        payload.keys,
+    )
+    .await?;
+
+    tracing::info!(target: "service::kv", "📦 batch delete successful");
+    Ok(Json(result))
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{namespace}/schema",
+    params(
+        ("namespace", Path, description = "Namespace of the schema")
+    ),
+    responses(
+        (status = 200, description = "JSON Schema values in the namespace must match", body = Object),
+        (status = 404, description = "No schema registered")
+    )
+)]
+#[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn get_schema(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+) -> Result<Json<Value>, AppError> {
+    tracing::info!(target: "service::kv", %namespace, "📥 incoming get schema request");
+    let db = state.db.scoped(&namespace);
+
+    let schema = kv_json::get_schema(db.as_ref()).await?;
+
+    tracing::info!(target: "service::kv", %namespace, "📦 get schema successful");
+    Ok(Json(schema))
+}
+
+#[utoipa::path(
+    put,
+    path = "/ns/{namespace}/schema",
+    params(
+        ("namespace", Path, description = "Namespace to register the schema for")
+    ),
+    request_body(
+        content = Object,
+        description = "JSON Schema for new values, existing values are not checked",
+        content_type = "application/json",
+        example = json!({"type": "object", "required": ["name"], "properties": {"name": {"type": "string"}}})
+    ),
+    responses(
+        (status = 204, description = "Schema registered"),
+        (status = 400, description = "Invalid schema")
+    )
+)]
+#[instrument(skip(state, schema), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn put_schema(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Json(schema): Json<Value>,
+) -> Result<StatusCode, AppError> {
+    tracing::info!(target: "service::kv", %namespace, "📥 incoming put schema request");
+    let db = state.db.scoped(&namespace);
+
+    kv_json::set_schema(db.as_ref(), schema).await?;
+
+    tracing::info!(target: "service::kv", %namespace, "📦 put schema successful");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/ns/{namespace}/schema",
+    params(
+        ("namespace", Path, description = "Namespace to remove the schema from")
+    ),
+    responses(
+        (status = 204, description = "Schema removed"),
+        (status = 404, description = "No schema registered")
+    )
+)]
+#[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn delete_schema(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+) -> Result<StatusCode, AppError> {
+    tracing::info!(target: "service::kv", %namespace, "📥 incoming delete schema request");
+    let db = state.db.scoped(&namespace);
+
+    kv_json::delete_schema(db.as_ref()).await?;
+
+    tracing::info!(target: "service::kv", %namespace, "📦 delete schema successful");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{namespace}/usage",
+    params(
+        ("namespace", Path, description = "Namespace to report on")
+    ),
+    responses(
+        (status = 200, description = "Usage and quota of the namespace", body = NamespaceUsage),
+        (status = 400, description = "Invalid namespace")
+    )
+)]
+#[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn namespace_usage(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+) -> Result<Json<NamespaceUsage>, AppError> {
+    tracing::info!(target: "service::kv", %namespace, "📥 incoming usage request");
+    let db = state.db.scoped(&namespace);
+
+    let usage = state.quota.usage(db.as_ref()).await?;
+
+    tracing::info!(target: "service::kv", %namespace, usage.keys, usage.bytes, "📦 usage successful");
+    Ok(Json(usage))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Every key-value pair in key order, one JSON object per line",
+            content_type = "application/x-ndjson", body = KvPair)
+    )
+)]
+#[instrument(skip(state), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn export_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    tracing::info!(target: "service::kv", %namespace, ?query, "📥 incoming export request");
+    // 边读边写，出错时连接直接断开，客户端可以用收到的最后一个 key 作为 `after` 继续
+    let body = Body::from_stream(kv_bulk::export(state.db.scoped(&namespace), query.after));
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    params(ImportQuery),
+    request_body(
+        content = String,
+        content_type = "application/x-ndjson",
+        description = "One `CreateKv` per line, the output of `/admin/export` can be imported as is"
+    ),
+    responses(
+        (status = 200, description = "Import finished, invalid lines are listed in the report", body = ImportReport),
+        (status = 403, description = "Import stopped by the namespace key quota", body = ImportReport),
+        (status = 409, description = "Import stopped at an existing key (`fail` mode)", body = ImportReport),
+        (status = 413, description = "Import stopped by the namespace size quota", body = ImportReport)
+    )
+)]
+#[instrument(skip(state, body), fields(layer = "kv_axum"), target = "service::kv")]
+pub async fn import_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    Query(query): Query<ImportQuery>,
+    body: Body,
+) -> Result<(StatusCode, Json<ImportReport>), AppError> {
+    tracing::info!(target: "service::kv", %namespace, ?query, "📥 incoming import request");
+    let db = state.db.scoped(&namespace);
+    let report = kv_bulk::import(
+        db.as_ref(),
+        state.cache.as_ref(),
+        &state.cache_aside,
+        &state.quota,
+        &state.limits,
+        query.mode,
+        body.into_data_stream(),
+    )
+    .await?;
+
+    tracing::info!(target: "service::kv", imported = report.imported, skipped = report.skipped, failed = report.failed, "📦 import finished");
+    Ok((kv_bulk::status(&report), Json(report)))
+}
+
+/// SSE 断线重连时浏览器带上的最后一个事件 id
+const LAST_EVENT_ID: &str = "last-event-id";
+
+/// 订阅变更，查询参数没有 `after` 时用 `Last-Event-ID` 作为 resume token
+async fn watcher(
+    state: &AppState,
+    namespace: &str,
+    headers: &HeaderMap,
+    mut query: WatchQuery,
+) -> Result<Watcher, AppError> {
+    if query.after.is_none()
+        && let Some(last_event_id) = headers.get(LAST_EVENT_ID)
+    {
+        let after = last_event_id
+            .to_str()
+            .ok()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| AppError::InvalidInput("Invalid Last-Event-ID".into()))?;
+        query.after = Some(after);
+    }
+    state.feed.watch(state.db.scoped(namespace), &query).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/kv/watch",
+    params(
+        WatchQuery,
+        ("Last-Event-ID" = Option<i64>, Header, description = "Resume token used when `after` is not given")
+    ),
+    responses(
+        (status = 200, description = "Server-sent events, one per change. The event id is the resume token \
+            and the event type is the operation", content_type = "text/event-stream", body = KvEvent),
+        (status = 400, description = "Invalid input")
+    )
+)]
+#[instrument(
+    skip(state, headers),
+    fields(layer = "kv_axum"),
+    target = "service::kv"
+)]
+pub async fn watch_kv(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    headers: HeaderMap,
+    Query(query): Query<WatchQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+    tracing::info!(target: "service::kv", %namespace, ?query, "📥 incoming watch request");
+    let watcher = watcher(&state, &namespace, &headers, query).await?;
+    let events = futures::stream::unfold(watcher, |mut watcher| async move {
+        let event = watcher.next().await?;
+        let sse = Event::default()
+            .id(event.id.to_string())
+            .event(&event.operation)
+            .json_data(&event);
+        Some((sse, watcher))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/kv/watch/ws",
+    params(WatchQuery),
+    responses(
+        (status = 101, description = "WebSocket upgrade, then one JSON text message per change", body = KvEvent),
+        (status = 400, description = "Invalid input")
+    )
+)]
+#[instrument(
+    skip(state, headers, ws),
+    fields(layer = "kv_axum"),
+    target = "service::kv"
+)]
+pub async fn watch_kv_ws(
+    State(state): State<AppState>,
+    Namespace(namespace): Namespace,
+    headers: HeaderMap,
+    Query(query): Query<WatchQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    tracing::info!(target: "service::kv", %namespace, ?query, "📥 incoming websocket watch request");
+    // 升级之前订阅，参数错误时还能返回 400
+    let watcher = watcher(&state, &namespace, &headers, query).await?;
+    Ok(ws.on_upgrade(move |socket| forward(socket, watcher)))
+}
+
+/// 把变更逐个作为 JSON 文本消息发给客户端，客户端断开或者服务关闭时结束
+async fn forward(mut socket: WebSocket, mut watcher: Watcher) {
+    loop {
+        tokio::select! {
+            event = watcher.next() => {
+                let Some(text) = event.and_then(|event| serde_json::to_string(&event).ok()) else {
+                    break;
+                };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    return;
+                }
+            }
+            // 客户端发来的消息只用来发现连接关闭
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// key-value 路由，挂在根路径下访问默认 namespace，挂在 `/ns/{namespace}` 下访问其他 namespace
+fn kv_routes() -> Router<AppState> {
+    Router::new()
+        .route("/kv", post(set_kv).get(list_kv))
+        .route("/kv/watch", get(watch_kv))
+        .route("/kv/watch/ws", get(watch_kv_ws))
+        .route(
+            "/kv/{key}",
+            get(get_kv).delete(delete_kv).put(update_kv).patch(patch_kv),
+        )
+        .route("/kv/{key}/history", get(kv_history))
+        .route("/kv/{key}/restore", post(restore_kv))
+        .route("/kv/{key}/incr", post(incr_kv))
+        .route("/kv/{key}/cas", post(cas_kv))
+        .route("/kv/batch/get", post(batch_get_kv))
+        .route("/kv/batch/set", post(batch_set_kv))
+        .route("/kv/batch/delete", post(batch_delete_kv))
+        .route("/admin/export", get(export_kv))
+        .route("/admin/import", post(import_kv))
+}
+
 #[allow(dead_code)]
 pub fn router(state: AppState) -> Router {
     Router::new()
-        .route("/kv", post(set_kv))
-        .route("/kv/{key}", get(get_kv).delete(delete_kv).put(update_kv))
+        .merge(kv_routes())
+        .nest("/ns/{namespace}", kv_routes())
+        .route("/ns/{namespace}/usage", get(namespace_usage))
+        .route(
+            "/ns/{namespace}/schema",
+            get(get_schema).put(put_schema).delete(delete_schema),
+        )
+        // 二进制值的请求体可能超过 axum 默认的 2MB 限制
+        .layer(DefaultBodyLimit::max(
+            state.limits.max_binary_bytes.max(AXUM_BODY_LIMIT),
+        ))
         .with_state(state)
 }
 
@@ -0,0 +1,230 @@
+//! 批量读写，`kv_axum` 和 `kv_tower` 共用。
+//!
+//! 单个 key 的校验失败或不存在不影响其他 key，结果里逐个返回状态码；
+//! 数据库或其他服务错误时整个请求失败，批量写在一个事务里，不会只写入一部分。
+use crate::{
+    cache::KvCache,
+    cache_aside::CacheAside,
+    error::AppError,
+    kv_json,
+    kv_value::{self, ValueLimits},
+    models::{BatchItemResult, BatchResult, CreateKv, KvPair},
+    namespace::NamespaceQuota,
+    store::{self, KvStore},
+};
+use hyper::StatusCode;
+use serde_json::Value;
+use tracing::{info, instrument};
+
+/// 单次批量请求最多包含的 key 数量
+pub const BATCH_MAX_KEYS: usize = 100;
+
+fn check_batch_size(len: usize) -> Result<(), AppError> {
+    if len == 0 || len > BATCH_MAX_KEYS {
+        return Err(AppError::InvalidInput(format!(
+            "batch size must be between 1 and {}",
+            BATCH_MAX_KEYS
+        )));
+    }
+    Ok(())
+}
+
+/// 写入之前的校验，key 是否已存在除外，返回的错误作为单个 key 的结果
+pub fn validate(
+    input: &CreateKv,
+    limits: &ValueLimits,
+    schema: Option<&Value>,
+) -> Result<(), String> {
+    kv_value::validate_key(&input.key).map_err(|e| e.to_string())?;
+    let content_type = input.content_type.as_deref();
+    limits
+        .validate(&input.value, content_type)
+        .map_err(|e| e.to_string())?;
+    if content_type.is_none() {
+        kv_json::conform(schema, &input.value).map_err(|e| e.to_string())?;
+    }
+    store::resolve_expiry(input.ttl_seconds, input.expires_at).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn ok(key: String, status: StatusCode, item: Option<KvPair>) -> BatchItemResult {
+    BatchItemResult {
+        key,
+        status: status.as_u16(),
+        item,
+        error: None,
+    }
+}
+
+fn err(key: String, status: StatusCode, error: String) -> BatchItemResult {
+    BatchItemResult {
+        key,
+        status: status.as_u16(),
+        item: None,
+        error: Some(error),
+    }
+}
+
+#[instrument(skip(db, cache, cache_aside), target = "service::kv")]
+pub async fn get_many(
+    db: &dyn KvStore,
+    cache: &dyn KvCache,
+    cache_aside: &CacheAside,
+    keys: Vec<String>,
+) -> Result<BatchResult, AppError> {
+    check_batch_size(keys.len())?;
+
+    let items = cache_aside.get_many(db, cache, &keys).await?;
+    let results = keys
+        .into_iter()
+        .zip(items)
+        .map(|(key, kv)| match kv {
+            Some(kv) => ok(key, StatusCode::OK, Some(kv)),
+            None => {
+                let error = format!("Key {} not found", key);
+                err(key, StatusCode::NOT_FOUND, error)
+            }
+        })
+        .collect();
+    Ok(BatchResult { results })
+}
+
+#[instrument(skip(db, cache, cache_aside), target = "service::kv")]
+pub async fn set_many(
+    db: &dyn KvStore,
+    cache: &dyn KvCache,
+    cache_aside: &CacheAside,
+    quota: &NamespaceQuota,
+    limits: &ValueLimits,
+    items: Vec<CreateKv>,
+) -> Result<BatchResult, AppError> {
+    check_batch_size(items.len())?;
+
+    // 校验失败的 key 不写数据库
+    let schema = db.schema().await?;
+    let mut results: Vec<Option<BatchItemResult>> = Vec::with_capacity(items.len());
+    let mut valid = Vec::new();
+    for input in items {
+        match validate(&input, limits, schema.as_ref()) {
+            Ok(()) => {
+                results.push(None);
+                valid.push(input);
+            }
+            Err(e) => results.push(Some(err(input.key, StatusCode::BAD_REQUEST, e))),
+        }
+    }
+
+    // 配额按整个批次检查，超出时整个请求失败
+    let sizes: Vec<usize> = valid
+        .iter()
+        .map(|input| kv_value::size(&input.value, input.content_type.as_deref()))
+        .collect();
+    let keys: Vec<String> = valid.iter().map(|input| input.key.clone()).collect();
+    let writes: Vec<(&str, usize)> = keys.iter().map(String::as_str).zip(sizes).collect();
+
+    info!(target: "service::kv", count = valid.len(), "✏️ batch update db");
+    let write = async {
+        match valid.is_empty() {
+            true => Ok(Vec::new()),
+            false => db.set_many(valid).await,
+        }
+    };
+    let written = quota.write(db, &writes, write).await?;
+
+    let mut written = keys.into_iter().zip(written);
+    for slot in results.iter_mut().filter(|slot| slot.is_none()) {
+        let (key, kv) = written.next().expect("one result per valid item");
+        *slot = Some(match kv {
+            Some(kv) => {
+                cache_aside.invalidate(db, cache, &key).await;
+                ok(key, StatusCode::CREATED, Some(kv))
+            }
+            None => err(key, StatusCode::CONFLICT, "Key already exists".into()),
+        });
+    }
+    Ok(BatchResult {
+        results: results.into_iter().flatten().collect(),
+    })
+}
+
+#[instrument(skip(db, cache, cache_aside), target = "service::kv")]
+pub async fn delete_many(
+    db: &dyn KvStore,
+    cache: &dyn KvCache,
+    cache_aside: &CacheAside,
+    keys: Vec<String>,
+) -> Result<BatchResult, AppError> {
+    check_batch_size(keys.len())?;
+
+    info!(target: "service::kv", count = keys.len(), "🗑️ batch delete from db");
+    let deleted = db.delete_many(&keys).await?;
+
+    let mut results = Vec::with_capacity(keys.len());
+    for (key, deleted) in keys.into_iter().zip(deleted) {
+        if deleted {
+            cache_aside.invalidate(db, cache, &key).await;
+            results.push(ok(key, StatusCode::NO_CONTENT, None));
+        } else {
+            let error = format!("Key {} not found", key);
+            results.push(err(key, StatusCode::NOT_FOUND, error));
+        }
+    }
+    Ok(BatchResult { results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::MemoryCache, cache_aside::CacheAsideOptions, store::MemoryStore};
+
+    fn kv(key: &str, value: &str) -> CreateKv {
+        CreateKv {
+            key: key.to_string(),
+            value: value.into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_results_are_reported_per_key() {
+        let db = MemoryStore::new();
+        let cache = MemoryCache::new();
+        let cache_aside = CacheAside::new(CacheAsideOptions::default());
+        db.set(kv("taken", "v")).await.unwrap();
+
+        let result = set_many(
+            &db,
+            &cache,
+            &cache_aside,
+            &NamespaceQuota::default(),
+            &ValueLimits::default(),
+            vec![
+                kv("a", "1"),
+                kv("bad key", "1"),
+                kv("taken", "1"),
+                kv("b", "2"),
+            ],
+        )
+        .await
+        .unwrap();
+        let statuses: Vec<u16> = result.results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, [201, 400, 409, 201]);
+
+        let keys = vec!["a".to_string(), "nope".to_string()];
+        let result = delete_many(&db, &cache, &cache_aside, keys).await.unwrap();
+        let statuses: Vec<u16> = result.results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, [204, 404]);
+
+        let result = get_many(&db, &cache, &cache_aside, vec!["a".into(), "b".into()])
+            .await
+            .unwrap();
+        let statuses: Vec<u16> = result.results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, [404, 200]);
+
+        assert!(
+            get_many(&db, &cache, &cache_aside, Vec::new())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! 批量导入导出，格式为 NDJSON（每行一个 key-value），`kv_axum` 和 `kv_tower` 共用。
+//!
+//! 导出按 key 升序分页读取，读一页输出一页，不会把整个 namespace 读进内存；
+//! 分页之间发生的写入可能被看到，导出结果不是某一时刻的快照。导出的每一行都可以原样导入，
+//! 其中的 `updated_at` 和 `version` 会被忽略。
+//!
+//! 导入每 [`IMPORT_CHUNK_SIZE`] 行在一个事务里写入，块提交之后失效块内 key 的缓存。
+//! 格式或校验错误的行逐行报告并跳过，已存在的 key 按 [`ImportMode`] 处理；
+//! 超出配额、`fail` 模式遇到已存在的 key 或者数据库出错时停止导入，之前提交的块保留。
+use crate::{
+    cache::KvCache,
+    cache_aside::CacheAside,
+    error::AppError,
+    kv_batch,
+    kv_value::{self, ValueLimits},
+    models::{CreateKv, ImportAbort, ImportLineError, ImportMode, ImportReport},
+    namespace::NamespaceQuota,
+    store::KvStore,
+};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::StatusCode;
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+/// 导出时每页读取的 key 数量
+pub const EXPORT_PAGE_SIZE: u32 = 500;
+/// 导入时每个事务写入的行数
+pub const IMPORT_CHUNK_SIZE: usize = 100;
+/// 导入结果中最多列出的错误行数
+const IMPORT_MAX_ERRORS: usize = 1000;
+/// 一行中除 value 以外的部分允许的最大字节数
+const IMPORT_LINE_OVERHEAD: usize = 4096;
+
+/// 导出 `after` 之后的一页，返回 NDJSON 和下一页的 `after`，没有下一页时为 `None`
+#[instrument(skip(db), target = "service::kv")]
+pub async fn export_page(
+    db: &dyn KvStore,
+    after: Option<&str>,
+) -> Result<(Bytes, Option<String>), AppError> {
+    let items = db.list("", after, EXPORT_PAGE_SIZE).await?;
+    let mut body = Vec::new();
+    for kv in &items {
+        serde_json::to_writer(&mut body, kv)?;
+        body.push(b'\n');
+    }
+    let next = match items.len() == EXPORT_PAGE_SIZE as usize {
+        true => items.last().map(|kv| kv.key.clone()),
+        false => None,
+    };
+    Ok((body.into(), next))
+}
+
+/// 逐页导出 `after` 之后的所有 key
+pub fn export(
+    db: Arc<dyn KvStore>,
+    after: Option<String>,
+) -> impl Stream<Item = Result<Bytes, AppError>> {
+    // 状态为 None 表示已经导出完
+    futures::stream::try_unfold(Some(after), move |state| {
+        let db = db.clone();
+        async move {
+            let Some(after) = state else {
+                return Ok(None);
+            };
+            let (page, next) = export_page(db.as_ref(), after.as_deref()).await?;
+            Ok(Some((page, next.map(Some))))
+        }
+    })
+}
+
+/// 一次导入的状态，按行接收，攒满一块后写入
+struct Importer<'a> {
+    db: &'a dyn KvStore,
+    cache: &'a dyn KvCache,
+    cache_aside: &'a CacheAside,
+    quota: &'a NamespaceQuota,
+    limits: &'a ValueLimits,
+    mode: ImportMode,
+    schema: Option<Value>,
+    line: u64,
+    /// 还没写入的行和行号
+    chunk: Vec<(u64, CreateKv)>,
+    report: ImportReport,
+}
+
+impl Importer<'_> {
+    fn reject(&mut self, key: Option<String>, error: String) {
+        self.report.failed += 1;
+        if self.report.errors.len() < IMPORT_MAX_ERRORS {
+            self.report.errors.push(ImportLineError {
+                line: self.line,
+                key,
+                error,
+            });
+        }
+    }
+
+    fn abort(&mut self, line: Option<u64>, status: StatusCode, error: String) {
+        warn!(target: "service::kv", ?line, %error, "⚠️ import aborted");
+        self.report.aborted = Some(ImportAbort {
+            line,
+            status: status.as_u16(),
+            error,
+        });
+    }
+
+    /// 解析并校验一行，空行忽略；返回 false 表示导入已经停止
+    async fn push(&mut self, line: &[u8]) -> bool {
+        self.line += 1;
+        if line.trim_ascii().is_empty() {
+            return true;
+        }
+        let input: CreateKv = match serde_json::from_slice(line) {
+            Ok(input) => input,
+            Err(e) => {
+                self.reject(None, format!("Invalid JSON: {}", e));
+                return true;
+            }
+        };
+        if let Err(e) = kv_batch::validate(&input, self.limits, self.schema.as_ref()) {
+            self.reject(Some(input.key), e);
+            return true;
+        }
+        self.chunk.push((self.line, input));
+        if self.chunk.len() >= IMPORT_CHUNK_SIZE {
+            return self.flush().await;
+        }
+        true
+    }
+
+    /// 在一个事务里写入攒下的行，返回 false 表示导入已经停止
+    async fn flush(&mut self) -> bool {
+        if self.chunk.is_empty() {
+            return true;
+        }
+        let (lines, items): (Vec<u64>, Vec<CreateKv>) =
+            std::mem::take(&mut self.chunk).into_iter().unzip();
+
+        let sizes: Vec<usize> = items
+            .iter()
+            .map(|input| kv_value::size(&input.value, input.content_type.as_deref()))
+            .collect();
+        let keys: Vec<String> = items.iter().map(|input| input.key.clone()).collect();
+        let writes: Vec<(&str, usize)> = keys.iter().map(String::as_str).zip(sizes).collect();
+        let write = self.db.import_many(items, self.mode);
+        let written = match self.quota.write(self.db, &writes, write).await {
+            Ok(written) => written,
+            Err(e) => {
+                self.abort(lines.first().copied(), status_of(&e), e.to_string());
+                return false;
+            }
+        };
+        if self.mode == ImportMode::Fail && written.iter().any(Option::is_none) {
+            let conflict = written.len() - 1;
+            let error = format!("Key {} already exists", keys[conflict]);
+            self.abort(Some(lines[conflict]), StatusCode::CONFLICT, error);
+            return false;
+        }
+
+        // 块已经提交，失效缓存后读路径会读到导入的值
+        for (key, kv) in keys.iter().zip(&written) {
+            match kv {
+                Some(_) => {
+                    self.report.imported += 1;
+                    self.cache_aside.invalidate(self.db, self.cache, key).await;
+                }
+                None => self.report.skipped += 1,
+            }
+        }
+        info!(target: "service::kv", count = written.len(), line = self.line, "✏️ import chunk committed");
+        true
+    }
+}
+
+/// 导入请求的状态码，中途停止时为停止原因对应的状态码
+pub fn status(report: &ImportReport) -> StatusCode {
+    report.aborted.as_ref().map_or(StatusCode::OK, |aborted| {
+        StatusCode::from_u16(aborted.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    })
+}
+
+fn status_of(e: &AppError) -> StatusCode {
+    match e {
+        AppError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+        AppError::Conflict(_) => StatusCode::CONFLICT,
+        AppError::KeyQuotaExceeded(_) => StatusCode::FORBIDDEN,
+        AppError::SizeQuotaExceeded(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// 从 `body` 中逐行读取 NDJSON 导入 `db` 所在的 namespace，请求体不需要一次读完
+///
+/// 只有开始导入之前的错误返回 `Err`，之后的错误记录在 [`ImportReport::aborted`] 中
+#[instrument(skip(db, cache, cache_aside, body), target = "service::kv")]
+pub async fn import<S, E>(
+    db: &dyn KvStore,
+    cache: &dyn KvCache,
+    cache_aside: &CacheAside,
+    quota: &NamespaceQuota,
+    limits: &ValueLimits,
+    mode: ImportMode,
+    body: S,
+) -> Result<ImportReport, AppError>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Display,
+{
+    let mut importer = Importer {
+        db,
+        cache,
+        cache_aside,
+        quota,
+        limits,
+        mode,
+        schema: db.schema().await?,
+        line: 0,
+        chunk: Vec::new(),
+        report: ImportReport::default(),
+    };
+    // 二进制值按 base64 编码，一行最长约为 value 上限的 4/3
+    let max_line =
+        limits.max_binary_bytes.max(limits.max_json_bytes) / 3 * 4 + IMPORT_LINE_OVERHEAD;
+
+    let mut body = std::pin::pin!(body);
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let error = format!("Failed to read request body: {}", e);
+                importer.abort(None, StatusCode::BAD_REQUEST, error);
+                return Ok(importer.report);
+            }
+        };
+        buf.extend_from_slice(&chunk);
+        let mut start = 0;
+        while let Some(end) = buf[start..].iter().position(|&b| b == b'\n') {
+            let line = &buf[start..start + end];
+            start += end + 1;
+            if !importer.push(line).await {
+                return Ok(importer.report);
+            }
+        }
+        buf.drain(..start);
+        if buf.len() > max_line {
+            let error = format!("Line must not exceed {} bytes", max_line);
+            importer.abort(Some(importer.line + 1), StatusCode::BAD_REQUEST, error);
+            return Ok(importer.report);
+        }
+    }
+    // 最后一行可以没有换行符
+    if importer.push(&buf).await {
+        importer.flush().await;
+    }
+    Ok(importer.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::MemoryCache, cache_aside::CacheAsideOptions, models::KvPair, store::MemoryStore,
+    };
+
+    async fn run(db: &MemoryStore, mode: ImportMode, body: &str) -> ImportReport {
+        let cache = MemoryCache::new();
+        let cache_aside = CacheAside::new(CacheAsideOptions::default());
+        // 分成两段发送，行在中间被截断
+        let (head, tail) = body.split_at(body.len() / 2);
+        let body = futures::stream::iter(
+            [head, tail].map(|part| Ok::<_, AppError>(Bytes::copy_from_slice(part.as_bytes()))),
+        );
+        import(
+            db,
+            &cache,
+            &cache_aside,
+            &NamespaceQuota::default(),
+            &ValueLimits::default(),
+            mode,
+            body,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn export_round_trips_through_import_modes() {
+        let db = MemoryStore::new();
+        let body = "{\"key\":\"a\",\"value\":\"1\"}\n\nnot json\n{\"key\":\"bad key\",\"value\":\"1\"}\n{\"key\":\"b\",\"value\":{\"x\":1}}";
+        let report = run(&db, ImportMode::Fail, body).await;
+        assert_eq!((report.imported, report.failed), (2, 2));
+        assert_eq!(report.errors[0].line, 3);
+        assert_eq!(report.errors[1].key.as_deref(), Some("bad key"));
+        assert!(report.aborted.is_none());
+
+        let (page, next) = export_page(&db, None).await.unwrap();
+        assert!(next.is_none());
+        let exported: Vec<KvPair> = page
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(exported.len(), 2);
+
+        // 导出的内容原样导入，已存在的 key 按模式处理
+        let snapshot = String::from_utf8(page.to_vec())
+            .unwrap()
+            .replace("\"1\"", "\"2\"");
+        let report = run(&db, ImportMode::Skip, &snapshot).await;
+        assert_eq!((report.imported, report.skipped), (0, 2));
+        let report = run(&db, ImportMode::Fail, &snapshot).await;
+        let aborted = report.aborted.unwrap();
+        assert_eq!((aborted.line, aborted.status), (Some(1), 409));
+        assert_eq!(db.get("a").await.unwrap().unwrap().value, "1");
+        let report = run(&db, ImportMode::Overwrite, &snapshot).await;
+        assert_eq!(report.imported, 2);
+        let kv = db.get("a").await.unwrap().unwrap();
+        assert_eq!((kv.value.as_str(), kv.version), (Some("2"), 2));
+    }
+}
@@ -0,0 +1,229 @@
+//! JSON 值、namespace 的 JSON Schema、JSON Pointer 读取和 merge-patch，`kv_axum` 和 `kv_tower` 共用。
+//!
+//! value 可以是字符串或任意 JSON，Postgres 中存成 `JSONB`，字符串值对外的格式不变；二进制值见 [`kv_value`]。
+//! namespace 注册了 schema 之后，新建、更新、批量写入、cas、回滚和 patch 写入的 value 都要先通过校验；
+//! 注册 schema 时不检查已有的 value，`incr` 只改变计数器的数值，也不做校验。
+use crate::{
+    error::AppError,
+    etag::Preconditions,
+    json_schema,
+    kv_value::{self, ValueLimits},
+    models::KvPair,
+    namespace::NamespaceQuota,
+    store::{Expiry, KvStore},
+};
+use serde_json::{Map, Value};
+use tracing::{info, instrument, warn};
+
+/// patch 时被其他请求抢先修改后最多重试的次数
+const PATCH_MAX_ATTEMPTS: usize = 3;
+
+/// 用 namespace 的 schema 校验 value，不通过时返回 400
+pub fn conform(schema: Option<&Value>, value: &Value) -> Result<(), AppError> {
+    let Some(schema) = schema else {
+        return Ok(());
+    };
+    json_schema::validate(schema, value).map_err(|e| {
+        warn!(target: "service::kv", error = %e, "⚠️ value does not match schema");
+        AppError::InvalidInput(format!("Value does not match schema: {}", e))
+    })
+}
+
+/// namespace 当前的 schema，没有注册时返回 404
+#[instrument(skip(db), target = "service::kv")]
+pub async fn get_schema(db: &dyn KvStore) -> Result<Value, AppError> {
+    db.schema()
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Namespace {} has no schema", db.namespace())))
+}
+
+/// 注册或替换 namespace 的 schema
+#[instrument(skip(db, schema), target = "service::kv")]
+pub async fn set_schema(db: &dyn KvStore, schema: Value) -> Result<(), AppError> {
+    json_schema::check_schema(&schema)
+        .map_err(|e| AppError::InvalidInput(format!("Invalid schema: {}", e)))?;
+    db.set_schema(Some(schema)).await?;
+    info!(target: "service::kv", namespace = db.namespace(), "📐 schema registered");
+    Ok(())
+}
+
+/// 删除 namespace 的 schema，没有注册时返回 404
+#[instrument(skip(db), target = "service::kv")]
+pub async fn delete_schema(db: &dyn KvStore) -> Result<(), AppError> {
+    if !db.set_schema(None).await? {
+        return Err(AppError::NotFound(format!(
+            "Namespace {} has no schema",
+            db.namespace()
+        )));
+    }
+    info!(target: "service::kv", namespace = db.namespace(), "📐 schema removed");
+    Ok(())
+}
+
+/// 按 JSON Pointer 取出 value 的一部分，`path` 为空时返回整个 value
+pub fn select(kv: KvPair, path: &str) -> Result<KvPair, AppError> {
+    if path.is_empty() {
+        return Ok(kv);
+    }
+    if kv.content_type.is_some() {
+        return Err(AppError::InvalidInput(format!(
+            "Key {} holds a binary value",
+            kv.key
+        )));
+    }
+    if !path.starts_with('/') {
+        return Err(AppError::InvalidInput(
+            "path must be a JSON Pointer starting with /".into(),
+        ));
+    }
+    let value =
+        kv.value.pointer(path).cloned().ok_or_else(|| {
+            AppError::NotFound(format!("Path {} not found in key {}", path, kv.key))
+        })?;
+    Ok(KvPair { value, ..kv })
+}
+
+/// RFC 7396 JSON merge-patch：对象逐个字段合并，`null` 表示删除字段，其他值直接替换
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (name, value) in patch {
+            if value.is_null() {
+                target.remove(name);
+            } else {
+                merge_patch(target.entry(name).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// 用 merge-patch 修改 key 的值，key 不存在时返回 404，二进制值返回 409，过期时间不变
+///
+/// 先读出当前值合并，再按读到的版本号条件更新。两步之间被其他请求修改时，
+/// 没有条件头会重新读取再合并，有条件头时返回 412。
+#[instrument(skip(db, patch), target = "service::kv")]
+pub async fn patch(
+    db: &dyn KvStore,
+    quota: &NamespaceQuota,
+    limits: &ValueLimits,
+    key: &str,
+    patch: &Value,
+    preconditions: &Preconditions,
+) -> Result<KvPair, AppError> {
+    let schema = db.schema().await?;
+    for attempt in 1..=PATCH_MAX_ATTEMPTS {
+        let current = db.get(key).await?;
+        preconditions.check(current.as_ref())?;
+        let current =
+            current.ok_or_else(|| AppError::NotFound(format!("Key {} not found", key)))?;
+        if current.content_type.is_some() {
+            return Err(AppError::Conflict(format!(
+                "Key {} holds a binary value",
+                key
+            )));
+        }
+
+        let mut value = current.value;
+        merge_patch(&mut value, patch);
+        limits.validate(&value, None)?;
+        conform(schema.as_ref(), &value)?;
+        let size = kv_value::size(&value, None);
+        let write = db.update_if_version(key, &value, None, current.version, Expiry::Keep);
+        match quota.write(db, &[(key, size)], write).await {
+            Err(AppError::PreconditionFailed(_)) if preconditions.is_empty() => {
+                info!(target: "service::kv", attempt, "🔁 key changed during patch, retry");
+            }
+            result => return result,
+        }
+    }
+    Err(AppError::Conflict(format!(
+        "Key {} is changing too fast, try again",
+        key
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::CreateKv, store::MemoryStore};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn patches_and_pointer_reads_respect_the_schema() {
+        let db = MemoryStore::new();
+        db.set(CreateKv {
+            key: "user".to_string(),
+            value: json!({"name": "a", "address": {"city": "x", "zip": "1"}}),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let schema = json!({
+            "type": "object",
+            "properties": {"name": {"type": "string"}}
+        });
+        set_schema(&db, schema.clone()).await.unwrap();
+        assert_eq!(get_schema(&db).await.unwrap(), schema);
+
+        let patched = json!({"address": {"zip": null, "street": "y"}});
+        let kv = patch(
+            &db,
+            &NamespaceQuota::default(),
+            &ValueLimits::default(),
+            "user",
+            &patched,
+            &Preconditions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            kv.value,
+            json!({"name": "a", "address": {"city": "x", "street": "y"}})
+        );
+        assert_eq!(select(kv.clone(), "/address/city").unwrap().value, "x");
+        assert!(matches!(
+            select(kv.clone(), "/address/zip"),
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            select(kv, "address"),
+            Err(AppError::InvalidInput(_))
+        ));
+
+        // 不符合 schema 的 patch 不会写入
+        assert!(matches!(
+            patch(
+                &db,
+                &NamespaceQuota::default(),
+                &ValueLimits::default(),
+                "user",
+                &json!({"name": 1}),
+                &Preconditions::default()
+            )
+            .await,
+            Err(AppError::InvalidInput(_))
+        ));
+        let limits = ValueLimits::default();
+        assert!(
+            kv_value::check(&db, &limits, &json!("plain string"), None)
+                .await
+                .is_err()
+        );
+        assert_eq!(db.get("user").await.unwrap().unwrap().version, 2);
+
+        delete_schema(&db).await.unwrap();
+        kv_value::check(&db, &limits, &json!("plain string"), None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            delete_schema(&db).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
@@ -1,8 +1,17 @@
 use crate::{
-    cache::CacheClient,
-    db::DBClient,
+    cache::KvCache,
+    cache_aside::CacheAside,
     error::AppError,
-    models::{CreateKv, KvPair},
+    etag::{self, Preconditions},
+    history, kv_atomic, kv_batch, kv_bulk, kv_json,
+    kv_value::{self, ValueLimits},
+    kv_watch::{self, ChangeFeed},
+    models::{
+        BatchKeys, BatchSetKv, CasKv, CreateKv, ExportQuery, GetKvQuery, HistoryQuery, ImportQuery,
+        IncrKv, ListKvQuery, RestoreKv, UpdateKvQuery, WatchQuery,
+    },
+    namespace::{self, NamespaceQuota},
+    store::{self, KvStore},
 };
 use http_body_util::{BodyExt, Full};
 use hyper::{
@@ -10,19 +19,38 @@ use hyper::{
     body::Bytes,
     http::{Method, StatusCode, header},
 };
+use serde_json::Value;
 use std::sync::Arc;
 use tracing::{Span, info, instrument, warn};
 
 #[derive(Clone)]
 pub struct KvService {
-    db: Arc<DBClient>,
-    cache: Arc<CacheClient>,
+    db: Arc<dyn KvStore>,
+    cache: Arc<dyn KvCache>,
+    cache_aside: Arc<CacheAside>,
+    quota: NamespaceQuota,
+    limits: ValueLimits,
+    feed: Arc<ChangeFeed>,
 }
 
 impl KvService {
     #[allow(unused)]
-    pub fn new(db: Arc<DBClient>, cache: Arc<CacheClient>) -> Self {
-        Self { db, cache }
+    pub fn new(
+        db: Arc<dyn KvStore>,
+        cache: Arc<dyn KvCache>,
+        cache_aside: Arc<CacheAside>,
+        quota: NamespaceQuota,
+        limits: ValueLimits,
+        feed: Arc<ChangeFeed>,
+    ) -> Self {
+        Self {
+            db,
+            cache,
+            cache_aside,
+            quota,
+            limits,
+            feed,
+        }
     }
 
     #[instrument(skip(self, req), fields(layer = "kv_tower"), target = "service::kv")]
@@ -34,8 +62,47 @@ impl KvService {
         let path = req.uri().path().to_string();
         tracing::info!(target: "service::kv", %method, %path, "handle request");
 
-        match (method, path.as_str()) {
+        // `/ns/{namespace}/...` 换成对应 namespace 的存储，后面的路由和默认 namespace 一样
+        let namespaced = path.starts_with("/ns/");
+        let (ns, path) = namespace::split_path(&path)?;
+        let svc = Self {
+            db: self.db.scoped(ns),
+            ..self.clone()
+        };
+        svc.route(method, path, namespaced, req).await
+    }
+
+    /// `namespaced` 表示路径带 `/ns/{namespace}` 前缀，用量和 schema 接口只在前缀下提供
+    async fn route(
+        &self,
+        method: Method,
+        path: &str,
+        namespaced: bool,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        match (method, path) {
+            (Method::GET, "/usage") if namespaced => self.handle_usage().await,
+            (method, "/schema") if namespaced => self.handle_schema(method, req).await,
             (Method::POST, "/kv") => self.handle_set_kv(req).await,
+            (Method::GET, "/kv") => self.handle_list_kv(req).await,
+            (Method::GET, "/kv/watch") => self.handle_watch(req).await,
+            (Method::GET, "/admin/export") => self.handle_export(req).await,
+            (Method::POST, "/admin/import") => self.handle_import(req).await,
+            (Method::POST, "/kv/batch/get" | "/kv/batch/set" | "/kv/batch/delete") => {
+                self.handle_batch(path, req).await
+            }
+            (Method::GET, path) if path.starts_with("/kv/") && path.ends_with("/history") => {
+                self.handle_history(path, req).await
+            }
+            (Method::POST, path) if path.starts_with("/kv/") && path.ends_with("/restore") => {
+                self.handle_restore(path, req).await
+            }
+            (Method::POST, path)
+                if path.starts_with("/kv/")
+                    && (path.ends_with("/incr") || path.ends_with("/cas")) =>
+            {
+                self.handle_atomic(path, req).await
+            }
             (Method::GET, path) if path.starts_with("/kv/") => self.handle_get_kv(path, req).await,
             (Method::PUT, path) if path.starts_with("/kv/") => {
                 self.handle_update_kv(path, req).await
@@ -43,6 +110,9 @@ impl KvService {
             (Method::DELETE, path) if path.starts_with("/kv/") => {
                 self.handle_delete_kv(path, req).await
             }
+            (Method::PATCH, path) if path.starts_with("/kv/") => {
+                self.handle_patch_kv(path, req).await
+            }
             _ => self.handle_not_allowed().await,
         }
     }
@@ -61,31 +131,33 @@ impl KvService {
         Span::current().record("payload", format!("{}", input));
 
         // 验证输入
-        if input.key.is_empty()
-            || input.key.len() > 50
-            || !input.key.chars().all(|c| c.is_alphanumeric() || c == '_')
-        {
+        kv_value::validate_key(&input.key).inspect_err(|_| {
             warn!("⚠️ invalid key: {}", input.key);
-            return Err(AppError::InvalidInput("Invalid key".into()));
-        }
-        if input.value.is_empty() || input.value.len() > 1000 {
-            warn!("⚠️ invalid value: {}", input.value);
-            return Err(AppError::InvalidInput("Invalid value".into()));
-        }
+        })?;
+        let content_type = input.content_type.as_deref();
+        kv_value::check(self.db.as_ref(), &self.limits, &input.value, content_type).await?;
 
         // 更新数据库
         info!("✏️ update db");
-        let kv = self.db.set(input).await?;
+        let size = kv_value::size(&input.value, content_type);
+        let key = input.key.clone();
+        let kv = self
+            .quota
+            .write(self.db.as_ref(), &[(&key, size)], self.db.set(input))
+            .await?;
 
-        // TODO: 缓存脏读问题待优化（可改为删除缓存）
-        info!("✏️ update cache");
-        self.cache.set(&format!("kv:{}", kv.key), &kv, 300).await?;
+        // 写路径只失效缓存，由读路径回填
+        info!("🗑️ invalidate cache");
+        self.cache_aside
+            .invalidate(self.db.as_ref(), self.cache.as_ref(), &kv.key)
+            .await;
 
         info!("📦 set key-value successful");
         let body = serde_json::to_vec(&kv)?;
         Ok(Response::builder()
             .status(StatusCode::CREATED)
             .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ETAG, etag::etag(&kv))
             .body(Full::new(Bytes::from(body)))
             .map_err(|e| {
                 AppError::InvalidInput(format!(
@@ -95,48 +167,45 @@ impl KvService {
             })?)
     }
 
-    #[instrument(skip(self, _req), fields(key), target = "service::kv")]
+    #[instrument(skip(self, req), fields(key), target = "service::kv")]
     async fn handle_get_kv(
         &self,
         path: &str,
-        _req: Request<hyper::body::Incoming>,
+        req: Request<hyper::body::Incoming>,
     ) -> Result<Response<Full<Bytes>>, AppError> {
         let key = path
             .strip_prefix("/kv/")
             .ok_or_else(|| AppError::InvalidInput("Invalid path".into()))?;
         Span::current().record("key", key);
         info!("📥 incoming get request");
+        let query: GetKvQuery =
+            serde_urlencoded::from_str(req.uri().query().unwrap_or_default())
+                .map_err(|e| AppError::InvalidInput(format!("Invalid query: {}", e)))?;
 
-        let cache_key = format!("kv:{}", key);
-        if let Some(kv) = self.cache.get::<KvPair>(&cache_key).await? {
-            info!("✅ cache hit");
-            let body = serde_json::to_vec(&kv)?;
-            return Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(Full::new(Bytes::from(body)))
-                .map_err(|e| {
-                    AppError::InvalidInput(format!(
-                        "Failed to construct response body:{}",
-                        e.to_string()
-                    ))
-                })?);
-        }
-        info!("⚠️ cache miss");
-
-        let kv = self.db.get(key).await?.ok_or_else(|| {
-            warn!("⚠️ key not found in db");
-            AppError::NotFound(format!("Key {} not found", key))
-        })?;
-
-        info!("✏️ update cache");
-        self.cache.set(&cache_key, &kv, 300).await?;
-        info!("📦 fetched from db");
+        // 历史值直接查历史表，不经过缓存
+        let kv = if query.is_history() {
+            history::get(self.db.as_ref(), key, &query).await?
+        } else {
+            self.cache_aside
+                .get(self.db.as_ref(), self.cache.as_ref(), key)
+                .await?
+                .ok_or_else(|| {
+                    warn!("⚠️ key not found");
+                    AppError::NotFound(format!("Key {} not found", key))
+                })?
+        };
+        let kv = kv_json::select(kv, query.path.as_deref().unwrap_or_default())?;
+        info!("📦 get successful");
 
-        let body = serde_json::to_vec(&kv)?;
+        // 二进制值按上传时的 Content-Type 原样返回
+        let (content_type, body) = match &kv.content_type {
+            Some(content_type) => (content_type.clone(), kv_value::decode(&kv.value)?),
+            None => ("application/json".to_string(), serde_json::to_vec(&kv)?),
+        };
         Ok(Response::builder()
             .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ETAG, etag::etag(&kv))
             .body(Full::new(Bytes::from(body)))
             .map_err(|e| {
                 AppError::InvalidInput(format!(
@@ -146,7 +215,7 @@ impl KvService {
             })?)
     }
 
-    #[instrument(skip(self, req), fields(key, value), target = "service::kv")]
+    #[instrument(skip(self, req), fields(key, content_type), target = "service::kv")]
     async fn handle_update_kv(
         &self,
         path: &str,
@@ -157,32 +226,66 @@ impl KvService {
             .ok_or_else(|| AppError::InvalidInput("Invalid path".into()))?;
         Span::current().record("key", key);
         info!("📥 incoming update request");
+        let preconditions = Preconditions::from_headers(req.headers());
+        let query: UpdateKvQuery =
+            serde_urlencoded::from_str(req.uri().query().unwrap_or_default())
+                .map_err(|e| AppError::InvalidInput(format!("Invalid query: {}", e)))?;
 
-        // 解析 JSON 字符串
+        // JSON 请求体解析为 JSON 值，其他 Content-Type 的请求体原样作为二进制值
+        let request_content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
         let body_bytes = req.collect().await?.to_bytes();
-        let value: String = serde_json::from_slice(&body_bytes)
-            .map_err(|e| AppError::InvalidInput(format!("Invalid JSON string: {}", e)))?;
-        Span::current().record("value", &value);
+        let (value, content_type) =
+            kv_value::from_body(request_content_type.as_deref(), &body_bytes)?;
+        let content_type = content_type.as_deref();
+        Span::current().record("content_type", content_type);
 
-        // 验证输入
-        if value.is_empty() || value.len() > 1000 {
-            warn!("⚠️ invalid value: {}", value);
-            return Err(AppError::InvalidInput("Invalid value".into()));
+        // 验证输入，upsert 可能新建 key，key 需要和新建接口同样的校验
+        if query.upsert {
+            kv_value::validate_key(key).inspect_err(|_| {
+                warn!("⚠️ invalid key: {}", key);
+            })?;
         }
+        kv_value::check(self.db.as_ref(), &self.limits, &value, content_type).await?;
 
         // 更新数据库
         info!("✏️ update db");
-        let kv = self.db.update(&key, &value).await?;
+        let expiry = store::resolve_update_expiry(&query)?;
+        let write = etag::update(
+            self.db.as_ref(),
+            key,
+            &value,
+            content_type,
+            expiry,
+            &preconditions,
+            query.upsert,
+        );
+        let size = kv_value::size(&value, content_type);
+        let (kv, created) = self
+            .quota
+            .write(self.db.as_ref(), &[(key, size)], write)
+            .await?;
 
-        // TODO: 缓存脏读问题待优化（可改为删除缓存）
-        info!("✏️ update cache");
-        self.cache.set(&format!("kv:{}", key), &kv, 300).await?;
+        // 先写数据库再失效缓存，不直接写缓存，避免并发写入时缓存脏读
+        info!("🗑️ invalidate cache");
+        self.cache_aside
+            .invalidate(self.db.as_ref(), self.cache.as_ref(), key)
+            .await;
 
-        info!("📦 update successful");
+        info!(created, "📦 update successful");
+        let status = if created {
+            StatusCode::CREATED
+        } else {
+            StatusCode::OK
+        };
         let body = serde_json::to_vec(&kv)?;
         Ok(Response::builder()
-            .status(StatusCode::OK)
+            .status(status)
             .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ETAG, etag::etag(&kv))
             .body(Full::new(Bytes::from(body)))
             .map_err(|e| {
                 AppError::InvalidInput(format!(
@@ -192,11 +295,11 @@ impl KvService {
             })?)
     }
 
-    #[instrument(skip(self, _req), fields(key), target = "service::kv")]
+    #[instrument(skip(self, req), fields(key), target = "service::kv")]
     async fn handle_delete_kv(
         &self,
         path: &str,
-        _req: Request<hyper::body::Incoming>,
+        req: Request<hyper::body::Incoming>,
     ) -> Result<Response<Full<Bytes>>, AppError> {
         let key = path
             .strip_prefix("/kv/")
@@ -205,14 +308,17 @@ impl KvService {
         info!("📥 incoming delete request");
 
         info!("🗑️ delete from db");
-        let deleted = self.db.delete(key).await?;
+        let preconditions = Preconditions::from_headers(req.headers());
+        let deleted = etag::delete(self.db.as_ref(), key, &preconditions).await?;
         if !deleted {
             warn!("⚠️ key not found in db");
             return Err(AppError::NotFound(format!("Key {} not found", key)));
         }
 
-        info!("🗑️ delete from cache");
-        self.cache.delete(&format!("kv:{}", key)).await?;
+        info!("🗑️ invalidate cache");
+        self.cache_aside
+            .invalidate(self.db.as_ref(), self.cache.as_ref(), key)
+            .await;
 
         info!("📦 delete successful");
         Ok(Response::builder()
@@ -226,6 +332,392 @@ impl KvService {
             })?)
     }
 
+    #[instrument(skip(self, req), fields(key), target = "service::kv")]
+    async fn handle_history(
+        &self,
+        path: &str,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        let key = path
+            .strip_prefix("/kv/")
+            .and_then(|path| path.strip_suffix("/history"))
+            .ok_or_else(|| AppError::InvalidInput("Invalid path".into()))?;
+        Span::current().record("key", key);
+        info!("📥 incoming history request");
+        let query: HistoryQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or_default())
+            .map_err(|e| AppError::InvalidInput(format!("Invalid query: {}", e)))?;
+
+        let entries = history::list(self.db.as_ref(), key, &query).await?;
+
+        info!(count = entries.len(), "📦 history successful");
+        let body = serde_json::to_vec(&entries)?;
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    #[instrument(skip(self, req), fields(key), target = "service::kv")]
+    async fn handle_restore(
+        &self,
+        path: &str,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        let key = path
+            .strip_prefix("/kv/")
+            .and_then(|path| path.strip_suffix("/restore"))
+            .ok_or_else(|| AppError::InvalidInput("Invalid path".into()))?;
+        Span::current().record("key", key);
+        info!("📥 incoming restore request");
+        let preconditions = Preconditions::from_headers(req.headers());
+
+        let body_bytes = req.collect().await?.to_bytes();
+        let input: RestoreKv = serde_json::from_slice(&body_bytes)
+            .map_err(|e| AppError::InvalidInput(format!("Invalid JSON: {}", e)))?;
+
+        let (kv, created) = history::restore(
+            self.db.as_ref(),
+            &self.quota,
+            &self.limits,
+            key,
+            input.version,
+            &preconditions,
+        )
+        .await?;
+
+        info!("🗑️ invalidate cache");
+        self.cache_aside
+            .invalidate(self.db.as_ref(), self.cache.as_ref(), key)
+            .await;
+
+        info!(created, "📦 restore successful");
+        let status = if created {
+            StatusCode::CREATED
+        } else {
+            StatusCode::OK
+        };
+        let body = serde_json::to_vec(&kv)?;
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ETAG, etag::etag(&kv))
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    #[instrument(skip(self, req), fields(key), target = "service::kv")]
+    async fn handle_atomic(
+        &self,
+        path: &str,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        let (key, op) = path
+            .strip_prefix("/kv/")
+            .and_then(|path| path.rsplit_once('/'))
+            .ok_or_else(|| AppError::InvalidInput("Invalid path".into()))?;
+        Span::current().record("key", key);
+        info!(op, "📥 incoming atomic request");
+
+        let body_bytes = req.collect().await?.to_bytes();
+        let (db, cache, cache_aside) = (
+            self.db.as_ref(),
+            self.cache.as_ref(),
+            self.cache_aside.as_ref(),
+        );
+        let (kv, created) = if op == "incr" {
+            let input: IncrKv = serde_json::from_slice(&body_bytes)
+                .map_err(|e| AppError::InvalidInput(format!("Invalid JSON: {}", e)))?;
+            let kv = kv_atomic::incr(db, cache, cache_aside, &self.quota, key, input.delta).await?;
+            (kv, false)
+        } else {
+            let input: CasKv = serde_json::from_slice(&body_bytes)
+                .map_err(|e| AppError::InvalidInput(format!("Invalid JSON: {}", e)))?;
+            kv_atomic::compare_and_swap(
+                db,
+                cache,
+                cache_aside,
+                &self.quota,
+                &self.limits,
+                key,
+                input,
+            )
+            .await?
+        };
+
+        info!(created, "📦 atomic successful");
+        let status = if created {
+            StatusCode::CREATED
+        } else {
+            StatusCode::OK
+        };
+        let body = serde_json::to_vec(&kv)?;
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ETAG, etag::etag(&kv))
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    #[instrument(skip(self, req), target = "service::kv")]
+    async fn handle_list_kv(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        info!("📥 incoming list request");
+
+        // 解析查询参数
+        let query: ListKvQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or_default())
+            .map_err(|e| AppError::InvalidInput(format!("Invalid query: {}", e)))?;
+
+        let page = store::list_page(self.db.as_ref(), &query).await?;
+
+        info!(count = page.items.len(), "📦 list successful");
+        let body = serde_json::to_vec(&page)?;
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    /// 这里的响应体不能流式返回，`GET /kv/watch` 只支持长轮询，没有 SSE：
+    /// 每次返回一页变更和下一次请求的 `after`，没有新变更时最多等待一会儿
+    #[instrument(skip(self, req), target = "service::kv")]
+    async fn handle_watch(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        info!("📥 incoming watch request");
+
+        let query: WatchQuery =
+            serde_urlencoded::from_str(req.uri().query().unwrap_or_default())
+                .map_err(|e| AppError::InvalidInput(format!("Invalid query: {}", e)))?;
+
+        let page = kv_watch::poll(&self.feed, self.db.clone(), &query).await?;
+
+        info!(
+            count = page.events.len(),
+            after = page.after,
+            "📦 watch successful"
+        );
+        let body = serde_json::to_vec(&page)?;
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    /// 响应体不能流式返回，每次导出一页，还有下一页时在 `X-Next-After` 响应头中返回下一次请求的 `after`
+    #[instrument(skip(self, req), target = "service::kv")]
+    async fn handle_export(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        info!("📥 incoming export request");
+
+        let query: ExportQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or_default())
+            .map_err(|e| AppError::InvalidInput(format!("Invalid query: {}", e)))?;
+
+        let (page, next) = kv_bulk::export_page(self.db.as_ref(), query.after.as_deref()).await?;
+
+        info!(bytes = page.len(), ?next, "📦 export page successful");
+        let mut response = Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/x-ndjson");
+        if let Some(next) = next {
+            response = response.header("X-Next-After", next);
+        }
+        response
+            .body(Full::new(page))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    #[instrument(skip(self, req), target = "service::kv")]
+    async fn handle_import(
+        &self,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        info!("📥 incoming import request");
+
+        let query: ImportQuery = serde_urlencoded::from_str(req.uri().query().unwrap_or_default())
+            .map_err(|e| AppError::InvalidInput(format!("Invalid query: {}", e)))?;
+
+        // 请求体边读边导入
+        let report = kv_bulk::import(
+            self.db.as_ref(),
+            self.cache.as_ref(),
+            &self.cache_aside,
+            &self.quota,
+            &self.limits,
+            query.mode,
+            req.into_body().into_data_stream(),
+        )
+        .await?;
+
+        info!(
+            imported = report.imported,
+            skipped = report.skipped,
+            failed = report.failed,
+            "📦 import finished"
+        );
+        let body = serde_json::to_vec(&report)?;
+        Response::builder()
+            .status(kv_bulk::status(&report))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    #[instrument(skip(self, req), target = "service::kv")]
+    async fn handle_batch(
+        &self,
+        path: &str,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        info!("📥 incoming batch request");
+
+        let body_bytes = req.collect().await?.to_bytes();
+        let (db, cache, cache_aside) = (
+            self.db.as_ref(),
+            self.cache.as_ref(),
+            self.cache_aside.as_ref(),
+        );
+        let result = match path {
+            "/kv/batch/set" => {
+                let input: BatchSetKv = serde_json::from_slice(&body_bytes)
+                    .map_err(|e| AppError::InvalidInput(format!("Invalid JSON: {}", e)))?;
+                kv_batch::set_many(
+                    db,
+                    cache,
+                    cache_aside,
+                    &self.quota,
+                    &self.limits,
+                    input.items,
+                )
+                .await?
+            }
+            _ => {
+                let input: BatchKeys = serde_json::from_slice(&body_bytes)
+                    .map_err(|e| AppError::InvalidInput(format!("Invalid JSON: {}", e)))?;
+                if path == "/kv/batch/get" {
+                    kv_batch::get_many(db, cache, cache_aside, input.keys).await?
+                } else {
+                    kv_batch::delete_many(db, cache, cache_aside, input.keys).await?
+                }
+            }
+        };
+
+        info!("📦 batch successful");
+        let body = serde_json::to_vec(&result)?;
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    #[instrument(skip(self, req), fields(key), target = "service::kv")]
+    async fn handle_patch_kv(
+        &self,
+        path: &str,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        let key = path
+            .strip_prefix("/kv/")
+            .ok_or_else(|| AppError::InvalidInput("Invalid path".into()))?;
+        Span::current().record("key", key);
+        info!("📥 incoming patch request");
+        let preconditions = Preconditions::from_headers(req.headers());
+
+        let body_bytes = req.collect().await?.to_bytes();
+        let patch: Value = serde_json::from_slice(&body_bytes)
+            .map_err(|e| AppError::InvalidInput(format!("Invalid JSON: {}", e)))?;
+
+        let kv = kv_json::patch(
+            self.db.as_ref(),
+            &self.quota,
+            &self.limits,
+            key,
+            &patch,
+            &preconditions,
+        )
+        .await?;
+
+        info!("🗑️ invalidate cache");
+        self.cache_aside
+            .invalidate(self.db.as_ref(), self.cache.as_ref(), key)
+            .await;
+
+        info!(version = kv.version, "📦 patch successful");
+        let body = serde_json::to_vec(&kv)?;
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ETAG, etag::etag(&kv))
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    #[instrument(skip(self, req), target = "service::kv")]
+    async fn handle_schema(
+        &self,
+        method: Method,
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>, AppError> {
+        info!(
+            namespace = self.db.namespace(),
+            "📥 incoming schema request"
+        );
+
+        let response = match method {
+            Method::GET => {
+                let schema = kv_json::get_schema(self.db.as_ref()).await?;
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Full::new(Bytes::from(serde_json::to_vec(&schema)?)))
+            }
+            Method::PUT => {
+                let body_bytes = req.collect().await?.to_bytes();
+                let schema: Value = serde_json::from_slice(&body_bytes)
+                    .map_err(|e| AppError::InvalidInput(format!("Invalid JSON: {}", e)))?;
+                kv_json::set_schema(self.db.as_ref(), schema).await?;
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Full::new(Bytes::new()))
+            }
+            Method::DELETE => {
+                kv_json::delete_schema(self.db.as_ref()).await?;
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Full::new(Bytes::new()))
+            }
+            _ => return self.handle_not_allowed().await,
+        };
+
+        info!("📦 schema successful");
+        response
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
+    #[instrument(skip(self), target = "service::kv")]
+    async fn handle_usage(&self) -> Result<Response<Full<Bytes>>, AppError> {
+        info!(namespace = self.db.namespace(), "📥 incoming usage request");
+
+        let usage = self.quota.usage(self.db.as_ref()).await?;
+
+        info!(usage.keys, usage.bytes, "📦 usage successful");
+        let body = serde_json::to_vec(&usage)?;
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| AppError::InvalidInput(format!("Failed to construct response body:{}", e)))
+    }
+
     async fn handle_not_allowed(&self) -> Result<Response<Full<Bytes>>, AppError> {
         warn!("⚠️ method not allowed");
         Err(AppError::InvalidInput("Method not allowed".into()))
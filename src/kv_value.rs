@@ -0,0 +1,198 @@
+//! key 的命名规则、value 的大小限制和二进制值，`kv_axum` 和 `kv_tower` 共用。
+//!
+//! `PUT /kv/{key}` 的 Content-Type 不是 JSON 时，请求体原样作为二进制值保存，连同 Content-Type 一起，
+//! `GET /kv/{key}` 原样返回。[`KvPair`] 里二进制值表示为 base64 字符串，`content_type` 不为空，
+//! 列表、批量和历史等 JSON 接口也这样返回；Postgres 中存成 `BYTEA`。
+//! 二进制值不经过 namespace 的 schema 校验，也不能被 `incr`、cas 和 merge-patch 修改。
+//!
+//! [`KvPair`]: crate::models::KvPair
+use crate::{error::AppError, kv_json, store::KvStore};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde_json::Value;
+use tracing::{instrument, warn};
+
+/// key 和 namespace 的最大长度
+pub const KEY_MAX_LEN: usize = 50;
+/// JSON value 默认的最大字节数
+pub const JSON_MAX_BYTES: usize = 1000;
+/// 二进制 value 默认的最大字节数
+pub const BINARY_MAX_BYTES: usize = 1024 * 1024;
+/// 上传二进制值时没有 Content-Type 使用的类型
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+/// 和 `/kv/` 下的固定路由同名的 key，`GET /kv/watch` 是变更订阅，不能读到名为 `watch` 的 key
+pub const RESERVED_KEYS: &[&str] = &["watch"];
+
+/// key 只能包含字母、数字和下划线，长度 1 到 [`KEY_MAX_LEN`]，不能是 [`RESERVED_KEYS`]
+pub fn validate_key(key: &str) -> Result<(), AppError> {
+    if RESERVED_KEYS.contains(&key) {
+        return Err(AppError::InvalidInput(format!("Key {} is reserved", key)));
+    }
+    if key.is_empty()
+        || key.len() > KEY_MAX_LEN
+        || !key.chars().all(|c| c.is_alphanumeric() || c == '_')
+    {
+        return Err(AppError::InvalidInput("Invalid key".into()));
+    }
+    Ok(())
+}
+
+/// Content-Type 是否表示 JSON 请求体：`application/json` 或 `+json` 后缀，忽略参数
+pub fn is_json(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence == "application/json" || essence.ends_with("+json")
+}
+
+/// 按 Content-Type 解析 `PUT /kv/{key}` 的请求体，返回 value 和二进制值的 Content-Type
+///
+/// JSON 请求体解析为 JSON 值，其他请求体原样作为二进制值，没有 Content-Type 时按
+/// [`DEFAULT_CONTENT_TYPE`] 保存
+pub fn from_body(
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<(Value, Option<String>), AppError> {
+    match content_type {
+        Some(content_type) if is_json(content_type) => {
+            let value = serde_json::from_slice(body)
+                .map_err(|e| AppError::InvalidInput(format!("Invalid JSON: {}", e)))?;
+            Ok((value, None))
+        }
+        content_type => {
+            let content_type = content_type.unwrap_or(DEFAULT_CONTENT_TYPE);
+            Ok((encode(body), Some(content_type.to_string())))
+        }
+    }
+}
+
+/// 二进制内容在 [`KvPair`](crate::models::KvPair) 中的表示
+pub fn encode(data: &[u8]) -> Value {
+    Value::String(STANDARD.encode(data))
+}
+
+/// 从 base64 字符串还原二进制内容
+pub fn decode(value: &Value) -> Result<Vec<u8>, AppError> {
+    value
+        .as_str()
+        .and_then(|s| STANDARD.decode(s).ok())
+        .ok_or_else(|| AppError::InvalidInput("Binary value must be a base64 string".into()))
+}
+
+/// value 占用的字节数：二进制值按原始内容，字符串按内容，其他 JSON 按序列化后的长度
+pub fn size(value: &Value, content_type: Option<&str>) -> usize {
+    match value {
+        Value::String(s) if content_type.is_some() => {
+            let padding = s.bytes().rev().take_while(|&b| b == b'=').count();
+            (s.len() / 4 * 3).saturating_sub(padding)
+        }
+        Value::String(s) => s.len(),
+        _ => value.to_string().len(),
+    }
+}
+
+/// value 的大小限制，JSON 值和二进制值分开配置
+#[derive(Clone, Copy, Debug)]
+pub struct ValueLimits {
+    pub max_json_bytes: usize,
+    pub max_binary_bytes: usize,
+}
+
+impl Default for ValueLimits {
+    fn default() -> Self {
+        Self {
+            max_json_bytes: JSON_MAX_BYTES,
+            max_binary_bytes: BINARY_MAX_BYTES,
+        }
+    }
+}
+
+impl ValueLimits {
+    /// 不依赖 schema 的校验：不能为空，二进制值必须是合法的 base64，大小不能超过限制
+    pub fn validate(&self, value: &Value, content_type: Option<&str>) -> Result<(), AppError> {
+        let max = match content_type {
+            Some(content_type) => {
+                // 读取时原样作为响应头返回，只允许可见的 ASCII 字符
+                if content_type.is_empty()
+                    || content_type.len() > 255
+                    || !content_type.bytes().all(|b| (b' '..=b'~').contains(&b))
+                {
+                    return Err(AppError::InvalidInput("Invalid content type".into()));
+                }
+                decode(value)?;
+                self.max_binary_bytes
+            }
+            None => self.max_json_bytes,
+        };
+        if value.as_str().is_some_and(str::is_empty) {
+            return Err(AppError::InvalidInput("Invalid value".into()));
+        }
+        let size = size(value, content_type);
+        if size > max {
+            warn!(target: "service::kv", size, max, "⚠️ value too large");
+            return Err(AppError::InvalidInput(format!(
+                "Value must not exceed {} bytes",
+                max
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 写入单个 value 之前的完整校验，JSON 值还要通过 namespace 注册的 schema
+#[instrument(skip(db, value), target = "service::kv")]
+pub async fn check(
+    db: &dyn KvStore,
+    limits: &ValueLimits,
+    value: &Value,
+    content_type: Option<&str>,
+) -> Result<(), AppError> {
+    limits.validate(value, content_type)?;
+    if content_type.is_some() {
+        return Ok(());
+    }
+    kv_json::conform(db.schema().await?.as_ref(), value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn binary_values_are_limited_by_decoded_size() {
+        let limits = ValueLimits {
+            max_json_bytes: 4,
+            max_binary_bytes: 3,
+        };
+        let data = encode(&[0, 255, 7]);
+        assert_eq!(size(&data, Some("image/png")), 3);
+        assert_eq!(decode(&data).unwrap(), [0, 255, 7]);
+        limits.validate(&data, Some("image/png")).unwrap();
+        assert!(
+            limits
+                .validate(&encode(&[0; 4]), Some("image/png"))
+                .is_err()
+        );
+        assert!(limits.validate(&json!("not base64!"), Some("a/b")).is_err());
+        assert!(limits.validate(&json!("12345"), None).is_err());
+
+        // 二进制值不受 schema 约束
+        let db = MemoryStore::new();
+        db.set_schema(Some(json!({"type": "object"})))
+            .await
+            .unwrap();
+        check(&db, &limits, &data, Some("image/png")).await.unwrap();
+        assert!(check(&db, &limits, &json!("abc"), None).await.is_err());
+
+        assert!(is_json("application/json; charset=utf-8"));
+        assert!(is_json("application/merge-patch+json"));
+        assert!(!is_json("text/plain"));
+        assert!(validate_key("bad key").is_err());
+        assert!(validate_key("watch").is_err());
+        assert!(validate_key("watcher").is_ok());
+    }
+}
@@ -0,0 +1,445 @@
+//! 变更订阅：`GET /kv/watch` 推送 key 的写操作，`kv_axum` 和 `kv_tower` 共用。
+//! `watch` 因此是保留的 key 名，见 [`crate::kv_value::RESERVED_KEYS`]。
+//!
+//! 每次写操作都会在 `kv_history` 追加一条记录，记录的 id 就是变更序号，也是客户端重连时的 resume token。
+//! `kv_store` 上的触发器通过 Postgres `NOTIFY` 通知所有实例，每个实例只有一个 [`ChangeFeed`] 监听通知，
+//! 读出新的变更后广播给本实例的所有 [`Watcher`]。watcher 带着 resume token 重连或者落后太多时，
+//! 先从 `kv_history` 补读错过的变更，再接上广播。过期的 key 被清理时产生 `expire` 事件。
+//!
+//! 序号在写入时分配，提交顺序可能和序号顺序不同。[`ChangeFeed`] 遇到不连续的序号时停在空洞前面，
+//! 直到空洞被填上，或者存储确认占用这些序号的事务都已经结束（回滚了），才继续往后广播；
+//! 补读也不会越过已经广播的位置，所以客户端拿到的 resume token 之前不会再冒出新的变更。
+use crate::{
+    error::AppError,
+    kv_value,
+    models::{KvEvent, KvEventPage, WatchQuery},
+    store::KvStore,
+};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tracing::{info, instrument, warn};
+
+/// 每次从 `kv_history` 读取的变更数量
+pub const WATCH_PAGE_SIZE: u32 = 100;
+/// 广播队列的长度，watcher 落后超过这个数量时改为从 `kv_history` 补读
+const FEED_CAPACITY: usize = 1024;
+/// 没有收到通知时的兜底轮询间隔
+const FEED_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// 停在空洞前面时重新读取的间隔
+const FEED_GAP_RETRY: Duration = Duration::from_millis(100);
+/// 空洞最多等待的时间，事务持有序号超过这个时间还没提交时跳过，避免整个广播一直停住
+const FEED_GAP_TIMEOUT: Duration = Duration::from_secs(60);
+/// 轮询时没有新变更最多等待的时间，加上 [`POLL_READ_MARGIN`] 要小于 `kv_tower` 的请求超时，
+/// 启动和重新加载配置时由 `Config::validate` 检查
+pub const POLL_WAIT: Duration = Duration::from_millis(250);
+/// 等待结束后留给读取变更和返回响应的时间
+pub const POLL_READ_MARGIN: Duration = Duration::from_millis(250);
+
+/// 本实例唯一的变更来源，把所有 namespace 的新变更广播给 watcher
+pub struct ChangeFeed {
+    events: broadcast::Sender<KvEvent>,
+    /// 已经广播到的序号，之前的变更都不会再出现
+    published: watch::Receiver<i64>,
+    shutdown: watch::Receiver<bool>,
+}
+
+/// 广播进度，`last` 之前的变更都已经广播过
+struct Cursor {
+    last: i64,
+    /// `last` 之后的序号还没提交时的空洞
+    gap: Option<Gap>,
+}
+
+/// 变更序号里的空洞：占用这些序号的事务还没提交，或者已经回滚
+struct Gap {
+    /// 发现空洞时的写事务水位，见 [`KvStore::write_horizon`]
+    horizon: i64,
+    since: Instant,
+}
+
+/// 启动变更广播任务，从当前最新的序号开始，收到存储的变更通知或者每隔 [`FEED_POLL_INTERVAL`]
+/// 读取一次新的变更。
+///
+/// `shutdown` 变为 true 时任务退出，所有 watcher 随之结束
+pub async fn spawn(
+    db: Arc<dyn KvStore>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(Arc<ChangeFeed>, JoinHandle<()>), AppError> {
+    // 先订阅通知再读取起点，两者之间的写入会在第一次通知时读到
+    let mut notify = db.subscribe();
+    let mut cursor = Cursor {
+        last: db.last_change_id().await?,
+        gap: None,
+    };
+    let (events, _) = broadcast::channel(FEED_CAPACITY);
+    let (published, published_rx) = watch::channel(cursor.last);
+    let feed = Arc::new(ChangeFeed {
+        events: events.clone(),
+        published: published_rx,
+        shutdown: shutdown.clone(),
+    });
+    let handle = tokio::spawn(async move {
+        info!(target: "service::watch", cursor = cursor.last, "📡 change feed started");
+        let mut ticker = tokio::time::interval(FEED_POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                Ok(()) = notify.changed() => {}
+                _ = ticker.tick() => {}
+                _ = tokio::time::sleep(FEED_GAP_RETRY), if cursor.gap.is_some() => {}
+                _ = shutdown.changed() => break,
+            }
+            publish(db.as_ref(), &events, &mut cursor).await;
+            published.send_if_modified(|last| std::mem::replace(last, cursor.last) != cursor.last);
+        }
+        info!(target: "service::watch", "📡 change feed stopped");
+    });
+    Ok((feed, handle))
+}
+
+/// 按序号广播 `cursor.last` 之后的变更，遇到空洞时停下，等空洞被填上或者确认不会再被填上
+async fn publish(db: &dyn KvStore, events: &broadcast::Sender<KvEvent>, cursor: &mut Cursor) {
+    loop {
+        // 先确认空洞不会再被填上，再读一次变更，刚好在这之间提交的写入也能读到
+        let skip = match &cursor.gap {
+            Some(gap) if gap.since.elapsed() >= FEED_GAP_TIMEOUT => {
+                warn!(target: "service::watch", after = cursor.last, "⚠️ change id gap not filled in time, skipping");
+                true
+            }
+            Some(gap) => match db.writes_finished(gap.horizon).await {
+                Ok(finished) => finished,
+                Err(e) => {
+                    warn!(target: "service::watch", error = %e, "⚠️ failed to check pending writes");
+                    return;
+                }
+            },
+            None => false,
+        };
+        let page = match db.changes(cursor.last, WATCH_PAGE_SIZE).await {
+            Ok(page) => page,
+            Err(e) => {
+                warn!(target: "service::watch", error = %e, "⚠️ failed to read changes");
+                return;
+            }
+        };
+        let full = page.len() == WATCH_PAGE_SIZE as usize;
+        for event in page {
+            if event.id != cursor.last + 1 {
+                match cursor.gap {
+                    Some(_) if skip => {
+                        info!(target: "service::watch", from = cursor.last + 1, to = event.id - 1, "change ids were rolled back, skipping");
+                    }
+                    Some(_) => return,
+                    None => {
+                        match db.write_horizon().await {
+                            Ok(horizon) => {
+                                cursor.gap = Some(Gap {
+                                    horizon,
+                                    since: Instant::now(),
+                                })
+                            }
+                            Err(e) => {
+                                warn!(target: "service::watch", error = %e, "⚠️ failed to read write horizon")
+                            }
+                        }
+                        return;
+                    }
+                }
+            }
+            cursor.gap = None;
+            cursor.last = event.id;
+            // 没有 watcher 时发送失败，忽略即可
+            let _ = events.send(event);
+        }
+        if !full {
+            return;
+        }
+    }
+}
+
+/// 校验 `prefix` 和 `after`
+fn validate(query: &WatchQuery) -> Result<(), AppError> {
+    if query
+        .prefix
+        .as_deref()
+        .is_some_and(|prefix| prefix.len() > kv_value::KEY_MAX_LEN)
+    {
+        return Err(AppError::InvalidInput("Invalid prefix".into()));
+    }
+    if query.after.is_some_and(|after| after < 0) {
+        return Err(AppError::InvalidInput("after must not be negative".into()));
+    }
+    Ok(())
+}
+
+impl ChangeFeed {
+    /// 订阅 `db` 所在 namespace 中以 `prefix` 开头的 key 的变更。
+    ///
+    /// 指定 `after` 时先补发序号大于 `after` 的变更，否则只推送订阅之后的新变更
+    #[instrument(skip(self, db), target = "service::watch")]
+    pub async fn watch(
+        &self,
+        db: Arc<dyn KvStore>,
+        query: &WatchQuery,
+    ) -> Result<Watcher, AppError> {
+        validate(query)?;
+        // 先订阅广播再确定起点，起点之后的变更要么补读到，要么从广播收到
+        let events = self.events.subscribe();
+        let (last, behind) = match query.after {
+            Some(after) => (after, true),
+            None => (*self.published.borrow(), false),
+        };
+        info!(target: "service::watch", namespace = db.namespace(), last, "👀 watcher subscribed");
+        Ok(Watcher {
+            namespace: db.namespace().to_string(),
+            prefix: query.prefix.clone().unwrap_or_default(),
+            db,
+            last,
+            behind,
+            pending: VecDeque::new(),
+            events,
+            published: self.published.clone(),
+            shutdown: self.shutdown.clone(),
+        })
+    }
+}
+
+/// 一个客户端的订阅，按序号升序逐个返回变更
+pub struct Watcher {
+    db: Arc<dyn KvStore>,
+    namespace: String,
+    prefix: String,
+    /// 已经处理过的最大序号，包括被过滤掉的变更
+    last: i64,
+    /// 为 true 时需要从 `kv_history` 补读
+    behind: bool,
+    pending: VecDeque<KvEvent>,
+    events: broadcast::Receiver<KvEvent>,
+    published: watch::Receiver<i64>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl Watcher {
+    fn matches(&self, event: &KvEvent) -> bool {
+        event.namespace == self.namespace && event.kv.key.starts_with(&self.prefix)
+    }
+
+    /// 下一个变更，服务关闭或者补读失败时返回 `None`，客户端可以用最后收到的序号重连
+    pub async fn next(&mut self) -> Option<KvEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            if self.behind {
+                // 只补读已经广播过的变更，空洞后面的变更等广播，不会越过还没提交的序号
+                let published = *self.published.borrow();
+                let page = match self.db.changes(self.last, WATCH_PAGE_SIZE).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        warn!(target: "service::watch", error = %e, "⚠️ failed to catch up");
+                        return None;
+                    }
+                };
+                let full = page.len() == WATCH_PAGE_SIZE as usize;
+                let page: Vec<_> = page.into_iter().filter(|e| e.id <= published).collect();
+                self.behind = full && page.len() == WATCH_PAGE_SIZE as usize;
+                self.last = match page.last() {
+                    Some(event) if self.behind => event.id,
+                    _ => self.last.max(published),
+                };
+                let matched: Vec<_> = page.into_iter().filter(|e| self.matches(e)).collect();
+                self.pending.extend(matched);
+                continue;
+            }
+            let event = tokio::select! {
+                event = self.events.recv() => event,
+                _ = self.shutdown.wait_for(|stop| *stop) => return None,
+            };
+            match event {
+                // 补读时已经处理过
+                Ok(event) if event.id <= self.last => {}
+                Ok(event) => {
+                    self.last = event.id;
+                    if self.matches(&event) {
+                        return Some(event);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(target: "service::watch", skipped, "⚠️ watcher lagged, catching up");
+                    self.behind = true;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// 不能保持长连接时的轮询：返回序号大于 `after` 的一页变更，没有新变更时最多等待 [`POLL_WAIT`]。
+/// 不指定 `after` 时只返回当前最新的序号，作为下一次请求的起点
+#[instrument(skip(feed, db), target = "service::watch")]
+pub async fn poll(
+    feed: &ChangeFeed,
+    db: Arc<dyn KvStore>,
+    query: &WatchQuery,
+) -> Result<KvEventPage, AppError> {
+    if query.after.is_none() {
+        validate(query)?;
+        return Ok(KvEventPage {
+            events: Vec::new(),
+            after: *feed.published.borrow(),
+        });
+    }
+    let mut watcher = feed.watch(db, query).await?;
+    let mut events = Vec::new();
+    while events.len() < WATCH_PAGE_SIZE as usize {
+        // 已经有变更时不再等待，只取已经读到的
+        let wait = if events.is_empty() {
+            POLL_WAIT
+        } else {
+            Duration::ZERO
+        };
+        match tokio::time::timeout(wait, watcher.next()).await {
+            Ok(Some(event)) => events.push(event),
+            _ => break,
+        }
+    }
+    // 补读到但还没返回的变更也要带上，`after` 已经越过了它们
+    events.extend(watcher.pending.drain(..));
+    Ok(KvEventPage {
+        events,
+        after: watcher.last,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CreateKv;
+    use crate::store::{Expiry, MemoryStore, testing::HookedStore};
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn watchers_resume_after_token_and_filter_by_prefix() {
+        let db: Arc<dyn KvStore> = Arc::new(MemoryStore::new());
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let (feed, handle) = spawn(db.clone(), shutdown).await.unwrap();
+        let set = |key: &str| CreateKv {
+            key: key.to_string(),
+            value: "v".into(),
+            ..Default::default()
+        };
+
+        db.set(set("app_a")).await.unwrap();
+        db.scoped("other").set(set("app_b")).await.unwrap();
+        let query = WatchQuery {
+            prefix: Some("app_".to_string()),
+            after: None,
+        };
+        // 等广播追上之前的写入，之后订阅的 watcher 只收到新的变更
+        feed.published
+            .clone()
+            .wait_for(|last| *last == 2)
+            .await
+            .unwrap();
+        let mut live = feed.watch(db.clone(), &query).await.unwrap();
+        db.set(set("db_c")).await.unwrap();
+        db.update("app_a", &"v2".into(), None, Expiry::Keep)
+            .await
+            .unwrap();
+        db.delete("app_a").await.unwrap();
+
+        let event = live.next().await.unwrap();
+        assert_eq!((event.id, event.operation.as_str()), (4, "update"));
+        assert_eq!(event.kv.value, "v2");
+        let event = live.next().await.unwrap();
+        assert_eq!((event.id, event.operation.as_str()), (5, "delete"));
+
+        // 用 resume token 重连，补发错过的变更，只包括当前 namespace
+        let resumed = WatchQuery {
+            after: Some(0),
+            ..query
+        };
+        let mut watcher = feed.watch(db.clone(), &resumed).await.unwrap();
+        let ids: Vec<_> = [
+            watcher.next().await,
+            watcher.next().await,
+            watcher.next().await,
+        ]
+        .into_iter()
+        .map(|event| event.unwrap().id)
+        .collect();
+        assert_eq!(ids, [1, 4, 5]);
+        let page = poll(&feed, db.clone(), &resumed).await.unwrap();
+        assert_eq!(page.events.len(), 3);
+        assert_eq!(page.after, 5);
+
+        shutdown_tx.send(true).unwrap();
+        handle.await.unwrap();
+        assert!(live.next().await.is_none());
+    }
+
+    /// 下一个变更的序号，300ms 内没有时返回 `None`
+    async fn next_id(watcher: &mut Watcher) -> Option<i64> {
+        let next = tokio::time::timeout(Duration::from_millis(300), watcher.next()).await;
+        next.ok().flatten().map(|event| event.id)
+    }
+
+    #[tokio::test]
+    async fn late_commits_are_delivered_in_order() {
+        // 2 和 4 的事务先分配了序号，还没提交
+        let store = Arc::new(HookedStore {
+            hidden_changes: Mutex::new(HashSet::from([2, 4])),
+            writes_finished: Mutex::new(Some(false)),
+            ..Default::default()
+        });
+        let db: Arc<dyn KvStore> = store.clone();
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        let (feed, _handle) = spawn(db.clone(), shutdown).await.unwrap();
+        let mut live = feed
+            .watch(db.clone(), &WatchQuery::default())
+            .await
+            .unwrap();
+        for key in ["a", "b", "c", "d", "e"] {
+            db.set(CreateKv {
+                key: key.to_string(),
+                value: "v".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        let resumed = WatchQuery {
+            after: Some(0),
+            ..Default::default()
+        };
+        let mut watcher = feed.watch(db.clone(), &resumed).await.unwrap();
+
+        // 2 还没提交，广播和补读都停在 2 前面，不会先推送 3
+        assert_eq!(next_id(&mut live).await, Some(1));
+        assert_eq!(next_id(&mut live).await, None);
+        assert_eq!(next_id(&mut watcher).await, Some(1));
+        assert_eq!(next_id(&mut watcher).await, None);
+
+        // 2 晚提交后按顺序推送；4 的事务一直没有结束，也停在 4 前面
+        store.hidden_changes.lock().unwrap().remove(&2);
+        for watcher in [&mut live, &mut watcher] {
+            assert_eq!(next_id(watcher).await, Some(2));
+            assert_eq!(next_id(watcher).await, Some(3));
+            assert_eq!(next_id(watcher).await, None);
+        }
+
+        // 4 的事务回滚后跳过 4
+        *store.writes_finished.lock().unwrap() = Some(true);
+        for watcher in [&mut live, &mut watcher] {
+            assert_eq!(next_id(watcher).await, Some(5));
+        }
+        let page = poll(&feed, db.clone(), &WatchQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(page.after, 5);
+    }
+}
@@ -1,12 +1,32 @@
 #![feature(duration_millis_float)]
 
 mod cache;
+mod cache_aside;
+mod circuit_breaker;
+mod config;
 mod db;
 mod error;
+mod etag;
+mod history;
+mod json_schema;
+mod kv_atomic;
 mod kv_axum;
+mod kv_batch;
+mod kv_bulk;
+mod kv_json;
 mod kv_tower;
+mod kv_value;
+mod kv_watch;
 mod models;
+mod namespace;
 mod open_api;
+mod reload;
+mod shutdown;
+mod single_flight;
+mod sqlite;
+mod store;
+mod sweeper;
+mod tls;
 
 mod app;
 mod appv2;
@@ -18,35 +38,37 @@ mod middleware_tower;
 #[cfg(feature = "service-my")]
 #[allow(unused_imports)]
 use crate::app::echo;
-use std::env;
 // use futures::SinkExt;
 
-use hyper::server::conn::http1;
 use hyper_util::rt::TokioIo;
 use hyper_util::service::TowerToHyperService;
-use std::net::SocketAddr;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
-#[cfg(feature = "middleware-tower")]
-use std::time::Duration;
 // use hyper::server::Server;
 // use hyper::service::make_service_fn;
 #[cfg(feature = "service-axum")]
 use crate::appv2::{AppState, echo_handler, health_handler};
-use crate::cache::CacheClient;
+use crate::cache::{CacheClient, KvCache, MemoryCache};
+use crate::cache_aside::CacheAside;
+use crate::config::{Cli, Config};
 use crate::db::DBClient;
 use crate::init_opentelemetry::init_tracing;
 #[cfg(feature = "service-axum")]
 use crate::open_api::ApiDoc;
+use crate::reload::{MiddlewareSettings, Reloader};
+use crate::shutdown::Shutdown;
+use crate::sqlite::SqliteClient;
+use crate::store::{KvStore, MemoryStore};
+use crate::tls::{PeerIdentityService, Tls};
 #[cfg(feature = "service-axum")]
 use axum::{
     Router,
     routing::{get, post},
 };
+use clap::Parser;
 use dotenvy::dotenv;
 use tokio::net::TcpListener;
 use tokio::signal::unix::{SignalKind, signal};
-use tokio::sync::Notify;
+use tokio::sync::{Notify, watch};
 use tower::ServiceBuilder;
 #[cfg(feature = "service-my")]
 use tower::service_fn;
@@ -225,31 +247,90 @@ use utoipa_swagger_ui::SwaggerUi;
 /// ...
 /// 2025-04-09T11:02:41.505884Z  INFO server::shutdown: Received SIGINT, shutting down...
 /// 2025-04-09T11:02:41.505920Z  INFO server::shutdown: Shutting down: stopping new connections
-/// 2025-04-09T11:02:41.505932Z  INFO server::shutdown: Waiting for active tasks to complete
-/// 2025-04-09T11:02:41.505938Z  INFO server::shutdown: Waiting for 100 active tasks to complete
+/// 2025-04-09T11:02:41.505932Z  INFO server::shutdown: Closing watch streams
+/// 2025-04-09T11:02:41.505938Z  INFO server::shutdown: Draining connections connections=100 deadline=30s
 /// ...
-/// 2025-04-09T11:02:41.827603Z  INFO server::shutdown: Waiting for 75 active tasks to complete
-/// ...
-/// 2025-04-09T11:02:42.214166Z  INFO server::shutdown: All active tasks completed
+/// 2025-04-09T11:02:42.214166Z  INFO server::shutdown: All connections closed
+/// 2025-04-09T11:02:42.214201Z  INFO server::shutdown: Stopping expired key sweeper
 /// 2025-04-09T11:02:42.214235Z  INFO server::shutdown: Shutting down OpenTelemetry
 /// 2025-04-09T11:02:42.267836Z  INFO server::shutdown: Server shutdown complete
 ///
 /// ```
+///
+/// idle keep-alive connections (e.g. opened by the browser for Swagger UI) are closed as soon as the shutdown starts,
+/// in-flight requests are finished first. connections still open after `--shutdown-timeout-secs` (30s by default) are aborted:
+///
+/// ```text
+/// WARN server::shutdown: ⚠️ drain deadline exceeded, aborting connections connections=1
+/// ```
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (otlp_tracer_provider, otlp_meter_provider) = init_tracing().await?;
-
+    // 配置依次来自 --config 指定的 TOML 文件、环境变量（含 .env）和命令行参数，后者覆盖前者
     dotenv().ok();
-    let db = Arc::new(DBClient::new(&env::var("DATABASE_URL")?).await?);
-    let cache = Arc::new(CacheClient::new(&env::var("REDIS_URL")?).await?);
+    let cli = Cli::parse();
+    let config = Config::from_cli(cli.clone())?;
+    let (otlp_tracer_provider, otlp_meter_provider, log_level) =
+        init_tracing(&config.telemetry).await?;
+
+    // 未配置数据库 URL 时使用内存存储，方便本地演示和测试
+    // sqlite:kv.db、sqlite::memory: 等 sqlite: 开头的 URL 使用 SQLite，其他使用 Postgres
+    let pool_options = config.database.pool_options();
+    let db: Arc<dyn KvStore> = match &config.database.url {
+        Some(database_url) if database_url.starts_with("sqlite:") => {
+            Arc::new(SqliteClient::new(database_url, pool_options).await?)
+        }
+        Some(database_url) => Arc::new(DBClient::new(database_url, pool_options).await?),
+        None => {
+            tracing::warn!(target: "server::startup", "DATABASE_URL not set, using in-memory kv store");
+            Arc::new(MemoryStore::new())
+        }
+    };
+    // 未配置 Redis URL 时使用进程内缓存
+    let cache: Arc<dyn KvCache> = match &config.redis.url {
+        Some(redis_url) => {
+            Arc::new(CacheClient::new(redis_url, config.redis.client_options()).await?)
+        }
+        None => {
+            tracing::warn!(target: "server::startup", "REDIS_URL not set, using in-process cache");
+            Arc::new(MemoryCache::new())
+        }
+    };
+    let cache_aside = Arc::new(CacheAside::new(config.cache.cache_aside_options()));
+    // 超时、限流和 Auth-Key 可以在收到 SIGHUP 重新加载配置时修改
+    let middleware_settings = MiddlewareSettings::new(&config.middleware);
+    let quota = config.kv.quota();
+    let limits = config.kv.limits();
+
+    // 后台清理过期的 key
+    let (sweeper_shutdown, sweeper_shutdown_rx) = watch::channel(false);
+    let sweeper = sweeper::spawn(
+        db.clone(),
+        cache.clone(),
+        cache_aside.clone(),
+        config.kv.sweep_interval(),
+        sweeper_shutdown_rx,
+    );
+
+    // 变更订阅：所有实例的写入通过 Postgres LISTEN/NOTIFY 推送给本实例的 watcher
+    let (watch_shutdown, watch_shutdown_rx) = watch::channel(false);
+    let (change_feed, change_feed_task) = kv_watch::spawn(db.clone(), watch_shutdown_rx).await?;
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    let addr = config.server.listen;
     let listener = TcpListener::bind(addr).await?;
 
+    // 配置了证书时使用 TLS，证书文件变化后自动重新加载
+    let tls = if config.tls.enabled() {
+        let tls = Arc::new(Tls::new(&config.tls)?);
+        tls::spawn_reload(tls.clone(), config.tls.reload_interval());
+        Some(tls)
+    } else {
+        None
+    };
+
     tracing::info!(
         target: "server::startup",
         service_name = "echo-server",
-        service_protocol = "http",
+        service_protocol = if tls.is_some() { "https" } else { "http" },
         service_address = %addr,
         "HTTP service is now listening on {} (Powered by hyper and tower), press Ctrl+C to stop",
         addr
@@ -259,11 +340,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let shutdown = Arc::new(Notify::new());
     let shutdown_clone = shutdown.clone();
 
-    // 跟踪活跃任务
-    // let active_tasks = Arc::new(tokio::sync::Mutex::new(0));
-    // 使用 AtomicUsize 替代 Mutex
-    let active_tasks = Arc::new(AtomicUsize::new(0));
-    let active_tasks_clone = active_tasks.clone();
+    // 跟踪活跃连接，退出时通知它们关闭
+    let connections = Shutdown::new();
+    let shutdown_timeout = config.server.shutdown_timeout();
 
     // 处理信号
     let mut sigint = signal(SignalKind::interrupt())?;
@@ -285,18 +364,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         // FIXME: 这里的body limit 中间件会导致Service<Request<Limited<ReqBody>> Request类型不一致
         // .layer(middleware_for_my_service::ratelimit::ratelimit_layer())
         .layer(middleware_for_my_service::cache::CacheLayer)
-        .layer(middleware_for_my_service::timeout::timeout_layer())
+        .layer(middleware_for_my_service::timeout::timeout_layer(
+            config.middleware.echo_timeout(),
+        ))
         .service(service_fn(echo));
 
     #[cfg(feature = "service-my")]
-    let svc = kv_tower::KvService::new(db.clone(), cache.clone());
+    // middleware-my 的中间件栈只提供 echo，不使用 kv 服务
+    #[cfg_attr(feature = "middleware-my", allow(unused_variables))]
+    let svc = kv_tower::KvService::new(
+        db.clone(),
+        cache.clone(),
+        cache_aside.clone(),
+        quota,
+        limits,
+        change_feed.clone(),
+    );
     // 构建 Tower Service 使用通用的标准 Tower Service middleware
     #[cfg(all(feature = "service-my", feature = "middleware-tower"))]
     let t_service = ServiceBuilder::new()
         .layer(middleware_tower::tracing::TracingLayer)
         .layer(middleware_tower::metrics::MetricsLayer)
+        .layer(middleware_tower::ratelimit::RateLimitLayer::new(
+            middleware_settings.rate_limit.subscribe(),
+        ))
         .layer(middleware_tower::timeout::TimeoutLayer::new(
-            Duration::from_millis(501),
+            middleware_settings.kv_timeout.subscribe(),
         ))
         // .layer(middleware_tower::cache::CacheLayer)
         // .layer(middleware_tower::auth::AuthLayer)
@@ -334,7 +427,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // FIXME: 这里的body limit 中间件会导致Service<Request<Limited<ReqBody>> Request类型不一致
                 // .layer(middleware_for_my_service::ratelimit::ratelimit_layer())
                 .layer(middleware_for_axum::cache::CacheLayer)
-                .layer(middleware_for_axum::timeout::timeout_layer()),
+                .layer(middleware_for_axum::timeout::timeout_layer(
+                    config.middleware.echo_timeout(),
+                )),
         ); // 添加 Tower 中间件
 
     // 构建Router 使用通用的标准 Tower Service middleware
@@ -349,10 +444,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .layer(middleware_tower::tracing::TracingLayer)
                 .layer(middleware_tower::metrics::MetricsLayer)
                 .layer(middleware_tower::timeout::TimeoutLayer::new(
-                    Duration::from_millis(1000),
+                    middleware_settings.echo_timeout.subscribe(),
                 ))
                 .layer(middleware_tower::cache::CacheLayer)
-                .layer(middleware_tower::auth::AuthLayer),
+                .layer(middleware_tower::auth::AuthLayer::new(
+                    middleware_settings.auth_keys.subscribe(),
+                )),
         ); // 添加 Tower 中间件
 
     // 构建 kv service 的Router
@@ -364,13 +461,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let kv_app_state = kv_axum::AppState {
         db: db.clone(),
         cache: cache.clone(),
+        cache_aside: cache_aside.clone(),
+        quota,
+        limits,
+        feed: change_feed,
     };
 
     #[cfg(all(feature = "service-axum"))]
     let kv_router = kv_axum::router(kv_app_state).layer(
         ServiceBuilder::new()
             .layer(middleware_tower::tracing::TracingLayer)
-            .layer(middleware_tower::metrics::MetricsLayer),
+            .layer(middleware_tower::metrics::MetricsLayer)
+            .layer(middleware_tower::ratelimit::RateLimitLayer::new(
+                middleware_settings.rate_limit.subscribe(),
+            )),
     );
 
     #[cfg(all(feature = "service-axum"))]
@@ -388,45 +492,63 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     #[cfg(feature = "service-axum")]
     let axum_service = TowerToHyperService::new(app.into_service());
 
+    // 同一个端口上同时支持 HTTP/1.1 和 h2c，按连接的前几个字节判断协议
+    let builder = config.http2.connection_builder();
+
+    // 收到 SIGHUP 时重新加载配置
+    let reloader = Reloader::new(
+        cli,
+        config,
+        log_level,
+        cache_aside.clone(),
+        middleware_settings,
+    );
+    reload::spawn(reloader)?;
+
     loop {
         tokio::select! {
             // TODO:这里是否只支持单线程处理请求？
             result = listener.accept() => {
                 match result {
-                    Ok((stream, _addr)) => {
-                        let io = TokioIo::new(stream);
+                    Ok((stream, peer_addr)) => {
+                        let tls = tls.clone();
                         #[cfg(feature = "service-my")]
                         let cloned_hyper_service = hyper_service.clone();
                         #[cfg(feature = "service-axum")]
                         let cloned_axum_service = axum_service.clone();
-                        let active_tasks = active_tasks.clone();
-
-                        // 增加活跃任务计数
-                        // let mut locked_active_tasks = active_tasks.lock().await;
-                        // *locked_active_tasks += 1;
-                        // 增加活跃任务计数
-                        let active_tasks_clone_for_current_connection = active_tasks.clone();
-                        active_tasks_clone.fetch_add(1, Ordering::SeqCst);
+                        let builder = builder.clone();
+                        // 连接结束（包括握手失败和被中断）时 guard 被释放，活跃连接数减一
+                        let mut connection = connections.track();
 
                         tokio::spawn(async move {
+                            // 配置了 TLS 时先完成握手，mTLS 校验通过的客户端证书放到每个请求里
+                            let (io, peer): (Box<dyn tls::Io>, _) = match &tls {
+                                // 握手不受 graceful shutdown 控制，开始退出时直接放弃
+                                Some(tls) => match connection.handshake(tls.accept(stream)).await {
+                                    Some(Ok((stream, peer))) => (Box::new(stream), peer),
+                                    Some(Err(e)) => {
+                                        tracing::warn!(target: "server::connection", %peer_addr, error = %e, "Rejected connection");
+                                        return;
+                                    }
+                                    None => {
+                                        tracing::info!(target: "server::connection", %peer_addr, "Dropped connection in TLS handshake on shutdown");
+                                        return;
+                                    }
+                                },
+                                None => (Box::new(stream), None),
+                            };
+                            let io = TokioIo::new(io);
+
                             #[cfg(feature = "service-my")]
-                            if let Err(e) = http1::Builder::new()
-                            .serve_connection(io, cloned_hyper_service)
-                            .await {
-                                tracing::error!(target: "server::connection", "Error serving connection: {}", e);
-                            }
+                            let conn = builder
+                                .serve_connection(io, PeerIdentityService::new(cloned_hyper_service, peer));
                             #[cfg(feature = "service-axum")]
-                            if let Err(e) = http1::Builder::new()
-                            .serve_connection(io, cloned_axum_service)
-                            .await {
+                            // WebSocket 需要在 HTTP/1.1 连接上升级协议
+                            let conn = builder
+                                .serve_connection_with_upgrades(io, PeerIdentityService::new(cloned_axum_service, peer));
+                            if let Err(e) = connection.serve(conn).await {
                                 tracing::error!(target: "server::connection", "Error serving connection: {}", e);
                             }
-
-                            // 任务完成后减少活跃任务计数
-                            // let mut locked_active_tasks = active_tasks.lock().await;
-                            // *locked_active_tasks -= 1;
-                            // 任务完成，减少计数
-                            active_tasks_clone_for_current_connection.fetch_sub(1, Ordering::SeqCst);
                         });
                     }
                     Err(e) => {
@@ -444,35 +566,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    // 等待所有活跃任务完成
-    tracing::info!(target: "server::shutdown", "Waiting for active tasks to complete");
-    // FIXME: 这里的active_tasks.lock().await == 0, sleep 会长时间占有锁，虽然不会阻塞
-    // 让出线程后锁还是被占有，其他active task执行完无法获取锁更新activeTaskCount
-    // NOTE: 已经修复，这里使用原子计数器即可
-    // loop {
-    //     if *active_tasks.lock().await == 0 {
-    //         break;
-    //     }
-    //     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-    // }
-    // TODO: 是否需要使用watch的方式优化这里的轮训
-    // watch主动通知，类似
-    // let (shutdown_tx, shutdown_rx) = watch::channel(false);
-    // // 任务线程
-    // let remaining = active_tasks_clone.fetch_sub(1, Ordering::SeqCst);
-    // if remaining == 1 && *shutdown_rx_task.borrow() {
-    //     shutdown_complete_clone.notify_one();
-    // }
-    // FIXME: 添加Swagger之后 会有浏览器建立的连接不退出，导致程序不退出
-    while active_tasks.load(Ordering::SeqCst) > 0 {
-        tracing::info!(target: "server::shutdown",
-            "Waiting for {} active tasks to complete",
-            active_tasks.load(Ordering::SeqCst)
-        );
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-    }
+    // 先结束所有 watch 连接，否则它们不会自己结束
+    tracing::info!(target: "server::shutdown", "Closing watch streams");
+    watch_shutdown.send(true)?;
+    change_feed_task.await?;
+
+    // 通知所有连接处理完当前请求后关闭，超过期限的连接直接中断
+    connections.drain(shutdown_timeout).await;
 
-    tracing::info!(target: "server::shutdown", "All active tasks completed");
+    // 停止过期 key 清理任务
+    tracing::info!(target: "server::shutdown", "Stopping expired key sweeper");
+    sweeper_shutdown.send(true)?;
+    sweeper.await?;
 
     // 关闭 OpenTelemetry
     tracing::info!(target: "server::shutdown", "Shutting down OpenTelemetry");
@@ -1,6 +1,6 @@
 use std::time::Duration;
 pub(crate) use tower_http::timeout::TimeoutLayer;
 
-pub fn timeout_layer() -> TimeoutLayer {
-    TimeoutLayer::new(Duration::from_secs(1))
+pub fn timeout_layer(timeout: Duration) -> TimeoutLayer {
+    TimeoutLayer::new(timeout)
 }
@@ -1,12 +1,16 @@
 use crate::middleware_tower::auth::service::AuthService;
+use tokio::sync::watch;
 use tower::Layer;
 
-#[derive(Clone, Copy, Debug)]
-pub struct AuthLayer;
+/// 允许的 `Auth-Key` 从 watch 通道读取，为空时只要求请求带上 `Auth-Key`
+#[derive(Clone, Debug)]
+pub struct AuthLayer {
+    keys: watch::Receiver<Vec<String>>,
+}
 
 impl AuthLayer {
-    pub fn new() -> Self {
-        Self
+    pub fn new(keys: watch::Receiver<Vec<String>>) -> Self {
+        Self { keys }
     }
 }
 
@@ -15,6 +19,9 @@ impl<S> Layer<S> for AuthLayer {
     type Service = AuthService<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        AuthService { inner }
+        AuthService {
+            inner,
+            keys: self.keys.clone(),
+        }
     }
 }
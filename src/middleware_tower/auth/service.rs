@@ -4,19 +4,21 @@ use crate::middleware_tower::auth::layer::AuthLayer;
 use http::{Request, Response};
 use http_body::Body;
 use std::task::{Context, Poll};
+use tokio::sync::watch;
 use tower::Service;
 use tracing::{Level, event, field};
 use tracing::{Span, instrument};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct AuthService<S> {
     pub inner: S,
+    pub keys: watch::Receiver<Vec<String>>,
 }
 
 #[allow(unused)]
 impl<S> AuthService<S> {
-    pub fn new(inner: S) -> Self {
-        Self { inner }
+    pub fn new(inner: S, keys: watch::Receiver<Vec<String>>) -> Self {
+        Self { inner, keys }
     }
 
     /// Gets a reference to the underlying service.
@@ -37,8 +39,8 @@ impl<S> AuthService<S> {
     /// Returns a new [`Layer`] that wraps services with a `AuthService` middleware.
     ///
     /// [`Layer`]: tower_layer::Layer
-    pub fn layer() -> AuthLayer {
-        AuthLayer::new()
+    pub fn layer(keys: watch::Receiver<Vec<String>>) -> AuthLayer {
+        AuthLayer::new(keys)
     }
 }
 
@@ -63,7 +65,11 @@ where
         let span = Span::current();
         // let authorized = req.headers().get("Authorization").is_some();
         // 适配swagger, 暂时使用自定义的Auth-Key 通过auth认证，Authorization是security内置key不让用
-        let authorized = req.headers().get("Auth-Key").is_some();
+        // 配置了 auth_keys 时 Auth-Key 必须是其中之一
+        let authorized = req.headers().get("Auth-Key").is_some_and(|key| {
+            let keys = self.keys.borrow();
+            keys.is_empty() || keys.iter().any(|k| key == k.as_str())
+        });
         span.record("authorized", &authorized);
 
         if !authorized {
@@ -5,3 +5,5 @@ pub mod tracing;
 pub mod timeout;
 
 pub mod cache;
+
+pub mod ratelimit;
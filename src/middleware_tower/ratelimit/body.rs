@@ -0,0 +1,98 @@
+use bytes::Bytes;
+use http::{HeaderValue, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::Full;
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+pin_project! {
+    /// Response body for [`RateLimitService`].
+    ///
+    /// [`RateLimitService`]: super::RateLimitService
+    pub struct RateLimitResponseBody<B> {
+        #[pin]
+        inner: ResponseBodyInner<B>,
+    }
+}
+const BODY: &[u8] = b"Too many requests, please retry later";
+
+impl<B> RateLimitResponseBody<B> {
+    pub fn payload_too_many_requests() -> Self {
+        Self {
+            inner: ResponseBodyInner::TooManyRequests {
+                body: Full::from(BODY),
+            },
+        }
+    }
+
+    pub(crate) fn new(body: B) -> Self {
+        Self {
+            inner: ResponseBodyInner::Body { body },
+        }
+    }
+}
+
+pin_project! {
+    #[project = ResponseBodyProj]
+    enum ResponseBodyInner<B> {
+        // 超过限流，构建Response时指定的Body类型
+        TooManyRequests {
+            #[pin]
+            body: Full<Bytes>,
+        },
+        // 上游返回的Response
+        Body {
+            #[pin]
+            body: B,
+        },
+    }
+}
+
+impl<B> Body for RateLimitResponseBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().inner.project() {
+            ResponseBodyProj::TooManyRequests { body } => {
+                body.poll_frame(cx).map_err(|err| match err {})
+            }
+            ResponseBodyProj::Body { body } => body.poll_frame(cx),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.inner {
+            ResponseBodyInner::TooManyRequests { body } => body.is_end_stream(),
+            ResponseBodyInner::Body { body } => body.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match &self.inner {
+            ResponseBodyInner::TooManyRequests { body } => body.size_hint(),
+            ResponseBodyInner::Body { body } => body.size_hint(),
+        }
+    }
+}
+
+pub fn create_too_many_requests_response<B>() -> Response<RateLimitResponseBody<B>> {
+    let mut res = Response::new(RateLimitResponseBody::payload_too_many_requests());
+    *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+
+    const TEXT_PLAIN: HeaderValue = HeaderValue::from_static("text/plain; charset=utf-8");
+    const RETRY_AFTER: HeaderValue = HeaderValue::from_static("1");
+    res.headers_mut()
+        .insert(http::header::CONTENT_TYPE, TEXT_PLAIN);
+    res.headers_mut()
+        .insert(http::header::RETRY_AFTER, RETRY_AFTER);
+
+    res
+}
@@ -0,0 +1,62 @@
+use crate::middleware_tower::ratelimit::RateLimitResponseBody;
+use crate::middleware_tower::ratelimit::create_too_many_requests_response;
+use http::Response;
+use http_body::Body;
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+pin_project! {
+    /// Response future for [`RateLimitService`].
+    ///
+    /// [`RateLimitService`]: super::RateLimitService
+    pub struct RateLimitResponseFuture<F> {
+        #[pin]
+        inner: ResponseFutureInner<F>,
+    }
+}
+
+impl<F> RateLimitResponseFuture<F> {
+    /// 超过限流，直接返回 429
+    pub fn too_many_requests() -> Self {
+        Self {
+            inner: ResponseFutureInner::TooManyRequests,
+        }
+    }
+
+    /// 包装上游Service的Future
+    pub fn new(future: F) -> Self {
+        Self {
+            inner: ResponseFutureInner::Future { future },
+        }
+    }
+}
+
+pin_project! {
+    #[project = ResFutProj]
+    enum ResponseFutureInner<F> {
+        TooManyRequests,
+        Future {
+            #[pin]
+            future: F,
+        }
+    }
+}
+
+impl<ResBody, F, E> Future for RateLimitResponseFuture<F>
+where
+    ResBody: Body,
+    F: Future<Output = Result<Response<ResBody>, E>>,
+{
+    type Output = Result<Response<RateLimitResponseBody<ResBody>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().inner.project() {
+            ResFutProj::TooManyRequests => Poll::Ready(Ok(create_too_many_requests_response())),
+            ResFutProj::Future { future } => {
+                let res = ready!(future.poll(cx))?.map(RateLimitResponseBody::new);
+                Poll::Ready(Ok(res))
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::middleware_tower::ratelimit::service::{RateLimitService, TokenBucket};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tower::Layer;
+
+/// 每秒允许的请求数和允许的突发请求数
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_sec: u32,
+    pub burst: u32,
+}
+
+/// 令牌桶限流，同一个 layer 包装出来的 service 共用一个桶。
+/// 限流配置从 watch 通道读取，`None` 时不限流，重新加载配置后立即生效
+#[derive(Clone, Debug)]
+pub struct RateLimitLayer {
+    limit: watch::Receiver<Option<RateLimit>>,
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimitLayer {
+    /// 只有 axum 的 kv 路由和 hyper+tower 的 tower 中间件栈使用限流
+    #[cfg(any(
+        feature = "service-axum",
+        all(feature = "service-my", feature = "middleware-tower")
+    ))]
+    pub fn new(limit: watch::Receiver<Option<RateLimit>>) -> Self {
+        Self {
+            limit,
+            bucket: Arc::new(Mutex::new(TokenBucket::new())),
+        }
+    }
+}
+
+#[allow(unused)]
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limit: self.limit.clone(),
+            bucket: self.bucket.clone(),
+        }
+    }
+}
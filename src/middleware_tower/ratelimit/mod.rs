@@ -0,0 +1,11 @@
+mod body;
+mod future;
+mod layer;
+mod service;
+
+pub use body::RateLimitResponseBody;
+pub use body::create_too_many_requests_response;
+#[allow(unused_imports)]
+pub use layer::{RateLimit, RateLimitLayer};
+#[allow(unused_imports)]
+pub use service::RateLimitService;
@@ -0,0 +1,95 @@
+use crate::middleware_tower::ratelimit::body::RateLimitResponseBody;
+use crate::middleware_tower::ratelimit::future::RateLimitResponseFuture;
+use crate::middleware_tower::ratelimit::layer::RateLimit;
+use http::{Request, Response};
+use http_body::Body;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::sync::watch;
+use tower::Service;
+use tracing::{Level, event};
+
+/// 令牌桶，按经过的时间补充令牌，最多补到 `burst` 个
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    #[cfg(any(
+        feature = "service-axum",
+        all(feature = "service-my", feature = "middleware-tower")
+    ))]
+    pub(crate) fn new() -> Self {
+        Self {
+            tokens: f64::MAX,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// 取一个令牌，没有令牌时返回 false
+    fn acquire(&mut self, limit: RateLimit) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_sec as f64).min(limit.burst as f64);
+        self.refilled_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimitService<S> {
+    pub inner: S,
+    pub limit: watch::Receiver<Option<RateLimit>>,
+    pub(crate) bucket: Arc<Mutex<TokenBucket>>,
+}
+
+#[allow(unused)]
+impl<S> RateLimitService<S> {
+    /// Gets a reference to the underlying service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying service.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Consumes `self`, returning the underlying service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<ReqBody, ResBody, S> Service<Request<ReqBody>> for RateLimitService<S>
+where
+    ResBody: Body,
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = Response<RateLimitResponseBody<ResBody>>;
+    type Error = S::Error;
+    type Future = RateLimitResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let limit = *self.limit.borrow();
+        if let Some(limit) = limit
+            && !self.bucket.lock().unwrap().acquire(limit)
+        {
+            event!(target: "middleware::ratelimit", Level::WARN, per_sec = limit.per_sec, "Too many requests");
+            return RateLimitResponseFuture::too_many_requests();
+        }
+
+        RateLimitResponseFuture::new(self.inner.call(req))
+    }
+}
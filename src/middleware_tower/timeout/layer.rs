@@ -1,14 +1,16 @@
 use crate::middleware_tower::timeout::service::TimeoutService;
 use std::time::Duration;
+use tokio::sync::watch;
 use tower::Layer;
 
-#[derive(Debug, Clone, Copy)]
+/// 超时时间从 watch 通道读取，每个请求开始时取当前值，重新加载配置后对新请求生效
+#[derive(Debug, Clone)]
 pub struct TimeoutLayer {
-    timeout: Duration,
+    timeout: watch::Receiver<Duration>,
 }
 
 impl TimeoutLayer {
-    pub fn new(timeout: Duration) -> Self {
+    pub fn new(timeout: watch::Receiver<Duration>) -> Self {
         TimeoutLayer { timeout }
     }
 }
@@ -20,7 +22,7 @@ impl<S> Layer<S> for TimeoutLayer {
     fn layer(&self, inner: S) -> Self::Service {
         TimeoutService {
             inner,
-            timeout: self.timeout,
+            timeout: self.timeout.clone(),
         }
     }
 }
@@ -5,18 +5,19 @@ use http::{Request, Response};
 use http_body::Body;
 use std::task::{Context, Poll};
 use std::time::Duration;
+use tokio::sync::watch;
 use tokio::time;
 use tower::Service;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct TimeoutService<S> {
     pub inner: S,
-    pub timeout: Duration,
+    pub timeout: watch::Receiver<Duration>,
 }
 
 #[allow(unused)]
 impl<S> TimeoutService<S> {
-    pub fn new(inner: S, timeout: Duration) -> Self {
+    pub fn new(inner: S, timeout: watch::Receiver<Duration>) -> Self {
         Self { inner, timeout }
     }
 
@@ -38,7 +39,7 @@ impl<S> TimeoutService<S> {
     /// Returns a new [`Layer`] that wraps services with a `TimeoutLayer` middleware.
     ///
     /// [`Layer`]: tower_layer::Layer
-    pub fn layer(timeout: Duration) -> TimeoutLayer {
+    pub fn layer(timeout: watch::Receiver<Duration>) -> TimeoutLayer {
         TimeoutLayer::new(timeout)
     }
 }
@@ -57,8 +58,9 @@ where
     }
 
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        let time_duratiom = self.timeout.as_micros();
-        let sleep = time::sleep(self.timeout);
+        let timeout = *self.timeout.borrow();
+        let time_duratiom = timeout.as_micros();
+        let sleep = time::sleep(timeout);
 
         let fut = self.inner.call(req);
 
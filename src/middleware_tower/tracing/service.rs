@@ -22,6 +22,7 @@
 use crate::middleware_tower::tracing::body::TracingResponseBody;
 use crate::middleware_tower::tracing::future::TracingResponseFuture;
 use crate::middleware_tower::tracing::layer::TracingLayer;
+use crate::tls::PeerIdentity;
 use http::{Request, Response};
 use http_body::Body;
 use std::task::{Context, Poll};
@@ -78,7 +79,11 @@ where
     #[instrument(
         skip_all,
         name = "request",
-        fields(method = %req.method(), uri = %req.uri()),
+        fields(
+            method = %req.method(),
+            uri = %req.uri(),
+            peer = req.extensions().get::<PeerIdentity>().map(|peer| peer.subject.as_str()),
+        ),
     )]
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
         let method = req.method().to_string();
@@ -1,18 +1,312 @@
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
-use utoipa::ToSchema;
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Clone, Default, Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize, ToSchema)]
 pub struct KvPair {
     pub key: String,
-    pub value: String,
+    /// 字符串或任意 JSON 值，二进制值为 base64 字符串
+    pub value: Value,
+    /// 二进制值上传时的 Content-Type，JSON 值为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// 行版本号，每次更新加 1，作为 ETag
+    #[serde(default)]
+    pub version: i64,
+    /// 过期时间，为空表示永不过期
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+impl KvPair {
+    /// 是否已经过期，过期的 key 视为不存在
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= chrono::Utc::now())
+    }
+}
+
+/// `kv_history` 中的一条记录，每次写操作追加一条
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct KvHistoryEntry {
+    pub key: String,
+    /// 写入后的值，删除和过期清理时为删除前的值，二进制值为 base64 字符串
+    pub value: Value,
+    /// 二进制值的 Content-Type，JSON 值为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// 写入后的版本号，删除和过期清理时为删除前的版本号
+    pub version: i64,
+    /// 写操作类型：`create`、`update`、`delete` 或过期清理的 `expire`
+    pub operation: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub changed_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl KvHistoryEntry {
+    /// 历史记录对应的 key-value，更新时间为这次写入的时间
+    pub fn into_kv(self) -> KvPair {
+        KvPair {
+            key: self.key,
+            value: self.value,
+            content_type: self.content_type,
+            updated_at: self.changed_at,
+            version: self.version,
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+/// 一次写操作产生的变更事件，来自 `kv_history`
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct KvEvent {
+    /// 变更序号，所有 namespace 全局递增，作为 resume token
+    pub id: i64,
+    pub namespace: String,
+    /// 写操作类型：`create`、`update`、`delete` 或过期清理的 `expire`
+    pub operation: String,
+    /// 写入后的 key-value，删除和过期清理时为删除前的值
+    pub kv: KvPair,
+}
+
+/// `GET /kv/watch` 的查询参数
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WatchQuery {
+    /// 只关注以该前缀开头的 key
+    pub prefix: Option<String>,
+    /// resume token，从这个变更序号之后开始推送；不指定时只推送之后的新变更
+    pub after: Option<i64>,
+}
+
+/// `GET /kv/watch` 在 `kv_tower` 中的一页结果，按变更序号升序
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct KvEventPage {
+    pub events: Vec<KvEvent>,
+    /// 下一次请求的 `after`
+    pub after: i64,
+}
+
+/// `GET /kv/{key}` 的查询参数，都不指定时读取当前值
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetKvQuery {
+    /// 读取指定的历史版本，不能和 `at` 同时指定
+    pub version: Option<i64>,
+    /// 读取该时间点的值，不能和 `version` 同时指定
+    pub at: Option<chrono::DateTime<chrono::Utc>>,
+    /// JSON Pointer，只返回 value 中的这一部分，例如 `/a/b`
+    pub path: Option<String>,
+}
+
+impl GetKvQuery {
+    /// 是否读取历史值，历史值不经过缓存
+    pub fn is_history(&self) -> bool {
+        self.version.is_some() || self.at.is_some()
+    }
+}
+
+/// `GET /kv/{key}/history` 的查询参数
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// 最多返回多少条，默认 20，最大 100
+    pub limit: Option<u32>,
+}
+
+/// `POST /kv/{key}/restore` 的请求体
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct RestoreKv {
+    /// 回滚到的历史版本
+    pub version: i64,
+}
+
+/// `POST /kv/{key}/incr` 的请求体
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct IncrKv {
+    /// 增加的值，可以为负数，默认 1
+    #[serde(default = "default_delta")]
+    pub delta: i64,
+}
+
+fn default_delta() -> i64 {
+    1
+}
+
+/// `POST /kv/{key}/cas` 的请求体
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CasKv {
+    /// 期望的当前值，为空表示期望 key 不存在
+    #[serde(default)]
+    pub expected: Option<Value>,
+    /// 当前值和期望一致时写入的新值
+    pub value: Value,
+}
+
+/// `GET /ns/{namespace}/usage` 的结果
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct NamespaceUsage {
+    pub namespace: String,
+    /// 未过期的 key 数量
+    pub keys: i64,
+    /// 未过期的 key 和 value 的总字节数
+    pub bytes: i64,
+    /// key 数量配额，为空表示不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_keys: Option<u64>,
+    /// 字节数配额，为空表示不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+}
+
+/// `GET /kv` 的查询参数
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListKvQuery {
+    /// 只列出以该前缀开头的 key
+    pub prefix: Option<String>,
+    /// 每页数量，默认 20，最大 100
+    pub limit: Option<u32>,
+    /// 上一页返回的 `next_cursor`，从这个 key 之后开始列出
+    pub cursor: Option<String>,
+}
+
+/// `GET /kv` 的一页结果，按 key 升序
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct KvPage {
+    pub items: Vec<KvPair>,
+    /// 还有下一页时返回，作为下一次请求的 `cursor`
+    pub next_cursor: Option<String>,
+}
+
+/// `PUT /kv/{key}` 的查询参数
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UpdateKvQuery {
+    /// 为 true 时 key 不存在会新建（返回 201），默认只更新已存在的 key
+    #[serde(default)]
+    pub upsert: bool,
+    /// 多少秒后过期，不能和 `expires_at`、`persist` 同时指定；都不指定时保留原来的过期时间
+    pub ttl_seconds: Option<u64>,
+    /// 过期时间，不能和 `ttl_seconds`、`persist` 同时指定
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// 为 true 时清除过期时间，key 之后永不过期
+    #[serde(default)]
+    pub persist: bool,
+}
+
+/// 批量读取或删除的请求体
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchKeys {
+    pub keys: Vec<String>,
+}
+
+/// 批量新建的请求体
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchSetKv {
+    pub items: Vec<CreateKv>,
+}
+
+/// 批量操作中单个 key 的结果，`status` 与单个操作接口的 HTTP 状态码一致
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchItemResult {
+    pub key: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub item: Option<KvPair>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 批量操作的结果，和请求中的 key 一一对应
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchResult {
+    pub results: Vec<BatchItemResult>,
+}
+
+/// `GET /admin/export` 的查询参数
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// 从这个 key 之后开始导出，用于中断后继续
+    pub after: Option<String>,
+}
+
+/// 导入时遇到已存在的 key 的处理方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// 保留已存在的值
+    Skip,
+    /// 用导入的值覆盖
+    Overwrite,
+    /// 回滚当前块并停止导入
+    #[default]
+    Fail,
+}
+
+/// `POST /admin/import` 的查询参数
+#[derive(Clone, Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    /// 已存在的 key 的处理方式，默认 `fail`
+    #[serde(default)]
+    #[param(inline)]
+    pub mode: ImportMode,
+}
+
+/// 导入时被跳过的一行
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportLineError {
+    /// 行号，从 1 开始
+    pub line: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub error: String,
+}
+
+/// 导入中途停止的原因，`status` 与单个操作接口的 HTTP 状态码一致
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportAbort {
+    /// 导致停止的行，读取请求体或数据库出错时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+    pub status: u16,
+    pub error: String,
+}
+
+/// `POST /admin/import` 的结果
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ImportReport {
+    /// 新建或覆盖的 key 数量
+    pub imported: u64,
+    /// 已存在而保留原值的 key 数量
+    pub skipped: u64,
+    /// 格式或校验错误而跳过的行数
+    pub failed: u64,
+    /// 跳过的行和原因，最多列出前 1000 行
+    pub errors: Vec<ImportLineError>,
+    /// 中途停止时不为空，之前的块已经提交，停止时所在的块整体回滚
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aborted: Option<ImportAbort>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
 pub struct CreateKv {
     pub key: String,
-    pub value: String,
+    /// 字符串或任意 JSON 值，指定 `content_type` 时为二进制内容的 base64 编码
+    pub value: Value,
+    /// 指定时 value 作为二进制值保存，读取时以该 Content-Type 原样返回
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// 多少秒后过期，不能和 `expires_at` 同时指定
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+    /// 过期时间，不能和 `ttl_seconds` 同时指定
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 // 接触Debug实现Display
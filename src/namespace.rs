@@ -0,0 +1,224 @@
+//! 多租户 namespace 和配额，`kv_axum` 和 `kv_tower` 共用。
+//!
+//! 每个 namespace 是独立的 key 空间，通过 `/ns/{namespace}/kv/...` 访问，
+//! 不带前缀的 `/kv/...` 访问 [`DEFAULT_NAMESPACE`]。存储按 `(namespace, key)` 区分，
+//! 缓存 key 也带上 namespace，见 [`cache_key`](crate::cache_aside::cache_key)。
+//!
+//! 配额在写接口里写数据库之前检查：先统计 namespace 当前的用量，再加上这次写入带来的增量。
+//! 检查和写入都在 namespace 的配额锁（[`KvStore::lock_quota`]）里进行，并发写入不会一起超出配额。
+use crate::{
+    error::AppError,
+    kv_value::{self, KEY_MAX_LEN},
+    models::NamespaceUsage,
+    store::KvStore,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use tracing::{instrument, warn};
+
+/// 不带 `/ns/{namespace}` 前缀的请求使用的 namespace
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// namespace 和 key 的命名规则相同
+pub fn validate(namespace: &str) -> Result<(), AppError> {
+    if namespace.is_empty()
+        || namespace.len() > KEY_MAX_LEN
+        || !namespace.chars().all(|c| c.is_alphanumeric() || c == '_')
+    {
+        return Err(AppError::InvalidInput("Invalid namespace".into()));
+    }
+    Ok(())
+}
+
+/// 拆出路径里的 `/ns/{namespace}` 前缀，没有前缀时返回 [`DEFAULT_NAMESPACE`] 和原路径
+pub fn split_path(path: &str) -> Result<(&str, &str), AppError> {
+    let Some(rest) = path.strip_prefix("/ns/") else {
+        return Ok((DEFAULT_NAMESPACE, path));
+    };
+    let (namespace, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    validate(namespace)?;
+    Ok((namespace, path))
+}
+
+/// 每个 namespace 的配额，为空表示不限制
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NamespaceQuota {
+    pub max_keys: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl NamespaceQuota {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_keys.is_none() && self.max_bytes.is_none()
+    }
+
+    /// namespace 当前的用量和配额
+    #[instrument(skip(db), target = "service::kv")]
+    pub async fn usage(&self, db: &dyn KvStore) -> Result<NamespaceUsage, AppError> {
+        let usage = db.usage().await?;
+        Ok(NamespaceUsage {
+            max_keys: self.max_keys,
+            max_bytes: self.max_bytes,
+            ..usage
+        })
+    }
+
+    /// 持有 namespace 的配额锁检查 `writes` 是否超出配额，没有超出时执行 `write`，
+    /// 写入完成后才释放锁。不限制配额时直接写入，不加锁
+    pub async fn write<T>(
+        &self,
+        db: &dyn KvStore,
+        writes: &[(&str, usize)],
+        write: impl Future<Output = Result<T, AppError>>,
+    ) -> Result<T, AppError> {
+        if self.is_unlimited() {
+            return write.await;
+        }
+        let lock = db.lock_quota().await?;
+        let result = match self.check(db, writes).await {
+            Ok(()) => write.await,
+            Err(e) => Err(e),
+        };
+        lock.release().await;
+        result
+    }
+
+    /// 检查写入 `writes`（key 和新 value 的字节数）之后是否超出配额，
+    /// key 数量超出时返回 [`AppError::KeyQuotaExceeded`]，字节数超出时返回 [`AppError::SizeQuotaExceeded`]
+    ///
+    /// 覆盖已有的 key 只计算 value 大小的变化，不减少用量的写入总是允许，
+    /// 用量已经超出配额时仍然可以更新为更小的值。
+    #[instrument(skip(db, writes), target = "service::kv")]
+    pub async fn check(&self, db: &dyn KvStore, writes: &[(&str, usize)]) -> Result<(), AppError> {
+        if self.is_unlimited() {
+            return Ok(());
+        }
+
+        // 同一个 key 写多次时以最后一次为准
+        let writes: HashMap<&str, usize> = writes.iter().copied().collect();
+        let keys: Vec<String> = writes.keys().map(|key| key.to_string()).collect();
+        let current: HashMap<String, usize> = db
+            .get_many(&keys)
+            .await?
+            .into_iter()
+            .map(|kv| {
+                let size = kv_value::size(&kv.value, kv.content_type.as_deref());
+                (kv.key, size)
+            })
+            .collect();
+        let (mut added_keys, mut added_bytes) = (0i64, 0i64);
+        for (key, size) in &writes {
+            match current.get(*key) {
+                Some(old_size) => added_bytes += *size as i64 - *old_size as i64,
+                None => {
+                    added_keys += 1;
+                    added_bytes += (key.len() + size) as i64;
+                }
+            }
+        }
+
+        let usage = db.usage().await?;
+        if let Some(max_keys) = self.max_keys
+            && added_keys > 0
+            && usage.keys + added_keys > max_keys as i64
+        {
+            warn!(target: "service::kv", namespace = db.namespace(), usage.keys, max_keys, "⚠️ key quota exceeded");
+            return Err(AppError::KeyQuotaExceeded(format!(
+                "namespace {} is limited to {} keys",
+                db.namespace(),
+                max_keys
+            )));
+        }
+        if let Some(max_bytes) = self.max_bytes
+            && added_bytes > 0
+            && usage.bytes + added_bytes > max_bytes as i64
+        {
+            warn!(target: "service::kv", namespace = db.namespace(), usage.bytes, max_bytes, "⚠️ size quota exceeded");
+            return Err(AppError::SizeQuotaExceeded(format!(
+                "namespace {} is limited to {} bytes",
+                db.namespace(),
+                max_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::CreateKv, store::MemoryStore};
+
+    #[tokio::test]
+    async fn namespaces_are_isolated_and_quotas_are_per_namespace() {
+        let root = MemoryStore::new();
+        let (a, b) = (root.scoped("team_a"), root.scoped("team_b"));
+        for db in [&a, &b] {
+            db.set(CreateKv {
+                key: "k".to_string(),
+                value: db.namespace().into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        assert_eq!(a.get("k").await.unwrap().unwrap().value, "team_a");
+        assert_eq!(b.get("k").await.unwrap().unwrap().value, "team_b");
+        assert!(root.get("k").await.unwrap().is_none());
+        assert_eq!(root.scoped("team_a").usage().await.unwrap().keys, 1);
+
+        let quota = NamespaceQuota {
+            max_keys: Some(2),
+            max_bytes: Some(20),
+        };
+        assert!(matches!(
+            quota.check(a.as_ref(), &[("k", 100)]).await,
+            Err(AppError::SizeQuotaExceeded(_))
+        ));
+        quota
+            .check(a.as_ref(), &[("k", 1), ("k2", 1)])
+            .await
+            .unwrap();
+        assert!(matches!(
+            quota.check(a.as_ref(), &[("k2", 1), ("k3", 1)]).await,
+            Err(AppError::KeyQuotaExceeded(_))
+        ));
+        // 其他 namespace 的用量不影响
+        quota.check(b.as_ref(), &[("k2", 1)]).await.unwrap();
+
+        assert_eq!(split_path("/ns/team_a/kv/k").unwrap(), ("team_a", "/kv/k"));
+        assert_eq!(split_path("/kv/k").unwrap(), (DEFAULT_NAMESPACE, "/kv/k"));
+        assert!(split_path("/ns/bad-name/kv/k").is_err());
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_do_not_exceed_quota_together() {
+        let db = MemoryStore::new();
+        let quota = NamespaceQuota {
+            max_keys: Some(3),
+            max_bytes: None,
+        };
+        let writes = (0..10).map(|i| {
+            let (db, key) = (&db, format!("k{}", i));
+            async move {
+                let write = async {
+                    // 写入之前让出，没有配额锁时所有检查都会先于写入通过
+                    tokio::task::yield_now().await;
+                    db.set(CreateKv {
+                        key: key.clone(),
+                        value: "v".into(),
+                        ..Default::default()
+                    })
+                    .await
+                };
+                quota.write(db, &[(&key, 1)], write).await
+            }
+        });
+        let results = futures::future::join_all(writes).await;
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 3);
+        assert_eq!(db.usage().await.unwrap().keys, 3);
+    }
+}
@@ -1,6 +1,10 @@
 use crate::{
     appv2::{EchoRequest, EchoResponse},
-    models::{CreateKv, KvPair},
+    models::{
+        BatchItemResult, BatchKeys, BatchResult, BatchSetKv, CasKv, CreateKv, ImportAbort,
+        ImportLineError, ImportMode, ImportReport, IncrKv, KvEvent, KvHistoryEntry, KvPage, KvPair,
+        NamespaceUsage, RestoreKv,
+    },
 };
 use utoipa::OpenApi;
 
@@ -13,9 +17,46 @@ use utoipa::OpenApi;
         crate::kv_axum::set_kv,
         crate::kv_axum::update_kv,
         crate::kv_axum::get_kv,
-        crate::kv_axum::delete_kv
+        crate::kv_axum::patch_kv,
+        crate::kv_axum::delete_kv,
+        crate::kv_axum::kv_history,
+        crate::kv_axum::restore_kv,
+        crate::kv_axum::incr_kv,
+        crate::kv_axum::cas_kv,
+        crate::kv_axum::list_kv,
+        crate::kv_axum::batch_get_kv,
+        crate::kv_axum::batch_set_kv,
+        crate::kv_axum::batch_delete_kv,
+        crate::kv_axum::watch_kv,
+        crate::kv_axum::watch_kv_ws,
+        crate::kv_axum::export_kv,
+        crate::kv_axum::import_kv,
+        crate::kv_axum::namespace_usage,
+        crate::kv_axum::get_schema,
+        crate::kv_axum::put_schema,
+        crate::kv_axum::delete_schema
     ),
-    components(schemas(EchoRequest, EchoResponse, CreateKv, KvPair)),
+    components(schemas(
+        EchoRequest,
+        EchoResponse,
+        CreateKv,
+        KvPair,
+        KvPage,
+        KvHistoryEntry,
+        KvEvent,
+        RestoreKv,
+        IncrKv,
+        CasKv,
+        BatchKeys,
+        BatchSetKv,
+        BatchItemResult,
+        BatchResult,
+        NamespaceUsage,
+        ImportMode,
+        ImportReport,
+        ImportLineError,
+        ImportAbort
+    )),
     info(
         title = "Combined Echo and Key-Value Store API",
         version = "1.0.0",
@@ -0,0 +1,295 @@
+//! 收到 SIGHUP 时重新读取配置，替换可以在运行时修改的配置项，已有的连接不受影响。
+//!
+//! 可以修改的是终端日志级别、中间件的超时、限流和 Auth-Key，以及缓存的过期时间等选项。
+//! 新配置必须通过校验，并且不能修改其他需要重启才能生效的配置项（监听地址、数据库等），
+//! 否则整个重新加载被拒绝，继续使用当前配置。
+use crate::{
+    cache_aside::CacheAside,
+    config::{Cli, Config, ConfigChange, ConfigError, MiddlewareConfig},
+    init_opentelemetry::LogLevelHandle,
+    middleware_tower::ratelimit::RateLimit,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// 当前编译的服务和中间件组合实际读取的 `middleware.` 配置项，
+/// 其他中间件配置项修改后没有效果：`middleware-my`、`middleware-axum` 的中间件在启动时固定，
+/// `kv_timeout_ms` 只用于 hyper+tower 的 kv 服务
+const APPLIED_MIDDLEWARE: &[&str] = &[
+    #[cfg(all(feature = "service-my", feature = "middleware-tower"))]
+    "middleware.kv_timeout_ms",
+    #[cfg(all(feature = "service-axum", feature = "middleware-tower"))]
+    "middleware.echo_timeout_ms",
+    #[cfg(all(feature = "service-axum", feature = "middleware-tower"))]
+    "middleware.auth_keys",
+    #[cfg(any(
+        feature = "service-axum",
+        all(feature = "service-my", feature = "middleware-tower")
+    ))]
+    "middleware.rate_limit_per_sec",
+    #[cfg(any(
+        feature = "service-axum",
+        all(feature = "service-my", feature = "middleware-tower")
+    ))]
+    "middleware.rate_limit_burst",
+];
+
+/// 可以在运行时修改的配置项，以 `.` 结尾的表示整个 section
+const RELOADABLE: &[&str] = &[
+    "middleware.",
+    "cache.ttl_secs",
+    "cache.negative_ttl_secs",
+    "cache.fill_lock",
+    "cache.max_value_bytes",
+    "telemetry.log_level",
+];
+
+#[derive(Debug, Error)]
+pub enum ReloadError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("changing {} requires a restart", .0.join(", "))]
+    RestartRequired(Vec<String>),
+    #[error("Failed to change log level: {0}")]
+    LogLevel(#[from] tracing_subscriber::reload::Error),
+}
+
+/// 中间件从这些通道读取当前的配置，每个请求开始时取一次
+pub struct MiddlewareSettings {
+    pub kv_timeout: watch::Sender<Duration>,
+    pub echo_timeout: watch::Sender<Duration>,
+    pub auth_keys: watch::Sender<Vec<String>>,
+    pub rate_limit: watch::Sender<Option<RateLimit>>,
+}
+
+impl MiddlewareSettings {
+    pub fn new(config: &MiddlewareConfig) -> Self {
+        Self {
+            kv_timeout: watch::Sender::new(config.kv_timeout()),
+            echo_timeout: watch::Sender::new(config.echo_timeout()),
+            auth_keys: watch::Sender::new(config.auth_keys.clone()),
+            rate_limit: watch::Sender::new(config.rate_limit()),
+        }
+    }
+
+    fn apply(&self, config: &MiddlewareConfig) {
+        self.kv_timeout.send_replace(config.kv_timeout());
+        self.echo_timeout.send_replace(config.echo_timeout());
+        self.auth_keys.send_replace(config.auth_keys.clone());
+        self.rate_limit.send_replace(config.rate_limit());
+    }
+}
+
+pub struct Reloader {
+    /// 启动时的命令行参数和环境变量，重新加载时仍然覆盖配置文件
+    cli: Cli,
+    current: Config,
+    log_level: LogLevelHandle,
+    cache_aside: Arc<CacheAside>,
+    middleware: MiddlewareSettings,
+}
+
+impl Reloader {
+    pub fn new(
+        cli: Cli,
+        current: Config,
+        log_level: LogLevelHandle,
+        cache_aside: Arc<CacheAside>,
+        middleware: MiddlewareSettings,
+    ) -> Self {
+        Self {
+            cli,
+            current,
+            log_level,
+            cache_aside,
+            middleware,
+        }
+    }
+
+    /// 重新加载一次配置并记录结果，返回生效的变化
+    ///
+    /// 当前编译的中间件不读取的配置项（见 [`APPLIED_MIDDLEWARE`]）仍然记下，但是单独警告，不算生效的变化
+    pub fn reload(&mut self) -> Result<Vec<ConfigChange>, ReloadError> {
+        let result = self.load().and_then(|(config, changes)| {
+            let (changes, ignored): (Vec<_>, Vec<_>) =
+                changes.into_iter().partition(|change| applied(&change.key));
+            if !ignored.is_empty() {
+                let keys = ignored.iter().map(|change| change.key.as_str());
+                warn!(target: "server::reload", keys = %keys.collect::<Vec<_>>().join(", "), "⚠️ config changes have no effect in this build");
+            }
+            // 先记录再替换，新的日志级别可能不再输出 info
+            if changes.is_empty() {
+                info!(target: "server::reload", "🔄 config reloaded, nothing changed");
+            } else {
+                let diff = changes.iter().map(ToString::to_string).collect::<Vec<_>>();
+                info!(target: "server::reload", changes = %diff.join("; "), "✅ config reload accepted");
+            }
+            if !changes.is_empty() || !ignored.is_empty() {
+                self.apply(config)?;
+            }
+            Ok(changes)
+        });
+        if let Err(e) = &result {
+            warn!(target: "server::reload", error = %e, "⚠️ config reload rejected, keep current config");
+        }
+        result
+    }
+
+    /// 读取并校验新配置，只修改了可以在运行时修改的配置项时返回新配置和变化
+    fn load(&self) -> Result<(Config, Vec<ConfigChange>), ReloadError> {
+        let config = Config::from_cli(self.cli.clone())?;
+        let changes = self.current.diff(&config);
+        let restart_required: Vec<String> = changes
+            .iter()
+            .filter(|change| !reloadable(&change.key))
+            .map(|change| change.key.clone())
+            .collect();
+        if !restart_required.is_empty() {
+            return Err(ReloadError::RestartRequired(restart_required));
+        }
+        Ok((config, changes))
+    }
+
+    fn apply(&mut self, config: Config) -> Result<(), ReloadError> {
+        self.log_level.reload(config.telemetry.log_level()?)?;
+        self.middleware.apply(&config.middleware);
+        self.cache_aside
+            .set_options(config.cache.cache_aside_options());
+        self.current = config;
+        Ok(())
+    }
+}
+
+fn reloadable(key: &str) -> bool {
+    RELOADABLE
+        .iter()
+        .any(|prefix| match prefix.strip_suffix('.') {
+            Some(section) => key.starts_with(prefix) || key == section,
+            None => key == *prefix,
+        })
+}
+
+/// 中间件配置项只有当前编译的中间件读取时才生效，其他可以重新加载的配置项总是生效
+fn applied(key: &str) -> bool {
+    !key.starts_with("middleware.") || APPLIED_MIDDLEWARE.contains(&key)
+}
+
+/// 启动重新加载任务，每次收到 SIGHUP 重新加载一次配置
+pub fn spawn(mut reloader: Reloader) -> std::io::Result<JoinHandle<()>> {
+    let mut sighup = signal(SignalKind::hangup())?;
+    Ok(tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            info!(target: "server::reload", "Received SIGHUP, reloading config");
+            let _ = reloader.reload();
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache_aside::CacheAsideOptions;
+    use clap::Parser;
+    use std::path::PathBuf;
+    use tracing_subscriber::{Registry, filter::LevelFilter, reload};
+
+    /// 日志级别的 reload layer，丢弃之后 [`LogLevelHandle`] 无法再修改日志级别
+    type LevelLayer = reload::Layer<LevelFilter, Registry>;
+
+    /// 用临时配置文件 `name` 启动的 Reloader，命令行参数 `--kv-timeout-ms 800` 覆盖配置文件
+    fn reloader(name: &str) -> (PathBuf, Reloader, LevelLayer, LogLevelHandle) {
+        let path =
+            std::env::temp_dir().join(format!("kv-reload-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, "[cache]\nttl_secs = 300\n").unwrap();
+        let config_arg = format!("--config={}", path.display());
+        let cli = Cli::try_parse_from(["kv", &config_arg, "--kv-timeout-ms", "800"]).unwrap();
+        let config = Config::from_cli(cli.clone()).unwrap();
+
+        let (level_layer, log_level) = LevelLayer::new(LevelFilter::INFO);
+        let cache_aside = Arc::new(CacheAside::new(CacheAsideOptions::default()));
+        let middleware = MiddlewareSettings::new(&config.middleware);
+        let mut reloader = Reloader::new(cli, config, log_level.clone(), cache_aside, middleware);
+        assert!(reloader.reload().unwrap().is_empty());
+        (path, reloader, level_layer, log_level)
+    }
+
+    #[test]
+    fn reload_swaps_runtime_settings() {
+        let (path, mut reloader, _level_layer, log_level) = reloader("swap");
+        std::fs::write(
+            &path,
+            r#"
+            [cache]
+            ttl_secs = 60
+            [middleware]
+            kv_timeout_ms = 600
+            auth_keys = ["secret"]
+            rate_limit_per_sec = 5
+            [telemetry]
+            log_level = "debug"
+            "#,
+        )
+        .unwrap();
+        let changes = reloader.reload().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // 命令行参数在重新加载后仍然覆盖配置文件，kv_timeout_ms 没有变化
+        let changes: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            changes,
+            [
+                "cache.ttl_secs: 300 -> 60",
+                "middleware.auth_keys: *** -> ***",
+                "middleware.rate_limit_per_sec: unset -> 5",
+                "telemetry.log_level: \"info\" -> \"debug\"",
+            ]
+        );
+        let middleware = &reloader.middleware;
+        assert_eq!(reloader.cache_aside.options().ttl_secs, 60);
+        assert_eq!(*middleware.kv_timeout.borrow(), Duration::from_millis(800));
+        assert_eq!(*middleware.auth_keys.borrow(), ["secret"]);
+        assert_eq!(
+            *middleware.rate_limit.borrow(),
+            Some(RateLimit {
+                per_sec: 5,
+                burst: 5
+            })
+        );
+        assert_eq!(
+            log_level.with_current(|level| *level).unwrap(),
+            LevelFilter::DEBUG
+        );
+    }
+
+    #[test]
+    fn reload_rejects_restart_only_and_invalid_changes() {
+        let (path, mut reloader, _level_layer, _log_level) = reloader("reject");
+        std::fs::write(&path, "[server]\nlisten = \"0.0.0.0:8080\"\n").unwrap();
+        assert!(matches!(
+            reloader.reload(),
+            Err(ReloadError::RestartRequired(keys)) if keys == ["server.listen"]
+        ));
+        std::fs::write(&path, "[cache]\nttl_secs = 0\n").unwrap();
+        assert!(matches!(reloader.reload(), Err(ReloadError::Config(_))));
+        std::fs::remove_file(&path).unwrap();
+        // 都不生效，继续使用原来的配置
+        assert_eq!(reloader.cache_aside.options().ttl_secs, 300);
+        assert_eq!(
+            reloader.current.server.listen,
+            Config::default().server.listen
+        );
+    }
+
+    #[test]
+    fn only_middleware_keys_read_by_this_build_count_as_applied() {
+        assert!(applied("cache.ttl_secs") && applied("middleware.rate_limit_per_sec"));
+        // axum 的 kv 路由没有请求超时，修改 kv_timeout_ms 没有效果，不算生效的变化
+        #[cfg(feature = "service-axum")]
+        assert!(!applied("middleware.kv_timeout_ms"));
+    }
+}
@@ -0,0 +1,222 @@
+//! 优雅退出：停止接受新连接后通知每个连接开始 graceful shutdown，
+//! 空闲的 keep-alive 连接马上关闭，正在处理的请求处理完后关闭。
+//! 超过 drain 期限还没有关闭的连接被直接中断，不会因为浏览器等客户端一直保持连接而无法退出。
+//!
+//! 还在 TLS 握手的连接在开始退出时直接放弃；中断之后最多再等 [`ABORT_WAIT`]，
+//! 退出时间不会超过 drain 期限太多。
+//!
+//! 活跃连接数放在 watch 通道里，连接结束时减一，等待方不需要轮询。
+use hyper_util::server::graceful::GracefulConnection;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// 中断连接之后等待它们退出的时间，被中断的连接通常在下一次被调度时就退出
+const ABORT_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Serving,
+    /// 通知连接处理完当前的请求后关闭
+    Draining,
+    /// drain 期限已过，中断所有连接
+    Aborting,
+}
+
+pub struct Shutdown {
+    stage: watch::Sender<Stage>,
+    active: watch::Sender<usize>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            stage: watch::Sender::new(Stage::Serving),
+            active: watch::Sender::new(0),
+        }
+    }
+
+    /// 跟踪一个新连接，返回的 guard 在连接结束（包括被中断）时释放
+    pub fn track(&self) -> ConnectionGuard {
+        self.active.send_modify(|n| *n += 1);
+        ConnectionGuard {
+            stage: self.stage.subscribe(),
+            active: self.active.clone(),
+        }
+    }
+
+    /// 通知所有连接开始 graceful shutdown，等待它们在 `deadline` 内关闭，
+    /// 超时后中断剩下的连接，返回被中断的连接数
+    pub async fn drain(&self, deadline: Duration) -> usize {
+        let mut active = self.active.subscribe();
+        info!(target: "server::shutdown", connections = *active.borrow(), ?deadline, "Draining connections");
+        self.stage.send_replace(Stage::Draining);
+        if tokio::time::timeout(deadline, active.wait_for(|n| *n == 0))
+            .await
+            .is_ok()
+        {
+            info!(target: "server::shutdown", "All connections closed");
+            return 0;
+        }
+
+        let remaining = *active.borrow();
+        warn!(target: "server::shutdown", connections = remaining, "⚠️ drain deadline exceeded, aborting connections");
+        self.stage.send_replace(Stage::Aborting);
+        // 被中断的连接在下一次被调度时退出，不会再等待 IO
+        if tokio::time::timeout(ABORT_WAIT, active.wait_for(|n| *n == 0))
+            .await
+            .is_err()
+        {
+            warn!(target: "server::shutdown", connections = *active.borrow(), "⚠️ connections still open after abort, exiting anyway");
+        }
+        remaining
+    }
+}
+
+pub struct ConnectionGuard {
+    stage: watch::Receiver<Stage>,
+    active: watch::Sender<usize>,
+}
+
+impl ConnectionGuard {
+    /// 开始处理请求之前的步骤（TLS 握手），开始退出时放弃并返回 `None`，
+    /// 慢的或者半开的客户端不会拖住退出
+    #[allow(clippy::manual_async_fn)]
+    pub fn handshake<F>(&mut self, handshake: F) -> impl Future<Output = Option<F::Output>> + Send
+    where
+        F: Future + Send,
+    {
+        async move {
+            tokio::select! {
+                result = handshake => Some(result),
+                _ = self.reached(Stage::Draining) => None,
+            }
+        }
+    }
+
+    /// 处理连接直到它结束；开始退出时通知连接 graceful shutdown，超过期限后直接丢弃连接。
+    /// 被中断时返回 `Ok(())`。
+    ///
+    /// 写成 async fn 时编译器在 `tokio::spawn` 里推断不出 future 满足 Send，这里显式标注
+    #[allow(clippy::manual_async_fn)]
+    pub fn serve<C>(mut self, conn: C) -> impl Future<Output = Result<(), C::Error>> + Send
+    where
+        C: GracefulConnection + Send,
+    {
+        async move {
+            tokio::pin!(conn);
+            tokio::select! {
+                result = conn.as_mut() => return result,
+                _ = self.reached(Stage::Draining) => {}
+            }
+            conn.as_mut().graceful_shutdown();
+            tokio::select! {
+                result = conn => result,
+                _ = self.reached(Stage::Aborting) => Ok(()),
+            }
+        }
+    }
+
+    /// 等待进入 `stage`，Shutdown 被丢弃时一直等待，不当作退出信号
+    async fn reached(&mut self, stage: Stage) {
+        while *self.stage.borrow_and_update() < stage {
+            if self.stage.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.active.send_modify(|n| *n -= 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Empty;
+    use hyper::body::{Bytes, Incoming};
+    use hyper::{Request, Response};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto;
+    use std::convert::Infallible;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// 起一个服务，`/slow` 需要 `slow` 时间才返回，返回服务地址
+    async fn serve(shutdown: &Shutdown, connections: usize, slow: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut guards = Vec::new();
+        for _ in 0..connections {
+            guards.push(shutdown.track());
+        }
+        tokio::spawn(async move {
+            for guard in guards {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let service =
+                        hyper::service::service_fn(move |req: Request<Incoming>| async move {
+                            if req.uri().path() == "/slow" {
+                                tokio::time::sleep(slow).await;
+                            }
+                            Ok::<_, Infallible>(Response::new(Empty::<Bytes>::new()))
+                        });
+                    let builder = auto::Builder::new(TokioExecutor::new());
+                    let conn = builder.serve_connection(TokioIo::new(stream), service);
+                    let _ = guard.serve(conn).await;
+                });
+            }
+        });
+        addr
+    }
+
+    async fn request(stream: &mut TcpStream, path: &str) -> String {
+        let req = format!("GET {} HTTP/1.1\r\nHost: kv\r\n\r\n", path);
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut buf = vec![0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    }
+
+    #[tokio::test]
+    async fn drain_closes_idle_connections_and_aborts_after_deadline() {
+        // 空闲的 keep-alive 连接马上关闭，正在处理的请求处理完
+        let shutdown = Shutdown::new();
+        let addr = serve(&shutdown, 2, Duration::from_millis(200)).await;
+        let mut idle = TcpStream::connect(&addr).await.unwrap();
+        assert!(request(&mut idle, "/").await.starts_with("HTTP/1.1 200"));
+        let mut busy = TcpStream::connect(&addr).await.unwrap();
+        let in_flight = tokio::spawn(async move { request(&mut busy, "/slow").await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(shutdown.drain(Duration::from_secs(5)).await, 0);
+        assert!(in_flight.await.unwrap().starts_with("HTTP/1.1 200"));
+        assert_eq!(idle.read(&mut [0; 16]).await.unwrap(), 0);
+
+        // 超过期限还在处理的请求被中断
+        let shutdown = Shutdown::new();
+        let addr = serve(&shutdown, 1, Duration::from_secs(60)).await;
+        let mut busy = TcpStream::connect(&addr).await.unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\nHost: kv\r\n\r\n")
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(shutdown.drain(Duration::from_millis(100)).await, 1);
+        assert_eq!(busy.read(&mut [0; 16]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn drain_does_not_wait_for_pending_handshakes() {
+        let shutdown = Shutdown::new();
+        let mut guard = shutdown.track();
+        let handshake =
+            tokio::spawn(async move { guard.handshake(std::future::pending::<()>()).await });
+        let started = std::time::Instant::now();
+        assert_eq!(shutdown.drain(Duration::from_secs(5)).await, 0);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(handshake.await.unwrap(), None);
+    }
+}
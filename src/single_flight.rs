@@ -0,0 +1,56 @@
+use crate::error::AppError;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+use tracing::info;
+
+/// 进程内的请求合并：同一个 key 同时只有一个调用真正执行，其他并发调用等待并共享结果。
+///
+/// 执行失败时结果不共享，等待者中的下一个会重新执行。
+pub struct SingleFlight<T> {
+    calls: Mutex<HashMap<String, Arc<OnceCell<T>>>>,
+}
+
+impl<T> Default for SingleFlight<T> {
+    fn default() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn run<F, Fut>(&self, key: &str, f: F) -> Result<T, AppError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        let call = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.get(key) {
+                Some(call) => {
+                    info!(target: "service::single_flight", %key, "⏳ join in-flight call");
+                    call.clone()
+                }
+                None => {
+                    let call = Arc::new(OnceCell::new());
+                    calls.insert(key.to_string(), call.clone());
+                    call
+                }
+            }
+        };
+
+        let result = call.get_or_try_init(f).await.cloned();
+
+        // 第一个完成的调用负责清理，之后到达的请求会开始新的一轮
+        let mut calls = self.calls.lock().unwrap();
+        if calls.get(key).is_some_and(|c| Arc::ptr_eq(c, &call)) {
+            calls.remove(key);
+        }
+        result
+    }
+}
@@ -0,0 +1,1068 @@
+//! 基于 SQLite 的 [`KvStore`] 实现，用于单机部署和 CI，`DATABASE_URL` 以 `sqlite:` 开头时使用。
+//!
+//! 语义和 Postgres 的 [`DBClient`](crate::db::DBClient) 一致：已过期的行按不存在处理，
+//! 每次写入都在同一个事务里追加历史记录。SQLite 不支持带写操作的 CTE，
+//! 所以写入和历史记录是同一个事务里的两条语句；写事务用 `BEGIN IMMEDIATE` 在开始时拿到写锁，
+//! 事务之间完全串行，变更序号的顺序就是提交顺序。
+//!
+//! 变更通知只在进程内传递，其他进程写入同一个数据库文件时，watch 要等到本进程下一次写入才会读到。
+use crate::{
+    db::{ChangeRow, DBPoolOptions, HistoryRow, KvRow, columns},
+    error::AppError,
+    models::{CreateKv, ImportMode, KvEvent, KvHistoryEntry, KvPair, NamespaceUsage},
+    namespace::DEFAULT_NAMESPACE,
+    store::{Expiry, KvStore, QuotaLock, incremented, resolve_expiry},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{
+    Sqlite, SqliteConnection, SqlitePool, Transaction,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    types::Json,
+};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::{Mutex, watch};
+use tracing::instrument;
+
+/// 新建 key，已过期的行按不存在处理，直接覆盖；未过期的行冲突时不返回行。
+/// 版本号从历史记录里最大的版本号继续递增，删除后重建的 key 不会复用旧的版本号
+///
+/// 参数依次为 key、value、data、content_type、expires_at、namespace 和当前时间
+const CREATE_SQL: &str = r#"
+    INSERT INTO kv_store (namespace, key, value, data, content_type, updated_at, expires_at, version)
+    VALUES ($6, $1, $2, $3, $4, $7, $5,
+            (SELECT COALESCE(MAX(version), 0) + 1 FROM kv_history WHERE namespace = $6 AND key = $1))
+    ON CONFLICT (namespace, key)
+    DO UPDATE SET value = excluded.value,
+                  data = excluded.data,
+                  content_type = excluded.content_type,
+                  updated_at = excluded.updated_at,
+                  version = kv_store.version + 1,
+                  expires_at = excluded.expires_at
+    WHERE kv_store.expires_at <= $7
+    RETURNING key, value, data, content_type, updated_at, version, expires_at
+"#;
+
+/// 更新未过期的 key，最后一个参数为 true 时保留原来的过期时间，否则替换为 `expires_at`
+///
+/// 参数依次为 key、value、data、content_type、expires_at、namespace、当前时间和是否保留过期时间
+const UPDATE_SQL: &str = r#"
+    UPDATE kv_store
+    SET value = $2,
+        data = $3,
+        content_type = $4,
+        updated_at = $7,
+        version = version + 1,
+        expires_at = CASE WHEN $8 THEN expires_at ELSE $5 END
+    WHERE namespace = $6 AND key = $1
+      AND (expires_at IS NULL OR expires_at > $7)
+    RETURNING key, value, data, content_type, updated_at, version, expires_at
+"#;
+
+/// 读取未过期的 key，参数依次为 key、namespace 和当前时间
+const GET_SQL: &str = r#"
+    SELECT key, value, data, content_type, updated_at, version, expires_at
+    FROM kv_store
+    WHERE namespace = $2 AND key = $1
+      AND (expires_at IS NULL OR expires_at > $3)
+"#;
+
+pub struct SqliteClient {
+    pool: SqlitePool,
+    namespace: String,
+    /// 所有 namespace 共用，写事务提交后改变
+    notify: watch::Sender<u64>,
+    /// 配额锁，所有 namespace 共用，SQLite 的写入本来就是串行的
+    quota: Arc<Mutex<()>>,
+}
+
+impl SqliteClient {
+    pub async fn new(database_url: &str, pool_options: DBPoolOptions) -> Result<Self, AppError> {
+        let options = SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        // `sqlite::memory:` 的连接共享同一个内存数据库，最后一个连接关闭时数据就没有了，
+        // 所以至少保留一个连接，连接也不会因为空闲或到期而关闭
+        let pool = SqlitePoolOptions::new()
+            .max_connections(pool_options.max_connections)
+            .min_connections(pool_options.min_connections.max(1))
+            .acquire_timeout(pool_options.acquire_timeout)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await?;
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+        Ok(Self {
+            pool,
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            notify: watch::Sender::new(0),
+            quota: Arc::default(),
+        })
+    }
+
+    /// 开始写事务，`BEGIN IMMEDIATE` 在开始时就拿到写锁，事务里先读后写不会因为锁升级失败而出错
+    async fn begin(&self) -> Result<Transaction<'static, Sqlite>, AppError> {
+        Ok(self.pool.begin_with("BEGIN IMMEDIATE").await?)
+    }
+
+    /// 提交写事务并通知订阅者
+    async fn commit(&self, tx: Transaction<'static, Sqlite>) -> Result<(), AppError> {
+        tx.commit().await?;
+        self.notify.send_modify(|n| *n += 1);
+        Ok(())
+    }
+}
+
+/// 把刚写入的行追加到 `kv_history`，需要和写入在同一个事务里
+async fn record(
+    conn: &mut SqliteConnection,
+    namespace: &str,
+    key: &str,
+    operation: &str,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+        SELECT namespace, key, value, data, content_type, version, $3, expires_at, updated_at
+        FROM kv_store
+        WHERE namespace = $1 AND key = $2
+        "#,
+    )
+    .bind(namespace)
+    .bind(key)
+    .bind(operation)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// 删除未过期的 key 并记录历史，`version` 不为空时只删除这个版本，返回是否删除
+async fn remove(
+    conn: &mut SqliteConnection,
+    namespace: &str,
+    key: &str,
+    version: Option<i64>,
+    now: DateTime<Utc>,
+) -> Result<bool, AppError> {
+    // 先按删除条件记录历史，记录到了说明有可以删除的行
+    let recorded = sqlx::query(
+        r#"
+        INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+        SELECT namespace, key, value, data, content_type, version, 'delete', expires_at, $4
+        FROM kv_store
+        WHERE namespace = $1 AND key = $2
+          AND ($3 IS NULL OR version = $3)
+          AND (expires_at IS NULL OR expires_at > $4)
+        "#,
+    )
+    .bind(namespace)
+    .bind(key)
+    .bind(version)
+    .bind(now)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+    if recorded == 0 {
+        return Ok(false);
+    }
+    sqlx::query("DELETE FROM kv_store WHERE namespace = $1 AND key = $2")
+        .bind(namespace)
+        .bind(key)
+        .execute(conn)
+        .await?;
+    Ok(true)
+}
+
+#[async_trait]
+impl KvStore for SqliteClient {
+    fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn scoped(&self, namespace: &str) -> Arc<dyn KvStore> {
+        // 连接池和通知通道都是共享的，所有 namespace 共用
+        Arc::new(Self {
+            pool: self.pool.clone(),
+            namespace: namespace.to_string(),
+            notify: self.notify.clone(),
+            quota: self.quota.clone(),
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn usage(&self) -> Result<NamespaceUsage, AppError> {
+        tracing::info!(target: "sqlite::kv", "get usage of {} from sqlite", self.namespace);
+        // length 对 TEXT 返回字符数，转成 BLOB 之后才是字节数
+        let (keys, bytes): (i64, i64) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*),
+                   COALESCE(SUM(length(CAST(key AS BLOB)) + COALESCE(length(data), length(CAST(
+                       CASE json_type(value) WHEN 'text' THEN value ->> '$' ELSE value END AS BLOB
+                   )))), 0)
+            FROM kv_store
+            WHERE namespace = $1
+              AND (expires_at IS NULL OR expires_at > $2)
+            "#,
+        )
+        .bind(&self.namespace)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await?;
+
+        tracing::info!(target: "sqlite::kv", "get usage of {} from sqlite success", self.namespace);
+        Ok(NamespaceUsage {
+            namespace: self.namespace.clone(),
+            keys,
+            bytes,
+            ..Default::default()
+        })
+    }
+
+    async fn lock_quota(&self) -> Result<Box<dyn QuotaLock>, AppError> {
+        Ok(Box::new(self.quota.clone().lock_owned().await))
+    }
+
+    #[instrument(skip(self))]
+    async fn schema(&self) -> Result<Option<Value>, AppError> {
+        tracing::info!(target: "sqlite::kv", "get schema of {} from sqlite", self.namespace);
+        let schema = sqlx::query_scalar("SELECT schema FROM kv_schema WHERE namespace = $1")
+            .bind(&self.namespace)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(schema)
+    }
+
+    #[instrument(skip(self, schema))]
+    async fn set_schema(&self, schema: Option<Value>) -> Result<bool, AppError> {
+        tracing::info!(target: "sqlite::kv", "set schema of {} in sqlite", self.namespace);
+        let mut tx = self.begin().await?;
+        let existed = sqlx::query("DELETE FROM kv_schema WHERE namespace = $1")
+            .bind(&self.namespace)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+            > 0;
+        if let Some(schema) = schema {
+            sqlx::query(
+                "INSERT INTO kv_schema (namespace, schema, updated_at) VALUES ($1, $2, $3)",
+            )
+            .bind(&self.namespace)
+            .bind(schema)
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        tracing::info!(target: "sqlite::kv", "set schema of {} in sqlite success", self.namespace);
+        Ok(existed)
+    }
+
+    #[instrument(skip(self))]
+    async fn set(&self, input: CreateKv) -> Result<KvPair, AppError> {
+        tracing::info!(target: "sqlite::kv", "set {:?} to sqlite", input);
+        let expires_at = resolve_expiry(input.ttl_seconds, input.expires_at)?;
+        let (value, data) = columns(&input.value, input.content_type.as_deref())?;
+        let mut tx = self.begin().await?;
+        // 没有写入时事务在 drop 时回滚，也不会记录历史
+        let kv = sqlx::query_as::<_, KvRow>(CREATE_SQL)
+            .bind(&input.key)
+            .bind(value)
+            .bind(data)
+            .bind(&input.content_type)
+            .bind(expires_at)
+            .bind(&self.namespace)
+            .bind(Utc::now())
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| AppError::Conflict(format!("Key {} already exists", input.key)))?;
+        record(&mut tx, &self.namespace, &input.key, "create").await?;
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "set success!");
+        Ok(kv.into())
+    }
+
+    #[instrument(skip(self))]
+    async fn update(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        expiry: Expiry,
+    ) -> Result<KvPair, AppError> {
+        tracing::info!(target: "sqlite::kv", "update sqlite, {} to {}", key, value);
+        let (value, data) = columns(value, content_type)?;
+        let mut tx = self.begin().await?;
+        let kv = sqlx::query_as::<_, KvRow>(UPDATE_SQL)
+            .bind(key)
+            .bind(value)
+            .bind(data)
+            .bind(content_type)
+            .bind(expiry.at())
+            .bind(&self.namespace)
+            .bind(Utc::now())
+            .bind(expiry.keeps())
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Key {} not found", key)))?;
+        record(&mut tx, &self.namespace, key, "update").await?;
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "update sqlite success");
+        Ok(kv.into())
+    }
+
+    #[instrument(skip(self))]
+    async fn upsert(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        expiry: Expiry,
+    ) -> Result<(KvPair, bool), AppError> {
+        tracing::info!(target: "sqlite::kv", "upsert sqlite, {} to {}", key, value);
+        let (value, data) = columns(value, content_type)?;
+        let mut tx = self.begin().await?;
+        let now = Utc::now();
+        // 写事务已经持有写锁，先读出是否存在未过期的行
+        let created = sqlx::query_as::<_, KvRow>(GET_SQL)
+            .bind(key)
+            .bind(&self.namespace)
+            .bind(now)
+            .fetch_optional(&mut *tx)
+            .await?
+            .is_none();
+        // 覆盖已过期的行等同于新建，过期时间重新开始，版本号仍然递增
+        let kv = sqlx::query_as::<_, KvRow>(
+            r#"
+            INSERT INTO kv_store (namespace, key, value, data, content_type, updated_at, expires_at, version)
+            VALUES ($6, $1, $2, $3, $4, $7, $5,
+                    (SELECT COALESCE(MAX(version), 0) + 1 FROM kv_history WHERE namespace = $6 AND key = $1))
+            ON CONFLICT (namespace, key)
+            DO UPDATE SET value = excluded.value,
+                          data = excluded.data,
+                          content_type = excluded.content_type,
+                          updated_at = excluded.updated_at,
+                          version = kv_store.version + 1,
+                          expires_at = CASE
+                              WHEN kv_store.expires_at <= $7 OR NOT $8 THEN excluded.expires_at
+                              ELSE kv_store.expires_at
+                          END
+            RETURNING key, value, data, content_type, updated_at, version, expires_at
+            "#,
+        )
+        .bind(key)
+        .bind(value)
+        .bind(data)
+        .bind(content_type)
+        .bind(expiry.at())
+        .bind(&self.namespace)
+        .bind(now)
+        .bind(expiry.keeps())
+        .fetch_one(&mut *tx)
+        .await?;
+        let kv = KvPair::from(kv);
+        let operation = if created { "create" } else { "update" };
+        record(&mut tx, &self.namespace, key, operation).await?;
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "upsert sqlite success, created: {}", created);
+        Ok((kv, created))
+    }
+
+    #[instrument(skip(self))]
+    async fn update_if_version(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        version: i64,
+        expiry: Expiry,
+    ) -> Result<KvPair, AppError> {
+        tracing::info!(target: "sqlite::kv", "update sqlite if version {}, {} to {}", version, key, value);
+        let (value, data) = columns(value, content_type)?;
+        let mut tx = self.begin().await?;
+        let kv = sqlx::query_as::<_, KvRow>(
+            r#"
+            UPDATE kv_store
+            SET value = $2,
+                data = $3,
+                content_type = $4,
+                updated_at = $8,
+                version = version + 1,
+                expires_at = CASE WHEN $9 THEN expires_at ELSE $6 END
+            WHERE namespace = $7 AND key = $1 AND version = $5
+              AND (expires_at IS NULL OR expires_at > $8)
+            RETURNING key, value, data, content_type, updated_at, version, expires_at
+            "#,
+        )
+        .bind(key)
+        .bind(value)
+        .bind(data)
+        .bind(content_type)
+        .bind(version)
+        .bind(expiry.at())
+        .bind(&self.namespace)
+        .bind(Utc::now())
+        .bind(expiry.keeps())
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::PreconditionFailed(format!("Key {} has changed", key)))?;
+        record(&mut tx, &self.namespace, key, "update").await?;
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "update sqlite if version success");
+        Ok(kv.into())
+    }
+
+    #[instrument(skip(self))]
+    async fn get(&self, key: &str) -> Result<Option<KvPair>, AppError> {
+        tracing::info!(target: "sqlite::kv", "get {} from sqlite", key);
+        let kv = sqlx::query_as::<_, KvRow>(GET_SQL)
+            .bind(key)
+            .bind(&self.namespace)
+            .bind(Utc::now())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        tracing::info!(target: "sqlite::kv", "get {} from sqlite success", key);
+        Ok(kv.map(KvPair::from))
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, key: &str) -> Result<bool, AppError> {
+        tracing::info!(target: "sqlite::kv", "delete {} from sqlite", key);
+        let mut tx = self.begin().await?;
+        let deleted = remove(&mut tx, &self.namespace, key, None, Utc::now()).await?;
+        if deleted {
+            self.commit(tx).await?;
+        }
+
+        tracing::info!(target: "sqlite::kv", "delete {} from sqlite success", key);
+        Ok(deleted)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_if_version(&self, key: &str, version: i64) -> Result<(), AppError> {
+        tracing::info!(target: "sqlite::kv", "delete {} from sqlite if version {}", key, version);
+        let mut tx = self.begin().await?;
+        if !remove(&mut tx, &self.namespace, key, Some(version), Utc::now()).await? {
+            return Err(AppError::PreconditionFailed(format!(
+                "Key {} has changed",
+                key
+            )));
+        }
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "delete {} from sqlite if version success", key);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_many(&self, keys: &[String]) -> Result<Vec<KvPair>, AppError> {
+        tracing::info!(target: "sqlite::kv", "get {} keys from sqlite", keys.len());
+        // SQLite 没有数组类型，key 列表按 JSON 数组传入
+        let items = sqlx::query_as::<_, KvRow>(
+            r#"
+            SELECT key, value, data, content_type, updated_at, version, expires_at
+            FROM kv_store
+            WHERE namespace = $2 AND key IN (SELECT value FROM json_each($1))
+              AND (expires_at IS NULL OR expires_at > $3)
+            "#,
+        )
+        .bind(Json(keys))
+        .bind(&self.namespace)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::info!(target: "sqlite::kv", "get {} keys from sqlite success", items.len());
+        Ok(items.into_iter().map(KvPair::from).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn set_many(&self, items: Vec<CreateKv>) -> Result<Vec<Option<KvPair>>, AppError> {
+        tracing::info!(target: "sqlite::kv", "set {} keys to sqlite", items.len());
+        let expires_at = items
+            .iter()
+            .map(|input| resolve_expiry(input.ttl_seconds, input.expires_at))
+            .collect::<Result<Vec<_>, _>>()?;
+        // 任何一条语句出错时事务在 drop 时回滚，key 已存在不算错误
+        let mut tx = self.begin().await?;
+        let now = Utc::now();
+        let mut results = Vec::with_capacity(items.len());
+        for (input, expires_at) in items.into_iter().zip(expires_at) {
+            let (value, data) = columns(&input.value, input.content_type.as_deref())?;
+            let kv = sqlx::query_as::<_, KvRow>(CREATE_SQL)
+                .bind(&input.key)
+                .bind(value)
+                .bind(data)
+                .bind(&input.content_type)
+                .bind(expires_at)
+                .bind(&self.namespace)
+                .bind(now)
+                .fetch_optional(&mut *tx)
+                .await?;
+            if kv.is_some() {
+                record(&mut tx, &self.namespace, &input.key, "create").await?;
+            }
+            results.push(kv.map(KvPair::from));
+        }
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "set {} keys to sqlite success", results.len());
+        Ok(results)
+    }
+
+    #[instrument(skip(self, items))]
+    async fn import_many(
+        &self,
+        items: Vec<CreateKv>,
+        mode: ImportMode,
+    ) -> Result<Vec<Option<KvPair>>, AppError> {
+        tracing::info!(target: "sqlite::kv", "import {} keys to sqlite, mode: {:?}", items.len(), mode);
+        let expires_at = items
+            .iter()
+            .map(|input| resolve_expiry(input.ttl_seconds, input.expires_at))
+            .collect::<Result<Vec<_>, _>>()?;
+        // 覆盖时和 upsert 一样更新版本号，但过期时间总是以导入的为准
+        // 不覆盖时和 set_many 一样，只有已过期的行可以被替换
+        let sql = match mode {
+            ImportMode::Overwrite => {
+                r#"
+                INSERT INTO kv_store (namespace, key, value, data, content_type, updated_at, expires_at, version)
+                VALUES ($6, $1, $2, $3, $4, $7, $5,
+                        (SELECT COALESCE(MAX(version), 0) + 1 FROM kv_history WHERE namespace = $6 AND key = $1))
+                ON CONFLICT (namespace, key)
+                DO UPDATE SET value = excluded.value,
+                              data = excluded.data,
+                              content_type = excluded.content_type,
+                              updated_at = excluded.updated_at,
+                              version = kv_store.version + 1,
+                              expires_at = excluded.expires_at
+                RETURNING key, value, data, content_type, updated_at, version, expires_at
+                "#
+            }
+            ImportMode::Skip | ImportMode::Fail => CREATE_SQL,
+        };
+
+        let mut tx = self.begin().await?;
+        let now = Utc::now();
+        let mut results = Vec::with_capacity(items.len());
+        for (input, expires_at) in items.into_iter().zip(expires_at) {
+            let (value, data) = columns(&input.value, input.content_type.as_deref())?;
+            let existed = sqlx::query_as::<_, KvRow>(GET_SQL)
+                .bind(&input.key)
+                .bind(&self.namespace)
+                .bind(now)
+                .fetch_optional(&mut *tx)
+                .await?
+                .is_some();
+            let kv = sqlx::query_as::<_, KvRow>(sql)
+                .bind(&input.key)
+                .bind(value)
+                .bind(data)
+                .bind(&input.content_type)
+                .bind(expires_at)
+                .bind(&self.namespace)
+                .bind(now)
+                .fetch_optional(&mut *tx)
+                .await?;
+            let Some(kv) = kv.map(KvPair::from) else {
+                if mode == ImportMode::Fail {
+                    tx.rollback().await?;
+                    tracing::info!(target: "sqlite::kv", "import rolled back, key {} already exists", input.key);
+                    return Ok(vec![None; results.len() + 1]);
+                }
+                results.push(None);
+                continue;
+            };
+            let operation = if existed { "update" } else { "create" };
+            record(&mut tx, &self.namespace, &input.key, operation).await?;
+            results.push(Some(kv));
+        }
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "import {} keys to sqlite success", results.len());
+        Ok(results)
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_many(&self, keys: &[String]) -> Result<Vec<bool>, AppError> {
+        tracing::info!(target: "sqlite::kv", "delete {} keys from sqlite", keys.len());
+        let mut tx = self.begin().await?;
+        let now = Utc::now();
+        let mut results = Vec::with_capacity(keys.len());
+        for key in keys {
+            results.push(remove(&mut tx, &self.namespace, key, None, now).await?);
+        }
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "delete {} keys from sqlite success", keys.len());
+        Ok(results)
+    }
+
+    #[instrument(skip(self))]
+    async fn list(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>, AppError> {
+        tracing::info!(target: "sqlite::kv", "list {} after {:?} from sqlite", prefix, after);
+        // SQLite 的 LIKE 默认不区分 ASCII 大小写，前缀按 substr 比较
+        let items = sqlx::query_as::<_, KvRow>(
+            r#"
+            SELECT key, value, data, content_type, updated_at, version, expires_at
+            FROM kv_store
+            WHERE namespace = $4 AND key >= $1 AND substr(key, 1, length($1)) = $1
+              AND ($2 IS NULL OR key > $2)
+              AND (expires_at IS NULL OR expires_at > $5)
+            ORDER BY key
+            LIMIT $3
+            "#,
+        )
+        .bind(prefix)
+        .bind(after)
+        .bind(limit as i64)
+        .bind(&self.namespace)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::info!(target: "sqlite::kv", "list {} keys from sqlite success", items.len());
+        Ok(items.into_iter().map(KvPair::from).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn purge_expired(&self, limit: u32) -> Result<Vec<(String, String)>, AppError> {
+        // 每个被清理的 key 记录一条 expire 历史，和其他写操作一样产生变更通知；
+        // 两条语句在同一个写事务里按 rowid 选出同样的行
+        let mut tx = self.begin().await?;
+        let now = Utc::now();
+        sqlx::query(
+            r#"
+            INSERT INTO kv_history (namespace, key, value, data, content_type, version, operation, expires_at, changed_at)
+            SELECT namespace, key, value, data, content_type, version, 'expire', expires_at, $2
+            FROM kv_store
+            WHERE expires_at <= $2
+            ORDER BY rowid
+            LIMIT $1
+            "#,
+        )
+        .bind(limit as i64)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        let keys: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            DELETE FROM kv_store
+            WHERE rowid IN (
+                SELECT rowid
+                FROM kv_store
+                WHERE expires_at <= $2
+                ORDER BY rowid
+                LIMIT $1
+            )
+            RETURNING namespace, key
+            "#,
+        )
+        .bind(limit as i64)
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await?;
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "purge {} expired keys from sqlite", keys.len());
+        Ok(keys)
+    }
+
+    #[instrument(skip(self))]
+    async fn incr(&self, key: &str, delta: i64) -> Result<KvPair, AppError> {
+        tracing::info!(target: "sqlite::kv", "incr {} by {} in sqlite", key, delta);
+        // 写事务已经持有写锁，先读后写不会丢失并发的自增
+        let mut tx = self.begin().await?;
+        let now = Utc::now();
+        let current = sqlx::query_as::<_, KvRow>(GET_SQL)
+            .bind(key)
+            .bind(&self.namespace)
+            .bind(now)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(KvPair::from);
+        let kv = match current {
+            Some(current) => {
+                let value = incremented(&current.value, current.content_type.as_deref(), delta)
+                    .ok_or_else(|| {
+                        AppError::Conflict(format!(
+                            "Value of key {} is not an integer or would overflow",
+                            key
+                        ))
+                    })?;
+                let kv = sqlx::query_as::<_, KvRow>(UPDATE_SQL)
+                    .bind(key)
+                    .bind(value)
+                    .bind(None::<Vec<u8>>)
+                    .bind(None::<String>)
+                    .bind(None::<DateTime<Utc>>)
+                    .bind(&self.namespace)
+                    .bind(now)
+                    .bind(true)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                record(&mut tx, &self.namespace, key, "update").await?;
+                kv
+            }
+            // 不存在或已过期，从 0 开始，新建的计数器是字符串
+            None => {
+                let kv = sqlx::query_as::<_, KvRow>(CREATE_SQL)
+                    .bind(key)
+                    .bind(Value::String(delta.to_string()))
+                    .bind(None::<Vec<u8>>)
+                    .bind(None::<String>)
+                    .bind(None::<DateTime<Utc>>)
+                    .bind(&self.namespace)
+                    .bind(now)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                record(&mut tx, &self.namespace, key, "create").await?;
+                kv
+            }
+        };
+        self.commit(tx).await?;
+
+        let kv = KvPair::from(kv);
+        tracing::info!(target: "sqlite::kv", "incr sqlite success, {} is now {}", key, kv.value);
+        Ok(kv)
+    }
+
+    #[instrument(skip(self))]
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &Value,
+        value: &Value,
+    ) -> Result<KvPair, AppError> {
+        tracing::info!(target: "sqlite::kv", "cas {} from {} to {} in sqlite", key, expected, value);
+        // value 是 JSON 文本，不能直接按文本比较，读出来按 JSON 值比较
+        let mut tx = self.begin().await?;
+        let now = Utc::now();
+        sqlx::query_as::<_, KvRow>(GET_SQL)
+            .bind(key)
+            .bind(&self.namespace)
+            .bind(now)
+            .fetch_optional(&mut *tx)
+            .await?
+            .map(KvPair::from)
+            .filter(|kv| kv.content_type.is_none() && kv.value == *expected)
+            .ok_or_else(|| AppError::Conflict(format!("Value of key {} does not match", key)))?;
+        let kv = sqlx::query_as::<_, KvRow>(UPDATE_SQL)
+            .bind(key)
+            .bind(value)
+            .bind(None::<Vec<u8>>)
+            .bind(None::<String>)
+            .bind(None::<DateTime<Utc>>)
+            .bind(&self.namespace)
+            .bind(now)
+            .bind(true)
+            .fetch_one(&mut *tx)
+            .await?;
+        record(&mut tx, &self.namespace, key, "update").await?;
+        self.commit(tx).await?;
+
+        tracing::info!(target: "sqlite::kv", "cas sqlite success");
+        Ok(kv.into())
+    }
+
+    #[instrument(skip(self))]
+    async fn history(&self, key: &str, limit: u32) -> Result<Vec<KvHistoryEntry>, AppError> {
+        tracing::info!(target: "sqlite::kv", "get history of {} from sqlite", key);
+        let entries = sqlx::query_as::<_, HistoryRow>(
+            r#"
+            SELECT key, value, data, content_type, version, operation, expires_at, changed_at
+            FROM kv_history
+            WHERE namespace = $3 AND key = $1
+            ORDER BY id DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(key)
+        .bind(limit as i64)
+        .bind(&self.namespace)
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::info!(target: "sqlite::kv", "get {} history entries from sqlite success", entries.len());
+        Ok(entries.into_iter().map(KvHistoryEntry::from).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn history_version(
+        &self,
+        key: &str,
+        version: i64,
+    ) -> Result<Option<KvHistoryEntry>, AppError> {
+        tracing::info!(target: "sqlite::kv", "get version {} of {} from sqlite", version, key);
+        let entry = sqlx::query_as::<_, HistoryRow>(
+            r#"
+            SELECT key, value, data, content_type, version, operation, expires_at, changed_at
+            FROM kv_history
+            WHERE namespace = $3 AND key = $1 AND version = $2 AND operation NOT IN ('delete', 'expire')
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(key)
+        .bind(version)
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        tracing::info!(target: "sqlite::kv", "get version {} of {} from sqlite success", version, key);
+        Ok(entry.map(KvHistoryEntry::from))
+    }
+
+    #[instrument(skip(self))]
+    async fn history_at(
+        &self,
+        key: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<KvHistoryEntry>, AppError> {
+        tracing::info!(target: "sqlite::kv", "get {} at {} from sqlite", key, at);
+        let entry = sqlx::query_as::<_, HistoryRow>(
+            r#"
+            SELECT key, value, data, content_type, version, operation, expires_at, changed_at
+            FROM kv_history
+            WHERE namespace = $3 AND key = $1 AND changed_at <= $2
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(key)
+        .bind(at)
+        .bind(&self.namespace)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        tracing::info!(target: "sqlite::kv", "get {} at {} from sqlite success", key, at);
+        Ok(entry.map(KvHistoryEntry::from))
+    }
+
+    #[instrument(skip(self))]
+    async fn changes(&self, after: i64, limit: u32) -> Result<Vec<KvEvent>, AppError> {
+        tracing::info!(target: "sqlite::kv", "get changes after {} from sqlite", after);
+        let events = sqlx::query_as::<_, ChangeRow>(
+            r#"
+            SELECT id, namespace, key, value, data, content_type, version, operation, expires_at, changed_at
+            FROM kv_history
+            WHERE id > $1
+            ORDER BY id
+            LIMIT $2
+            "#,
+        )
+        .bind(after)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        tracing::info!(target: "sqlite::kv", "get {} changes from sqlite success", events.len());
+        Ok(events.into_iter().map(KvEvent::from).collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn last_change_id(&self) -> Result<i64, AppError> {
+        let (id,): (i64,) = sqlx::query_as("SELECT COALESCE(MAX(id), 0) FROM kv_history")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(id)
+    }
+
+    fn subscribe(&self) -> watch::Receiver<u64> {
+        self.notify.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create(key: &str, value: Value) -> CreateKv {
+        CreateKv {
+            key: key.to_string(),
+            value,
+            content_type: None,
+            ttl_seconds: None,
+            expires_at: None,
+        }
+    }
+
+    async fn client() -> SqliteClient {
+        SqliteClient::new("sqlite::memory:", DBPoolOptions::default())
+            .await
+            .unwrap()
+    }
+
+    /// 让 `key` 在一秒前过期
+    async fn expire(db: &SqliteClient, key: &str) {
+        sqlx::query("UPDATE kv_store SET expires_at = $1 WHERE key = $2")
+            .bind(Utc::now() - chrono::TimeDelta::seconds(1))
+            .bind(key)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn writes_check_conflicts_and_versions() {
+        let db = client().await;
+        let changes = db.subscribe();
+
+        let kv = db.set(create("a_1", "1".into())).await.unwrap();
+        assert_eq!(kv.version, 1);
+        assert!(matches!(
+            db.set(create("a_1", "2".into())).await,
+            Err(AppError::Conflict(_))
+        ));
+        let kv = db
+            .update("a_1", &"2".into(), None, Expiry::Keep)
+            .await
+            .unwrap();
+        assert_eq!((kv.value.as_str(), kv.version), (Some("2"), 2));
+        assert!(matches!(
+            db.update_if_version("a_1", &"3".into(), None, 1, Expiry::Keep)
+                .await,
+            Err(AppError::PreconditionFailed(_))
+        ));
+        assert!(changes.has_changed().unwrap());
+    }
+
+    #[tokio::test]
+    async fn expired_rows_read_as_missing_and_upsert_recreates_them() {
+        let db = client().await;
+        db.set(create("a_1", "1".into())).await.unwrap();
+        db.update("a_1", &"2".into(), None, Expiry::Keep)
+            .await
+            .unwrap();
+
+        // 覆盖已过期的行时算新建，版本号继续递增
+        expire(&db, "a_1").await;
+        assert!(db.get("a_1").await.unwrap().is_none());
+        let (kv, created) = db
+            .upsert("a_1", &"4".into(), None, Expiry::Keep)
+            .await
+            .unwrap();
+        assert!(created && kv.version == 3 && kv.expires_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn incr_and_compare_and_swap_keep_value_types() {
+        let db = client().await;
+        assert_eq!(db.incr("n", 5).await.unwrap().value, "5");
+        assert_eq!(db.incr("n", -7).await.unwrap().value, "-2");
+        db.set(create("j", serde_json::json!({"x": 1})))
+            .await
+            .unwrap();
+        assert!(matches!(db.incr("j", 1).await, Err(AppError::Conflict(_))));
+        let kv = db
+            .compare_and_swap("j", &serde_json::json!({"x": 1}), &serde_json::json!(2))
+            .await
+            .unwrap();
+        assert_eq!(kv.version, 2);
+        assert_eq!(db.incr("j", 1).await.unwrap().value, 3);
+    }
+
+    #[tokio::test]
+    async fn list_prefix_is_literal_and_usage_is_per_namespace() {
+        let db = client().await;
+        let mut bin = create("b", crate::kv_value::encode(b"\x00\xff"));
+        bin.content_type = Some("image/png".into());
+        assert_eq!(db.set(bin.clone()).await.unwrap().value, bin.value);
+        for (key, value) in [
+            ("a_1", "1".into()),
+            ("A_2", "x".into()),
+            ("ab", "x".into()),
+            ("j", serde_json::json!({"x": 1})),
+        ] {
+            db.set(create(key, value)).await.unwrap();
+        }
+
+        // 前缀区分大小写，`_` 不是通配符
+        let keys: Vec<String> = db
+            .list("a_", None, 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|kv| kv.key)
+            .collect();
+        assert_eq!(keys, ["a_1"]);
+        // 字符串按内容、其他 JSON 按序列化后的长度、二进制值按原始内容计算
+        let usage = db.usage().await.unwrap();
+        assert_eq!((usage.keys, usage.bytes), (5, 3 + 4 + 4 + 3 + 8));
+
+        let other = db.scoped("other");
+        assert!(other.get("a_1").await.unwrap().is_none());
+        assert_eq!(other.usage().await.unwrap().keys, 0);
+    }
+
+    #[tokio::test]
+    async fn history_and_changes_record_every_write() {
+        let db = client().await;
+        db.set(create("a_1", "1".into())).await.unwrap();
+        db.update("a_1", &"2".into(), None, Expiry::Keep)
+            .await
+            .unwrap();
+        db.set(create("ab", "x".into())).await.unwrap();
+        assert!(db.delete("ab").await.unwrap());
+        assert!(!db.delete("ab").await.unwrap());
+
+        let history = db.history("a_1", 10).await.unwrap();
+        let operations: Vec<&str> = history.iter().map(|e| e.operation.as_str()).collect();
+        assert_eq!(operations, ["update", "create"]);
+        let events = db.changes(0, 100).await.unwrap();
+        assert_eq!(events.len() as i64, db.last_change_id().await.unwrap());
+        assert_eq!(events.last().unwrap().operation, "delete");
+
+        // 删除后重建的 key 版本号继续递增，旧的 ETag 不会再次匹配
+        let kv = db.set(create("ab", "y".into())).await.unwrap();
+        assert_eq!(kv.version, 2);
+    }
+
+    #[tokio::test]
+    async fn purge_records_an_expire_change() {
+        let db = client().await;
+        db.set(create("ab", "x".into())).await.unwrap();
+        db.update("ab", &"y".into(), None, Expiry::Keep)
+            .await
+            .unwrap();
+        expire(&db, "ab").await;
+
+        let purged = db.purge_expired(10).await.unwrap();
+        assert_eq!(purged, [("default".to_string(), "ab".to_string())]);
+        let event = db.changes(0, 100).await.unwrap().pop().unwrap();
+        assert_eq!((event.operation.as_str(), event.kv.version), ("expire", 2));
+    }
+
+    #[tokio::test]
+    async fn updates_keep_or_clear_expiry() {
+        let db = client().await;
+        let later = Utc::now() + chrono::TimeDelta::seconds(60);
+        db.set(create("e", "5".into())).await.unwrap();
+        let kv = db.update("e", &"5".into(), None, Expiry::At(later)).await;
+        assert_eq!(kv.unwrap().expires_at, Some(later));
+
+        // 不指定过期时间时保留，Persist 清除
+        let (kv, _) = db
+            .upsert("e", &"6".into(), None, Expiry::Keep)
+            .await
+            .unwrap();
+        assert_eq!(kv.expires_at, Some(later));
+        let kv = db
+            .update_if_version("e", &"7".into(), None, 3, Expiry::Persist)
+            .await
+            .unwrap();
+        assert!(kv.expires_at.is_none());
+    }
+}
@@ -0,0 +1,1234 @@
+use crate::{
+    error::AppError,
+    kv_value,
+    models::{
+        CreateKv, ImportMode, KvEvent, KvHistoryEntry, KvPage, KvPair, ListKvQuery, NamespaceUsage,
+        UpdateKvQuery,
+    },
+    namespace::DEFAULT_NAMESPACE,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{Mutex, OwnedMutexGuard, watch};
+use tracing::instrument;
+
+/// KV 存储抽象，`kv_axum` 和 `kv_tower` 只依赖这个 trait，
+/// 不再直接依赖 Postgres 的 [`DBClient`]。
+///
+/// 每个实例只读写一个 namespace，通过 [`KvStore::scoped`] 得到其他 namespace 的视图。
+///
+/// [`DBClient`]: crate::db::DBClient
+#[async_trait]
+pub trait KvStore: Send + Sync {
+    /// 当前读写的 namespace
+    fn namespace(&self) -> &str;
+
+    /// 同一个存储上 `namespace` 的视图，key 只在 namespace 内唯一
+    fn scoped(&self, namespace: &str) -> Arc<dyn KvStore>;
+
+    /// 当前 namespace 中未过期的 key 数量，以及 key 和 value 的总字节数
+    async fn usage(&self) -> Result<NamespaceUsage, AppError>;
+
+    /// 锁住当前 namespace 的配额，直到释放返回的锁。配额检查和写入都在持有锁时进行，
+    /// 并发写入不会都通过检查之后一起超出配额，见 [`NamespaceQuota::write`]
+    ///
+    /// [`NamespaceQuota::write`]: crate::namespace::NamespaceQuota::write
+    async fn lock_quota(&self) -> Result<Box<dyn QuotaLock>, AppError>;
+
+    /// 当前 namespace 注册的 JSON Schema
+    async fn schema(&self) -> Result<Option<Value>, AppError>;
+
+    /// 注册、替换或删除（`None`）当前 namespace 的 JSON Schema，返回之前是否注册了 schema
+    async fn set_schema(&self, schema: Option<Value>) -> Result<bool, AppError>;
+
+    /// 新建 key，key 已存在时返回 [`AppError::Conflict`]
+    async fn set(&self, input: CreateKv) -> Result<KvPair, AppError>;
+
+    /// 更新已存在的 key，过期时间按 `expiry` 保留、清除或替换
+    ///
+    /// `content_type` 不为空时 `value` 是二进制内容的 base64 编码，为空时是 JSON 值
+    async fn update(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        expiry: Expiry,
+    ) -> Result<KvPair, AppError>;
+
+    async fn get(&self, key: &str) -> Result<Option<KvPair>, AppError>;
+
+    /// key 不存在时新建，存在时更新，返回值的第二项表示是否新建
+    async fn upsert(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        expiry: Expiry,
+    ) -> Result<(KvPair, bool), AppError>;
+
+    /// 仅当版本号仍为 `version` 时更新，否则返回 [`AppError::PreconditionFailed`]
+    async fn update_if_version(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        version: i64,
+        expiry: Expiry,
+    ) -> Result<KvPair, AppError>;
+
+    /// 删除 key，返回 key 是否存在
+    async fn delete(&self, key: &str) -> Result<bool, AppError>;
+
+    /// 仅当版本号仍为 `version` 时删除，否则返回 [`AppError::PreconditionFailed`]
+    async fn delete_if_version(&self, key: &str, version: i64) -> Result<(), AppError>;
+
+    /// 批量读取，只返回存在的 key，顺序不保证
+    async fn get_many(&self, keys: &[String]) -> Result<Vec<KvPair>, AppError>;
+
+    /// 在一个事务里批量新建，返回值和 `items` 一一对应，key 已存在时为 `None`
+    async fn set_many(&self, items: Vec<CreateKv>) -> Result<Vec<Option<KvPair>>, AppError>;
+
+    /// 在一个事务里批量导入，返回值和 `items` 一一对应，已存在而没有写入的 key 为 `None`。
+    ///
+    /// [`ImportMode::Overwrite`] 覆盖已存在的 key，过期时间也以导入的为准；
+    /// [`ImportMode::Fail`] 遇到已存在的 key 时回滚整个事务，返回值截止到这个 key，全部为 `None`
+    async fn import_many(
+        &self,
+        items: Vec<CreateKv>,
+        mode: ImportMode,
+    ) -> Result<Vec<Option<KvPair>>, AppError>;
+
+    /// 在一个事务里批量删除，返回值和 `keys` 一一对应，表示 key 是否存在
+    async fn delete_many(&self, keys: &[String]) -> Result<Vec<bool>, AppError>;
+
+    /// 按 key 升序列出以 `prefix` 开头、且大于 `after` 的 key，最多 `limit` 个
+    async fn list(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>, AppError>;
+
+    /// 删除所有 namespace 中最多 `limit` 个已过期的 key，每个记录一条 `expire` 历史，
+    /// 返回被删除的 `(namespace, key)`
+    async fn purge_expired(&self, limit: u32) -> Result<Vec<(String, String)>, AppError>;
+
+    /// 把 key 的值按整数加上 `delta`，key 不存在时从 0 开始，新建的计数器是字符串；
+    /// 当前值是整数字符串时结果仍是字符串，是 JSON 整数时结果仍是整数；
+    /// 当前值不是整数、是二进制值或结果溢出时返回 [`AppError::Conflict`]
+    async fn incr(&self, key: &str, delta: i64) -> Result<KvPair, AppError>;
+
+    /// 仅当当前值等于 `expected` 时写入 `value`，否则返回 [`AppError::Conflict`]；
+    /// 两个都是 JSON 值，当前值是二进制值时总是不相等
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &Value,
+        value: &Value,
+    ) -> Result<KvPair, AppError>;
+
+    /// 按写入顺序倒序返回 key 的历史记录，最多 `limit` 条
+    async fn history(&self, key: &str, limit: u32) -> Result<Vec<KvHistoryEntry>, AppError>;
+
+    /// 版本号为 `version` 的那次写入，不包括删除和过期清理的记录；
+    /// 版本号按 key 单调递增，删除或过期后重建也不会复用，所以最多只有一条
+    async fn history_version(
+        &self,
+        key: &str,
+        version: i64,
+    ) -> Result<Option<KvHistoryEntry>, AppError>;
+
+    /// `at` 时刻之前的最后一次写入，包括删除和过期清理的记录
+    async fn history_at(
+        &self,
+        key: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<KvHistoryEntry>, AppError>;
+
+    /// 所有 namespace 中变更序号大于 `after` 的写操作，按序号升序，最多 `limit` 条。
+    /// 过期清理也会记录一条 `expire`；清理之前就被重新写入的 key 只有新写入的 `create`
+    async fn changes(&self, after: i64, limit: u32) -> Result<Vec<KvEvent>, AppError>;
+
+    /// 最新的变更序号，还没有任何变更时为 0
+    async fn last_change_id(&self) -> Result<i64, AppError>;
+
+    /// 当前写事务的水位。变更序号在写入时分配、提交顺序可能不同，序号出现空洞时先记下水位，
+    /// 之后用 [`KvStore::writes_finished`] 判断占用这些序号的事务是否都已经结束。
+    /// 序号按提交顺序分配的存储不会出现空洞，默认返回 0
+    async fn write_horizon(&self) -> Result<i64, AppError> {
+        Ok(0)
+    }
+
+    /// `horizon` 之前开始的写事务是否都已经提交或回滚，之后不会再出现更小的序号
+    async fn writes_finished(&self, _horizon: i64) -> Result<bool, AppError> {
+        Ok(true)
+    }
+
+    /// 订阅变更通知，有新的写操作（包括其他实例的写入）时值会改变。
+    /// 通知只表示需要用 [`KvStore::changes`] 读取，多次写入可能只通知一次
+    fn subscribe(&self) -> watch::Receiver<u64>;
+}
+
+/// [`KvStore::lock_quota`] 返回的锁，没有释放就被丢弃时也会释放
+#[async_trait]
+pub trait QuotaLock: Send {
+    async fn release(self: Box<Self>);
+}
+
+/// 进程内的锁，丢弃 guard 即释放
+#[async_trait]
+impl QuotaLock for OwnedMutexGuard<()> {
+    async fn release(self: Box<Self>) {}
+}
+
+/// 更新 key 时对过期时间的处理，新建的 key 只有 [`Expiry::At`] 会过期
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Expiry {
+    /// 保留原来的过期时间
+    #[default]
+    Keep,
+    /// 清除过期时间，之后永不过期
+    Persist,
+    /// 替换为新的过期时间
+    At(DateTime<Utc>),
+}
+
+impl Expiry {
+    /// 写入的过期时间，[`Expiry::Keep`] 和 [`Expiry::Persist`] 为 `None`
+    pub fn at(self) -> Option<DateTime<Utc>> {
+        match self {
+            Expiry::At(at) => Some(at),
+            _ => None,
+        }
+    }
+
+    /// 是否保留原来的过期时间
+    pub fn keeps(self) -> bool {
+        self == Expiry::Keep
+    }
+}
+
+/// 把更新接口的 `ttl_seconds`/`expires_at`/`persist` 参数换算成 [`Expiry`]，都没指定时保留原来的过期时间
+pub fn resolve_update_expiry(query: &UpdateKvQuery) -> Result<Expiry, AppError> {
+    let expires_at = resolve_expiry(query.ttl_seconds, query.expires_at)?;
+    match (query.persist, expires_at) {
+        (true, Some(_)) => Err(AppError::InvalidInput(
+            "persist is mutually exclusive with ttl_seconds and expires_at".into(),
+        )),
+        (true, None) => Ok(Expiry::Persist),
+        (false, Some(expires_at)) => Ok(Expiry::At(expires_at)),
+        (false, None) => Ok(Expiry::Keep),
+    }
+}
+
+/// 把 `ttl_seconds`/`expires_at` 参数换算成过期时间，两个都没指定时返回 `None`
+pub fn resolve_expiry(
+    ttl_seconds: Option<u64>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Option<DateTime<Utc>>, AppError> {
+    match (ttl_seconds, expires_at) {
+        (Some(_), Some(_)) => Err(AppError::InvalidInput(
+            "ttl_seconds and expires_at are mutually exclusive".into(),
+        )),
+        (Some(0), None) => Err(AppError::InvalidInput(
+            "ttl_seconds must be positive".into(),
+        )),
+        (Some(ttl_seconds), None) => {
+            let ttl = i64::try_from(ttl_seconds)
+                .ok()
+                .and_then(TimeDelta::try_seconds)
+                .ok_or_else(|| AppError::InvalidInput("ttl_seconds is too large".into()))?;
+            Ok(Some(Utc::now() + ttl))
+        }
+        (None, Some(expires_at)) if expires_at <= Utc::now() => Err(AppError::InvalidInput(
+            "expires_at must be in the future".into(),
+        )),
+        (None, expires_at) => Ok(expires_at),
+    }
+}
+
+/// 计数器加上 `delta` 之后的值，整数字符串仍是字符串，JSON 整数仍是整数；
+/// 当前值不是整数、是二进制值或结果溢出时返回 `None`
+pub fn incremented(value: &Value, content_type: Option<&str>, delta: i64) -> Option<Value> {
+    let current = match value {
+        _ if content_type.is_some() => None,
+        Value::String(s) => s.parse::<i64>().ok(),
+        value => value.as_i64(),
+    };
+    let incremented = current?.checked_add(delta)?;
+    match value {
+        Value::String(_) => Some(Value::String(incremented.to_string())),
+        _ => Some(Value::from(incremented)),
+    }
+}
+
+/// 列表默认每页数量
+pub const LIST_DEFAULT_LIMIT: u32 = 20;
+/// 列表每页最大数量
+pub const LIST_MAX_LIMIT: u32 = 100;
+
+/// 校验列表参数并查询一页，`kv_axum` 和 `kv_tower` 共用
+#[instrument(skip(db), target = "service::kv")]
+pub async fn list_page(db: &dyn KvStore, query: &ListKvQuery) -> Result<KvPage, AppError> {
+    let limit = query.limit.unwrap_or(LIST_DEFAULT_LIMIT);
+    if limit == 0 || limit > LIST_MAX_LIMIT {
+        return Err(AppError::InvalidInput(format!(
+            "limit must be between 1 and {}",
+            LIST_MAX_LIMIT
+        )));
+    }
+    let prefix = query.prefix.as_deref().unwrap_or_default();
+    if prefix.len() > kv_value::KEY_MAX_LEN {
+        return Err(AppError::InvalidInput("Invalid prefix".into()));
+    }
+
+    // 多取一条，用来判断是否还有下一页
+    let mut items = db.list(prefix, query.cursor.as_deref(), limit + 1).await?;
+    let next_cursor = if items.len() > limit as usize {
+        items.truncate(limit as usize);
+        items.last().map(|kv| kv.key.clone())
+    } else {
+        None
+    };
+    Ok(KvPage { items, next_cursor })
+}
+
+/// 一个 namespace 的数据和历史记录
+#[derive(Default)]
+struct Space {
+    data: RwLock<HashMap<String, KvPair>>,
+    history: RwLock<Vec<KvHistoryEntry>>,
+    schema: RwLock<Option<Value>>,
+    /// 配额锁，见 [`KvStore::lock_quota`]
+    quota: Arc<Mutex<()>>,
+}
+
+/// 所有 namespace 共用的变更记录，序号即下标加 1
+struct Changes {
+    events: RwLock<Vec<KvEvent>>,
+    notify: watch::Sender<u64>,
+}
+
+impl Default for Changes {
+    fn default() -> Self {
+        Self {
+            events: RwLock::default(),
+            notify: watch::Sender::new(0),
+        }
+    }
+}
+
+/// 基于内存的 [`KvStore`] 实现，用于本地演示和测试，不依赖任何外部服务
+pub struct MemoryStore {
+    namespace: String,
+    space: Arc<Space>,
+    spaces: Arc<RwLock<HashMap<String, Arc<Space>>>>,
+    changes: Arc<Changes>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        let space = Arc::new(Space::default());
+        let spaces = HashMap::from([(DEFAULT_NAMESPACE.to_string(), space.clone())]);
+        Self {
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            space,
+            spaces: Arc::new(RwLock::new(spaces)),
+            changes: Arc::default(),
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 新建 key 时的版本号，从历史记录里最大的版本号继续递增，
+    /// 删除或过期后重建的 key 不会复用旧的版本号和 ETag
+    fn next_version(&self, key: &str) -> i64 {
+        let history = self.space.history.read().unwrap();
+        let last = history.iter().filter(|e| e.key == key).map(|e| e.version);
+        last.max().unwrap_or(0) + 1
+    }
+
+    /// 追加一条历史记录，调用方需要持有 `data` 的写锁，保证记录顺序和写入顺序一致
+    fn record(&self, kv: &KvPair, operation: &str, changed_at: DateTime<Utc>) {
+        self.record_in(&self.namespace, &self.space, kv, operation, changed_at);
+    }
+
+    /// 同 [`MemoryStore::record`]，记录到 `namespace` 里，用于跨 namespace 的过期清理
+    fn record_in(
+        &self,
+        namespace: &str,
+        space: &Space,
+        kv: &KvPair,
+        operation: &str,
+        changed_at: DateTime<Utc>,
+    ) {
+        let entry = KvHistoryEntry {
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+            content_type: kv.content_type.clone(),
+            version: kv.version,
+            operation: operation.to_string(),
+            expires_at: kv.expires_at,
+            changed_at,
+        };
+        space.history.write().unwrap().push(entry.clone());
+
+        let mut events = self.changes.events.write().unwrap();
+        let id = events.len() as i64 + 1;
+        events.push(KvEvent {
+            id,
+            namespace: namespace.to_string(),
+            operation: operation.to_string(),
+            kv: entry.into_kv(),
+        });
+        drop(events);
+        self.changes.notify.send_modify(|n| *n += 1);
+    }
+}
+
+/// 写操作之前先删掉已过期的 key，之后按不存在处理
+fn remove_expired(data: &mut HashMap<String, KvPair>, key: &str) {
+    if data.get(key).is_some_and(KvPair::is_expired) {
+        data.remove(key);
+    }
+}
+
+#[async_trait]
+impl KvStore for MemoryStore {
+    fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn scoped(&self, namespace: &str) -> Arc<dyn KvStore> {
+        let space = self
+            .spaces
+            .write()
+            .unwrap()
+            .entry(namespace.to_string())
+            .or_default()
+            .clone();
+        Arc::new(Self {
+            namespace: namespace.to_string(),
+            space,
+            spaces: self.spaces.clone(),
+            changes: self.changes.clone(),
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn usage(&self) -> Result<NamespaceUsage, AppError> {
+        tracing::info!(target: "memory::kv", "get usage of {} from memory", self.namespace);
+        let data = self.space.data.read().unwrap();
+        let live = data.values().filter(|kv| !kv.is_expired());
+        let (keys, bytes) = live.fold((0, 0), |(keys, bytes), kv| {
+            (
+                keys + 1,
+                bytes
+                    + (kv.key.len() + kv_value::size(&kv.value, kv.content_type.as_deref())) as i64,
+            )
+        });
+        Ok(NamespaceUsage {
+            namespace: self.namespace.clone(),
+            keys,
+            bytes,
+            ..Default::default()
+        })
+    }
+
+    async fn lock_quota(&self) -> Result<Box<dyn QuotaLock>, AppError> {
+        Ok(Box::new(self.space.quota.clone().lock_owned().await))
+    }
+
+    #[instrument(skip(self))]
+    async fn schema(&self) -> Result<Option<Value>, AppError> {
+        Ok(self.space.schema.read().unwrap().clone())
+    }
+
+    #[instrument(skip(self, schema))]
+    async fn set_schema(&self, schema: Option<Value>) -> Result<bool, AppError> {
+        tracing::info!(target: "memory::kv", "set schema of {} in memory", self.namespace);
+        let mut current = self.space.schema.write().unwrap();
+        Ok(std::mem::replace(&mut *current, schema).is_some())
+    }
+
+    #[instrument(skip(self))]
+    async fn set(&self, input: CreateKv) -> Result<KvPair, AppError> {
+        tracing::info!(target: "memory::kv", "set {:?} to memory", input);
+        let expires_at = resolve_expiry(input.ttl_seconds, input.expires_at)?;
+        let mut data = self.space.data.write().unwrap();
+        remove_expired(&mut data, &input.key);
+        if data.contains_key(&input.key) {
+            return Err(AppError::Conflict(format!(
+                "Key {} already exists",
+                input.key
+            )));
+        }
+
+        let kv = KvPair {
+            key: input.key.clone(),
+            value: input.value,
+            content_type: input.content_type,
+            updated_at: Utc::now(),
+            version: self.next_version(&input.key),
+            expires_at,
+        };
+        self.record(&kv, "create", kv.updated_at);
+        data.insert(input.key, kv.clone());
+        Ok(kv)
+    }
+
+    #[instrument(skip(self))]
+    async fn update(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        expiry: Expiry,
+    ) -> Result<KvPair, AppError> {
+        tracing::info!(target: "memory::kv", "update memory, {} to {}", key, value);
+        let mut data = self.space.data.write().unwrap();
+        remove_expired(&mut data, key);
+        let kv = data
+            .get_mut(key)
+            .ok_or_else(|| AppError::NotFound(format!("Key {} not found", key)))?;
+        kv.value = value.clone();
+        kv.content_type = content_type.map(str::to_string);
+        kv.updated_at = Utc::now();
+        kv.version += 1;
+        if !expiry.keeps() {
+            kv.expires_at = expiry.at();
+        }
+        self.record(kv, "update", kv.updated_at);
+        Ok(kv.clone())
+    }
+
+    #[instrument(skip(self))]
+    async fn upsert(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        expiry: Expiry,
+    ) -> Result<(KvPair, bool), AppError> {
+        tracing::info!(target: "memory::kv", "upsert memory, {} to {}", key, value);
+        let mut data = self.space.data.write().unwrap();
+        remove_expired(&mut data, key);
+        let now = Utc::now();
+        match data.get_mut(key) {
+            Some(kv) => {
+                kv.value = value.clone();
+                kv.content_type = content_type.map(str::to_string);
+                kv.updated_at = now;
+                kv.version += 1;
+                if !expiry.keeps() {
+                    kv.expires_at = expiry.at();
+                }
+                self.record(kv, "update", now);
+                Ok((kv.clone(), false))
+            }
+            None => {
+                let kv = KvPair {
+                    key: key.to_string(),
+                    value: value.clone(),
+                    content_type: content_type.map(str::to_string),
+                    updated_at: now,
+                    version: self.next_version(key),
+                    expires_at: expiry.at(),
+                };
+                self.record(&kv, "create", now);
+                data.insert(key.to_string(), kv.clone());
+                Ok((kv, true))
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn update_if_version(
+        &self,
+        key: &str,
+        value: &Value,
+        content_type: Option<&str>,
+        version: i64,
+        expiry: Expiry,
+    ) -> Result<KvPair, AppError> {
+        tracing::info!(target: "memory::kv", "update memory if version {}, {} to {}", version, key, value);
+        let mut data = self.space.data.write().unwrap();
+        remove_expired(&mut data, key);
+        let kv = data
+            .get_mut(key)
+            .filter(|kv| kv.version == version)
+            .ok_or_else(|| AppError::PreconditionFailed(format!("Key {} has changed", key)))?;
+        kv.value = value.clone();
+        kv.content_type = content_type.map(str::to_string);
+        kv.updated_at = Utc::now();
+        kv.version += 1;
+        if !expiry.keeps() {
+            kv.expires_at = expiry.at();
+        }
+        self.record(kv, "update", kv.updated_at);
+        Ok(kv.clone())
+    }
+
+    #[instrument(skip(self))]
+    async fn get(&self, key: &str) -> Result<Option<KvPair>, AppError> {
+        tracing::info!(target: "memory::kv", "get {} from memory", key);
+        Ok(self
+            .space
+            .data
+            .read()
+            .unwrap()
+            .get(key)
+            .filter(|kv| !kv.is_expired())
+            .cloned())
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, key: &str) -> Result<bool, AppError> {
+        tracing::info!(target: "memory::kv", "delete {} from memory", key);
+        let mut data = self.space.data.write().unwrap();
+        remove_expired(&mut data, key);
+        let removed = data.remove(key);
+        if let Some(kv) = &removed {
+            self.record(kv, "delete", Utc::now());
+        }
+        Ok(removed.is_some())
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_if_version(&self, key: &str, version: i64) -> Result<(), AppError> {
+        tracing::info!(target: "memory::kv", "delete {} from memory if version {}", key, version);
+        let mut data = self.space.data.write().unwrap();
+        remove_expired(&mut data, key);
+        if data.get(key).is_none_or(|kv| kv.version != version) {
+            return Err(AppError::PreconditionFailed(format!(
+                "Key {} has changed",
+                key
+            )));
+        }
+        if let Some(kv) = data.remove(key) {
+            self.record(&kv, "delete", Utc::now());
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_many(&self, keys: &[String]) -> Result<Vec<KvPair>, AppError> {
+        tracing::info!(target: "memory::kv", "get {} keys from memory", keys.len());
+        let data = self.space.data.read().unwrap();
+        Ok(keys
+            .iter()
+            .filter_map(|key| data.get(key).filter(|kv| !kv.is_expired()).cloned())
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn set_many(&self, items: Vec<CreateKv>) -> Result<Vec<Option<KvPair>>, AppError> {
+        tracing::info!(target: "memory::kv", "set {} keys to memory", items.len());
+        let expires_at = items
+            .iter()
+            .map(|input| resolve_expiry(input.ttl_seconds, input.expires_at))
+            .collect::<Result<Vec<_>, _>>()?;
+        // 持有写锁完成整批写入，效果等同于一个事务
+        let mut data = self.space.data.write().unwrap();
+        let now = Utc::now();
+        Ok(items
+            .into_iter()
+            .zip(expires_at)
+            .map(|(input, expires_at)| {
+                remove_expired(&mut data, &input.key);
+                if data.contains_key(&input.key) {
+                    return None;
+                }
+                let kv = KvPair {
+                    key: input.key.clone(),
+                    value: input.value,
+                    content_type: input.content_type,
+                    updated_at: now,
+                    version: self.next_version(&input.key),
+                    expires_at,
+                };
+                self.record(&kv, "create", now);
+                data.insert(input.key, kv.clone());
+                Some(kv)
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self, items))]
+    async fn import_many(
+        &self,
+        items: Vec<CreateKv>,
+        mode: ImportMode,
+    ) -> Result<Vec<Option<KvPair>>, AppError> {
+        tracing::info!(target: "memory::kv", "import {} keys to memory", items.len());
+        let expires_at = items
+            .iter()
+            .map(|input| resolve_expiry(input.ttl_seconds, input.expires_at))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut data = self.space.data.write().unwrap();
+        for input in &items {
+            remove_expired(&mut data, &input.key);
+        }
+        // 先找出第一个冲突的 key，什么都不写，效果等同于回滚
+        if mode == ImportMode::Fail {
+            let mut seen = HashSet::new();
+            if let Some(conflict) = items
+                .iter()
+                .position(|input| data.contains_key(&input.key) || !seen.insert(&input.key))
+            {
+                return Ok(vec![None; conflict + 1]);
+            }
+        }
+
+        let now = Utc::now();
+        Ok(items
+            .into_iter()
+            .zip(expires_at)
+            .map(|(input, expires_at)| match data.get_mut(&input.key) {
+                Some(kv) if mode == ImportMode::Overwrite => {
+                    kv.value = input.value;
+                    kv.content_type = input.content_type;
+                    kv.updated_at = now;
+                    kv.version += 1;
+                    kv.expires_at = expires_at;
+                    self.record(kv, "update", now);
+                    Some(kv.clone())
+                }
+                Some(_) => None,
+                None => {
+                    let kv = KvPair {
+                        key: input.key.clone(),
+                        value: input.value,
+                        content_type: input.content_type,
+                        updated_at: now,
+                        version: self.next_version(&input.key),
+                        expires_at,
+                    };
+                    self.record(&kv, "create", now);
+                    data.insert(input.key, kv.clone());
+                    Some(kv)
+                }
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn delete_many(&self, keys: &[String]) -> Result<Vec<bool>, AppError> {
+        tracing::info!(target: "memory::kv", "delete {} keys from memory", keys.len());
+        let mut data = self.space.data.write().unwrap();
+        let now = Utc::now();
+        Ok(keys
+            .iter()
+            .map(|key| {
+                remove_expired(&mut data, key);
+                let removed = data.remove(key);
+                if let Some(kv) = &removed {
+                    self.record(kv, "delete", now);
+                }
+                removed.is_some()
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn list(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<KvPair>, AppError> {
+        tracing::info!(target: "memory::kv", "list {} after {:?} from memory", prefix, after);
+        let data = self.space.data.read().unwrap();
+        let mut items: Vec<KvPair> = data
+            .values()
+            .filter(|kv| {
+                kv.key.starts_with(prefix)
+                    && after.is_none_or(|after| kv.key.as_str() > after)
+                    && !kv.is_expired()
+            })
+            .cloned()
+            .collect();
+        items.sort_by(|a, b| a.key.cmp(&b.key));
+        items.truncate(limit as usize);
+        Ok(items)
+    }
+
+    #[instrument(skip(self))]
+    async fn purge_expired(&self, limit: u32) -> Result<Vec<(String, String)>, AppError> {
+        let spaces = self.spaces.read().unwrap();
+        let mut expired = Vec::new();
+        for (namespace, space) in spaces.iter() {
+            let mut data = space.data.write().unwrap();
+            let keys: Vec<String> = data
+                .values()
+                .filter(|kv| kv.is_expired())
+                .take(limit as usize - expired.len())
+                .map(|kv| kv.key.clone())
+                .collect();
+            let now = Utc::now();
+            for key in keys {
+                if let Some(kv) = data.remove(&key) {
+                    self.record_in(namespace, space, &kv, "expire", now);
+                }
+                expired.push((namespace.clone(), key));
+            }
+        }
+        tracing::info!(target: "memory::kv", "purge {} expired keys from memory", expired.len());
+        Ok(expired)
+    }
+
+    #[instrument(skip(self))]
+    async fn incr(&self, key: &str, delta: i64) -> Result<KvPair, AppError> {
+        tracing::info!(target: "memory::kv", "incr {} by {} in memory", key, delta);
+        let mut data = self.space.data.write().unwrap();
+        remove_expired(&mut data, key);
+        let now = Utc::now();
+        match data.get_mut(key) {
+            Some(kv) => {
+                kv.value =
+                    incremented(&kv.value, kv.content_type.as_deref(), delta).ok_or_else(|| {
+                        AppError::Conflict(format!(
+                            "Value of key {} is not an integer or would overflow",
+                            key
+                        ))
+                    })?;
+                kv.updated_at = now;
+                kv.version += 1;
+                self.record(kv, "update", now);
+                Ok(kv.clone())
+            }
+            None => {
+                let kv = KvPair {
+                    key: key.to_string(),
+                    value: Value::String(delta.to_string()),
+                    content_type: None,
+                    updated_at: now,
+                    version: self.next_version(key),
+                    expires_at: None,
+                };
+                self.record(&kv, "create", now);
+                data.insert(key.to_string(), kv.clone());
+                Ok(kv)
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: &Value,
+        value: &Value,
+    ) -> Result<KvPair, AppError> {
+        tracing::info!(target: "memory::kv", "cas {} from {} to {} in memory", key, expected, value);
+        let mut data = self.space.data.write().unwrap();
+        remove_expired(&mut data, key);
+        let kv = data
+            .get_mut(key)
+            .filter(|kv| kv.content_type.is_none() && kv.value == *expected)
+            .ok_or_else(|| AppError::Conflict(format!("Value of key {} does not match", key)))?;
+        kv.value = value.clone();
+        kv.updated_at = Utc::now();
+        kv.version += 1;
+        self.record(kv, "update", kv.updated_at);
+        Ok(kv.clone())
+    }
+
+    #[instrument(skip(self))]
+    async fn history(&self, key: &str, limit: u32) -> Result<Vec<KvHistoryEntry>, AppError> {
+        tracing::info!(target: "memory::kv", "get history of {} from memory", key);
+        Ok(self
+            .space
+            .history
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|entry| entry.key == key)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn history_version(
+        &self,
+        key: &str,
+        version: i64,
+    ) -> Result<Option<KvHistoryEntry>, AppError> {
+        tracing::info!(target: "memory::kv", "get version {} of {} from memory", version, key);
+        Ok(self
+            .space
+            .history
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|entry| {
+                entry.key == key
+                    && entry.version == version
+                    && !matches!(entry.operation.as_str(), "delete" | "expire")
+            })
+            .cloned())
+    }
+
+    #[instrument(skip(self))]
+    async fn history_at(
+        &self,
+        key: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<KvHistoryEntry>, AppError> {
+        tracing::info!(target: "memory::kv", "get {} at {} from memory", key, at);
+        Ok(self
+            .space
+            .history
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|entry| entry.key == key && entry.changed_at <= at)
+            .cloned())
+    }
+
+    #[instrument(skip(self))]
+    async fn changes(&self, after: i64, limit: u32) -> Result<Vec<KvEvent>, AppError> {
+        Ok(self
+            .changes
+            .events
+            .read()
+            .unwrap()
+            .iter()
+            .skip(after.max(0) as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn last_change_id(&self) -> Result<i64, AppError> {
+        Ok(self.changes.events.read().unwrap().len() as i64)
+    }
+
+    fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.notify.subscribe()
+    }
+}
+
+/// 测试用的 [`KvStore`]：所有方法转发给 [`MemoryStore`]，只有设置了钩子的方法改变行为。
+/// trait 新增方法时只需要在这里转发一次
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    pub struct HookedStore {
+        pub inner: MemoryStore,
+        /// 读 key（`get`、`get_many`）的次数
+        pub reads: AtomicUsize,
+        /// 读 key 之后、返回之前等待的时间，用来放大读写并发的窗口
+        pub read_delay: Duration,
+        /// 读变更时隐藏的序号，模拟先分配序号、后提交的事务
+        pub hidden_changes: Mutex<HashSet<i64>>,
+        /// 替代 [`KvStore::writes_finished`] 的结果，`None` 时转发
+        pub writes_finished: Mutex<Option<bool>>,
+    }
+
+    impl HookedStore {
+        async fn after_read(&self) {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.read_delay).await;
+        }
+    }
+
+    #[async_trait]
+    impl KvStore for HookedStore {
+        fn namespace(&self) -> &str {
+            self.inner.namespace()
+        }
+
+        fn scoped(&self, namespace: &str) -> Arc<dyn KvStore> {
+            self.inner.scoped(namespace)
+        }
+
+        async fn usage(&self) -> Result<NamespaceUsage, AppError> {
+            self.inner.usage().await
+        }
+
+        async fn lock_quota(&self) -> Result<Box<dyn QuotaLock>, AppError> {
+            self.inner.lock_quota().await
+        }
+
+        async fn schema(&self) -> Result<Option<Value>, AppError> {
+            self.inner.schema().await
+        }
+
+        async fn set_schema(&self, schema: Option<Value>) -> Result<bool, AppError> {
+            self.inner.set_schema(schema).await
+        }
+
+        async fn set(&self, input: CreateKv) -> Result<KvPair, AppError> {
+            self.inner.set(input).await
+        }
+
+        async fn update(
+            &self,
+            key: &str,
+            value: &Value,
+            content_type: Option<&str>,
+            expiry: Expiry,
+        ) -> Result<KvPair, AppError> {
+            self.inner.update(key, value, content_type, expiry).await
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<KvPair>, AppError> {
+            let kv = self.inner.get(key).await?;
+            self.after_read().await;
+            Ok(kv)
+        }
+
+        async fn upsert(
+            &self,
+            key: &str,
+            value: &Value,
+            content_type: Option<&str>,
+            expiry: Expiry,
+        ) -> Result<(KvPair, bool), AppError> {
+            self.inner.upsert(key, value, content_type, expiry).await
+        }
+
+        async fn update_if_version(
+            &self,
+            key: &str,
+            value: &Value,
+            content_type: Option<&str>,
+            version: i64,
+            expiry: Expiry,
+        ) -> Result<KvPair, AppError> {
+            self.inner
+                .update_if_version(key, value, content_type, version, expiry)
+                .await
+        }
+
+        async fn delete(&self, key: &str) -> Result<bool, AppError> {
+            self.inner.delete(key).await
+        }
+
+        async fn delete_if_version(&self, key: &str, version: i64) -> Result<(), AppError> {
+            self.inner.delete_if_version(key, version).await
+        }
+
+        async fn get_many(&self, keys: &[String]) -> Result<Vec<KvPair>, AppError> {
+            let items = self.inner.get_many(keys).await?;
+            self.after_read().await;
+            Ok(items)
+        }
+
+        async fn set_many(&self, items: Vec<CreateKv>) -> Result<Vec<Option<KvPair>>, AppError> {
+            self.inner.set_many(items).await
+        }
+
+        async fn import_many(
+            &self,
+            items: Vec<CreateKv>,
+            mode: ImportMode,
+        ) -> Result<Vec<Option<KvPair>>, AppError> {
+            self.inner.import_many(items, mode).await
+        }
+
+        async fn delete_many(&self, keys: &[String]) -> Result<Vec<bool>, AppError> {
+            self.inner.delete_many(keys).await
+        }
+
+        async fn list(
+            &self,
+            prefix: &str,
+            after: Option<&str>,
+            limit: u32,
+        ) -> Result<Vec<KvPair>, AppError> {
+            self.inner.list(prefix, after, limit).await
+        }
+
+        async fn purge_expired(&self, limit: u32) -> Result<Vec<(String, String)>, AppError> {
+            self.inner.purge_expired(limit).await
+        }
+
+        async fn incr(&self, key: &str, delta: i64) -> Result<KvPair, AppError> {
+            self.inner.incr(key, delta).await
+        }
+
+        async fn compare_and_swap(
+            &self,
+            key: &str,
+            expected: &Value,
+            value: &Value,
+        ) -> Result<KvPair, AppError> {
+            self.inner.compare_and_swap(key, expected, value).await
+        }
+
+        async fn history(&self, key: &str, limit: u32) -> Result<Vec<KvHistoryEntry>, AppError> {
+            self.inner.history(key, limit).await
+        }
+
+        async fn history_version(
+            &self,
+            key: &str,
+            version: i64,
+        ) -> Result<Option<KvHistoryEntry>, AppError> {
+            self.inner.history_version(key, version).await
+        }
+
+        async fn history_at(
+            &self,
+            key: &str,
+            at: DateTime<Utc>,
+        ) -> Result<Option<KvHistoryEntry>, AppError> {
+            self.inner.history_at(key, at).await
+        }
+
+        async fn changes(&self, after: i64, limit: u32) -> Result<Vec<KvEvent>, AppError> {
+            let mut page = self.inner.changes(after, limit).await?;
+            let hidden = self.hidden_changes.lock().unwrap();
+            page.retain(|event| !hidden.contains(&event.id));
+            Ok(page)
+        }
+
+        async fn last_change_id(&self) -> Result<i64, AppError> {
+            self.inner.last_change_id().await
+        }
+
+        async fn write_horizon(&self) -> Result<i64, AppError> {
+            self.inner.write_horizon().await
+        }
+
+        async fn writes_finished(&self, horizon: i64) -> Result<bool, AppError> {
+            let finished = *self.writes_finished.lock().unwrap();
+            match finished {
+                Some(finished) => Ok(finished),
+                None => self.inner.writes_finished(horizon).await,
+            }
+        }
+
+        fn subscribe(&self) -> watch::Receiver<u64> {
+            self.inner.subscribe()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn list_page_walks_prefix_with_cursor() {
+        let db = MemoryStore::new();
+        for key in ["a1", "b1", "b2", "b3", "b_4", "c1"] {
+            db.set(CreateKv {
+                key: key.to_string(),
+                value: "v".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        let mut query = ListKvQuery {
+            prefix: Some("b".to_string()),
+            limit: Some(2),
+            cursor: None,
+        };
+        let mut keys = Vec::new();
+        loop {
+            let page = list_page(&db, &query).await.unwrap();
+            assert!(page.items.len() <= 2);
+            keys.extend(page.items.into_iter().map(|kv| kv.key));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(keys, ["b1", "b2", "b3", "b_4"]);
+
+        query.limit = Some(LIST_MAX_LIMIT + 1);
+        assert!(list_page(&db, &query).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn expired_keys_read_as_missing_until_purged() {
+        let db = MemoryStore::new();
+        db.set(CreateKv {
+            key: "k".to_string(),
+            value: "v".into(),
+            expires_at: Some(Utc::now() + TimeDelta::milliseconds(50)),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(db.get("k").await.unwrap().is_some());
+
+        tokio::time::sleep(std::time::Duration::from_millis(60)).await;
+        assert!(db.get("k").await.unwrap().is_none());
+        assert_eq!(
+            db.purge_expired(10).await.unwrap(),
+            [(DEFAULT_NAMESPACE.to_string(), "k".to_string())]
+        );
+        assert!(db.purge_expired(10).await.unwrap().is_empty());
+        // 清理产生一条 expire 变更，带着清理前的值和版本号
+        let event = db.changes(1, 10).await.unwrap().pop().unwrap();
+        assert_eq!((event.id, event.operation.as_str()), (2, "expire"));
+        assert_eq!((event.kv.value.as_str(), event.kv.version), (Some("v"), 1));
+        assert!(matches!(
+            db.update("k", &"v2".into(), None, Expiry::Keep).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        assert!(resolve_expiry(Some(10), Some(Utc::now())).is_err());
+        assert!(resolve_expiry(None, Some(Utc::now() - TimeDelta::seconds(1))).is_err());
+    }
+
+    #[tokio::test]
+    async fn updates_keep_replace_or_clear_expiry() {
+        let db = MemoryStore::new();
+        let later = Utc::now() + TimeDelta::seconds(60);
+        db.set(CreateKv {
+            key: "k".to_string(),
+            value: "v1".into(),
+            expires_at: Some(later),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let kv = db.update("k", &"v2".into(), None, Expiry::Keep).await;
+        assert_eq!(kv.unwrap().expires_at, Some(later));
+        let query = UpdateKvQuery {
+            persist: true,
+            ..Default::default()
+        };
+        let expiry = resolve_update_expiry(&query).unwrap();
+        let (kv, _) = db.upsert("k", &"v3".into(), None, expiry).await.unwrap();
+        assert!(kv.expires_at.is_none());
+        let kv = db.update("k", &"v4".into(), None, Expiry::At(later)).await;
+        assert_eq!(kv.unwrap().expires_at, Some(later));
+        let kv = db
+            .update_if_version("k", &"v5".into(), None, 4, Expiry::Persist)
+            .await
+            .unwrap();
+        assert!(kv.expires_at.is_none());
+
+        let query = UpdateKvQuery {
+            ttl_seconds: Some(10),
+            ..query
+        };
+        assert!(resolve_update_expiry(&query).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{cache::KvCache, cache_aside::CacheAside, store::KvStore};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// 每一批最多清理的 key 数量
+const SWEEP_BATCH_SIZE: u32 = 500;
+
+/// 启动过期 key 清理任务，每隔 `interval` 删除所有 namespace 中已过期的 key 并失效对应的缓存。
+///
+/// `shutdown` 变为 true 时任务退出，正在进行的一轮清理会先完成。
+pub fn spawn(
+    db: Arc<dyn KvStore>,
+    cache: Arc<dyn KvCache>,
+    cache_aside: Arc<CacheAside>,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        info!(target: "service::sweeper", ?interval, "🧹 expired key sweeper started");
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => sweep(db.as_ref(), cache.as_ref(), &cache_aside).await,
+                _ = shutdown.changed() => break,
+            }
+        }
+        info!(target: "service::sweeper", "🧹 expired key sweeper stopped");
+    })
+}
+
+/// 清理一轮，直到没有过期的 key 或者出错
+async fn sweep(db: &dyn KvStore, cache: &dyn KvCache, cache_aside: &CacheAside) {
+    loop {
+        let keys = match db.purge_expired(SWEEP_BATCH_SIZE).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!(target: "service::sweeper", error = %e, "⚠️ failed to purge expired keys");
+                return;
+            }
+        };
+        for (namespace, key) in &keys {
+            cache_aside
+                .invalidate(db.scoped(namespace).as_ref(), cache, key)
+                .await;
+        }
+        if !keys.is_empty() {
+            info!(target: "service::sweeper", count = keys.len(), "🧹 purged expired keys");
+        }
+        if keys.len() < SWEEP_BATCH_SIZE as usize {
+            return;
+        }
+    }
+}
@@ -0,0 +1,442 @@
+//! 监听端口上的 TLS，基于 rustls。
+//!
+//! 证书、私钥和客户端 CA 定期检查修改时间，变化后重新构建 `ServerConfig`，新连接使用新证书，
+//! 已经建立的连接不受影响；重新加载失败时继续使用原来的证书。
+//! 配置了客户端 CA 时校验客户端证书（mTLS），校验通过的证书作为 [`PeerIdentity`]
+//! 放到这个连接上每个请求的 extensions 里，中间件和 handler 都可以读取。
+use crate::config::TlsConfig;
+use http::Request;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tracing::{info, warn};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+/// TLS 握手的超时时间，避免连接建立后一直不握手占用资源
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: rustls::pki_types::pem::Error,
+    },
+    #[error("No certificate found in {0}")]
+    NoCertificate(PathBuf),
+    #[error("Invalid client CA certificate: {0}")]
+    ClientCa(#[from] rustls::server::VerifierBuilderError),
+    #[error("TLS error: {0}")]
+    Rustls(#[from] rustls::Error),
+    #[error("TLS handshake timed out")]
+    HandshakeTimeout,
+    #[error("TLS handshake failed: {0}")]
+    Handshake(#[from] std::io::Error),
+}
+
+/// 明文和 TLS 连接统一成一个类型，交给同一个连接 builder
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// mTLS 校验通过的客户端证书
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerIdentity {
+    /// 证书的 subject，例如 `CN=client-1, O=example`
+    pub subject: String,
+    pub common_name: Option<String>,
+    /// subjectAltName 中的 DNS 名、URI 和 email
+    pub alt_names: Vec<String>,
+}
+
+impl PeerIdentity {
+    fn from_der(der: &CertificateDer<'_>) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let subject = cert.subject();
+        let common_name = subject
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+        let alt_names = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|san| {
+                san.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::DNSName(name)
+                        | GeneralName::URI(name)
+                        | GeneralName::RFC822Name(name) => Some(name.to_string()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            subject: subject.to_string(),
+            common_name,
+            alt_names,
+        })
+    }
+}
+
+pub struct Tls {
+    options: TlsConfig,
+    server_config: watch::Sender<Arc<ServerConfig>>,
+}
+
+impl Tls {
+    pub fn new(options: &TlsConfig) -> Result<Self, TlsError> {
+        Ok(Self {
+            options: options.clone(),
+            server_config: watch::Sender::new(Arc::new(server_config(options)?)),
+        })
+    }
+
+    /// 用当前的证书完成握手，返回 TLS 连接和校验通过的客户端证书
+    pub async fn accept(
+        &self,
+        stream: TcpStream,
+    ) -> Result<(TlsStream<TcpStream>, Option<PeerIdentity>), TlsError> {
+        let acceptor = TlsAcceptor::from(self.server_config.borrow().clone());
+        let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+            .await
+            .map_err(|_| TlsError::HandshakeTimeout)??;
+        let peer = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(PeerIdentity::from_der);
+        Ok((stream, peer))
+    }
+
+    /// 重新读取证书文件，失败时继续使用原来的证书
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let config = server_config(&self.options)?;
+        self.server_config.send_replace(Arc::new(config));
+        Ok(())
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        [
+            &self.options.cert_path,
+            &self.options.key_path,
+            &self.options.client_ca_path,
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+/// 启动证书重新加载任务，每隔 `interval` 检查一次证书文件的修改时间
+pub fn spawn_reload(tls: Arc<Tls>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let modified = |tls: &Tls| tls.paths().map(|p| modified(p)).collect::<Vec<_>>();
+        let mut last = modified(&tls);
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let current = modified(&tls);
+            if current == last {
+                continue;
+            }
+            // 证书和私钥可能不是同时写完的，没有加载成功时下一次检查再试
+            match tls.reload() {
+                Ok(()) => {
+                    info!(target: "server::tls", "🔐 TLS certificates reloaded");
+                    last = current;
+                }
+                Err(e) => {
+                    warn!(target: "server::tls", error = %e, "⚠️ failed to reload TLS certificates, keep current ones");
+                }
+            }
+        }
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn server_config(options: &TlsConfig) -> Result<ServerConfig, TlsError> {
+    let (Some(cert_path), Some(key_path)) = (&options.cert_path, &options.key_path) else {
+        return Err(TlsError::Rustls(rustls::Error::General(
+            "tls.cert_path and tls.key_path are required".into(),
+        )));
+    };
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|source| TlsError::Read {
+            path: cert_path.clone(),
+            source,
+        })?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate(cert_path.clone()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|source| TlsError::Read {
+        path: key_path.clone(),
+        source,
+    })?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &options.client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(ca_path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|source| TlsError::Read {
+                    path: ca_path.clone(),
+                    source,
+                })?
+            {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if options.client_auth_optional {
+                verifier.allow_unauthenticated().build()?
+            } else {
+                verifier.build()?
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certs, key)?;
+    // 优先 HTTP/2，连接 builder 根据客户端发送的前几个字节选择协议
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// 把连接上校验通过的客户端证书放到每个请求的 extensions 里
+#[derive(Clone, Debug)]
+pub struct PeerIdentityService<S> {
+    inner: S,
+    peer: Option<PeerIdentity>,
+}
+
+impl<S> PeerIdentityService<S> {
+    pub fn new(inner: S, peer: Option<PeerIdentity>) -> Self {
+        Self { inner, peer }
+    }
+}
+
+impl<S, B> hyper::service::Service<Request<B>> for PeerIdentityService<S>
+where
+    S: hyper::service::Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&self, mut req: Request<B>) -> Self::Future {
+        if let Some(peer) = &self.peer {
+            req.extensions_mut().insert(peer.clone());
+        }
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedIssuer, DnType, ExtendedKeyUsagePurpose,
+        IsCa, KeyPair, KeyUsagePurpose, SanType,
+    };
+    use rustls::ClientConfig;
+    use rustls::pki_types::ServerName;
+    use std::path::{Path, PathBuf};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsConnector;
+
+    /// 在临时目录 `name` 下生成和 `scripts/gen-demo-certs.sh` 相同的 CA、服务端和客户端证书，
+    /// 已经有证书时整套换新
+    fn demo_certs(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kv-tls-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut ca = CertificateParams::default();
+        ca.distinguished_name.push(DnType::CommonName, "kv demo CA");
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        let ca = CertifiedIssuer::self_signed(ca, KeyPair::generate().unwrap()).unwrap();
+        std::fs::write(dir.join("ca.crt"), ca.pem()).unwrap();
+
+        let write = |name: &str, mut params: CertificateParams, usage| {
+            params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+            params.extended_key_usages = vec![usage];
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &ca).unwrap();
+            std::fs::write(dir.join(format!("{}.crt", name)), cert.pem()).unwrap();
+            std::fs::write(dir.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+        };
+        let mut server = CertificateParams::new(["localhost".into(), "127.0.0.1".into()]).unwrap();
+        server
+            .distinguished_name
+            .push(DnType::CommonName, "localhost");
+        write("server", server, ExtendedKeyUsagePurpose::ServerAuth);
+        let mut client = CertificateParams::default();
+        client
+            .distinguished_name
+            .push(DnType::OrganizationName, "kv demo");
+        client
+            .distinguished_name
+            .push(DnType::CommonName, "client-1");
+        let uri = "spiffe://kv/client-1".try_into().unwrap();
+        client.subject_alt_names = vec![SanType::URI(uri)];
+        write("client", client, ExtendedKeyUsagePurpose::ClientAuth);
+        dir
+    }
+
+    fn options(dir: &Path, client_auth_optional: bool) -> TlsConfig {
+        TlsConfig {
+            cert_path: Some(dir.join("server.crt")),
+            key_path: Some(dir.join("server.key")),
+            client_ca_path: Some(dir.join("ca.crt")),
+            client_auth_optional,
+            ..Default::default()
+        }
+    }
+
+    /// 客户端看到的握手结果：协商的 ALPN 和服务端出示的证书
+    struct ClientView {
+        alpn: Option<Vec<u8>>,
+        server_cert: CertificateDer<'static>,
+    }
+
+    /// 用 [`demo_certs`] 生成的证书连接一次，返回服务端看到的握手结果和客户端看到的结果
+    async fn handshake(
+        tls: &Tls,
+        dir: &Path,
+        client_cert: bool,
+    ) -> (Result<Option<PeerIdentity>, TlsError>, Option<ClientView>) {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(dir.join("ca.crt")).unwrap() {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let mut config = if client_cert {
+            let certs = CertificateDer::pem_file_iter(dir.join("client.crt"))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let key = PrivateKeyDer::from_pem_file(dir.join("client.key")).unwrap();
+            builder.with_client_auth_cert(certs, key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        config.alpn_protocols = vec![b"h2".to_vec()];
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let connector = TlsConnector::from(Arc::new(config));
+            let server_name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(server_name, stream).await.ok()?;
+            // 客户端握手完成时服务端可能还在校验客户端证书，等服务端关闭连接再返回
+            let _ = tokio::io::AsyncReadExt::read(&mut stream, &mut [0; 1]).await;
+            let connection = stream.get_ref().1;
+            Some(ClientView {
+                alpn: connection.alpn_protocol().map(<[u8]>::to_vec),
+                server_cert: connection
+                    .peer_certificates()?
+                    .first()?
+                    .clone()
+                    .into_owned(),
+            })
+        });
+        let (stream, _) = listener.accept().await.unwrap();
+        let result = tls.accept(stream).await.map(|(_, peer)| peer);
+        (result, client.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn mtls_exposes_peer_identity_and_negotiates_h2() {
+        let dir = demo_certs("mtls");
+        let tls = Tls::new(&options(&dir, false)).unwrap();
+        let (peer, client) = handshake(&tls, &dir, true).await;
+        let peer = peer.unwrap().unwrap();
+        assert_eq!(peer.common_name.as_deref(), Some("client-1"));
+        assert_eq!(peer.alt_names, ["spiffe://kv/client-1"]);
+        assert_eq!(client.unwrap().alpn.as_deref(), Some(&b"h2"[..]));
+
+        // 要求客户端证书时，不带证书的客户端握手失败
+        let (result, _) = handshake(&tls, &dir, false).await;
+        assert!(result.is_err());
+
+        // 客户端证书可选时可以连接，但是没有 PeerIdentity
+        let tls = Tls::new(&options(&dir, true)).unwrap();
+        let (peer, _) = handshake(&tls, &dir, false).await;
+        assert_eq!(peer.unwrap(), None);
+
+        // 证书文件有问题时重新加载失败，继续使用原来的证书
+        let mut broken = options(&dir, false);
+        broken.key_path = Some(dir.join("missing.key"));
+        let tls = Tls {
+            options: broken,
+            server_config: tls.server_config,
+        };
+        assert!(matches!(tls.reload(), Err(TlsError::Read { .. })));
+        let (peer, _) = handshake(&tls, &dir, false).await;
+        assert_eq!(peer.unwrap(), None);
+    }
+
+    fn server_cert(dir: &Path) -> CertificateDer<'static> {
+        CertificateDer::from_pem_file(dir.join("server.crt")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reload_task_picks_up_rotated_certificates() {
+        let dir = demo_certs("rotate");
+        let tls = Arc::new(Tls::new(&options(&dir, true)).unwrap());
+        let (_, client) = handshake(&tls, &dir, false).await;
+        let old = server_cert(&dir);
+        assert_eq!(client.unwrap().server_cert, old);
+
+        let interval = Duration::from_millis(50);
+        let mut reloaded = tls.server_config.subscribe();
+        let task = spawn_reload(tls.clone(), interval);
+        // 等重新加载任务记下当前的修改时间，再在磁盘上换一套证书
+        tokio::time::sleep(interval * 2).await;
+        demo_certs("rotate");
+        let new = server_cert(&dir);
+        assert_ne!(new, old);
+
+        // 证书和私钥不是同时写完的，可能先加载到一半写完的文件，直到新的握手出示新证书为止
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                reloaded.changed().await.unwrap();
+                let (_, client) = handshake(&tls, &dir, false).await;
+                if client.is_some_and(|client| client.server_cert == new) {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("new certificate is not presented after a reload");
+        task.abort();
+    }
+}